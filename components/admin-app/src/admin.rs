@@ -1,19 +1,48 @@
-use core::{convert::TryInto, marker::PhantomData};
+use core::{convert::TryFrom, marker::PhantomData};
 use ctaphid_dispatch::app::{self as hid, Command as HidCommand, Message};
 use ctaphid_dispatch::command::VendorCommand;
 use apdu_dispatch::{Command, response, app as apdu};
 use apdu_dispatch::{command::Size as CommandSize, response::Size as ResponseSize};
 use apdu_dispatch::iso7816::Status;
+use usbd_ccid::escape::{EscapeError, EscapeHandler, EscapeResponse};
+use ctap_types::serde::{cbor_deserialize, cbor_serialize};
 use trussed::{
+    api::request,
+    platform::consent,
+    store::Store,
     syscall,
     Client as TrussedClient,
 };
 
-const UPDATE: VendorCommand = VendorCommand::H51;
-const REBOOT: VendorCommand = VendorCommand::H53;
-const RNG: VendorCommand = VendorCommand::H60;
-const VERSION: VendorCommand = VendorCommand::H61;
-const UUID: VendorCommand = VendorCommand::H62;
+
+use crate::command::Command as AdminCommand;
+use crate::config::Config;
+use crate::filesystem;
+use crate::info::{self, DeviceInfo};
+
+const HID_COMMANDS: &[HidCommand] = &[
+    HidCommand::Wink,
+    HidCommand::Vendor(AdminCommand::Update.code()),
+    HidCommand::Vendor(AdminCommand::Reboot.code()),
+    HidCommand::Vendor(AdminCommand::Rng.code()),
+    HidCommand::Vendor(AdminCommand::Version.code()),
+    HidCommand::Vendor(AdminCommand::Uuid.code()),
+    HidCommand::Vendor(AdminCommand::Info.code()),
+    HidCommand::Vendor(AdminCommand::Filesystem.code()),
+    HidCommand::Vendor(AdminCommand::FactoryReset.code()),
+    HidCommand::Vendor(AdminCommand::ConfigList.code()),
+    HidCommand::Vendor(AdminCommand::ConfigGet.code()),
+    HidCommand::Vendor(AdminCommand::ConfigSet.code()),
+];
+
+enum Error {
+    /// The user did not confirm a firmware update or factory reset.
+    UserNotPresent,
+    /// The response does not fit.
+    NotEnoughMemory,
+    /// An unknown setting, or an invalid value for one.
+    InvalidSetting,
+}
 
 pub trait Reboot {
     /// Reboots the device.
@@ -32,25 +61,45 @@ pub trait Reboot {
     /// reliable way of rebooting into the firmware mode of operation,
     /// does so.
     fn reboot_to_firmware_update_destructive() -> !;
+
+    /// Runs `f` while the Trussed service is held off, then reboots the device.
+    ///
+    /// The admin app changes the filesystems behind the service's back this way
+    /// (for the factory reset), so the service must neither run meanwhile nor
+    /// afterwards, when it might write its old state back.
+    ///
+    /// By default `f` just runs, which suffices where the service only processes
+    /// requests in syscalls: the admin app makes none in `f`.
+    fn reboot_after(f: impl FnOnce()) -> ! {
+        f();
+        Self::reboot()
+    }
 }
 
-pub struct App<T, R>
+pub struct App<T, R, S>
 where T: TrussedClient,
       R: Reboot,
+      S: Store,
 {
     got_wink: bool,
     trussed: T,
+    store: S,
     uuid: [u8; 16],
     version: u32,
+    info: DeviceInfo,
+    config: Config,
     boot_interface: PhantomData<R>,
 }
 
-impl<T, R> App<T, R>
+impl<T, R, S> App<T, R, S>
 where T: TrussedClient,
       R: Reboot,
+      S: Store,
 {
-    pub fn new(client: T, uuid: [u8; 16], version: u32) -> Self {
-        Self { got_wink: false, trussed: client, uuid, version, boot_interface: PhantomData }
+    /// The store is where the settings are kept, and to report how full it is.
+    pub fn new(client: T, store: S, uuid: [u8; 16], version: u32, info: DeviceInfo) -> Self {
+        let config = Config::load(store);
+        Self { got_wink: false, trussed: client, store, uuid, version, info, config, boot_interface: PhantomData }
     }
 
     /// Indicate if a wink was recieved
@@ -64,65 +113,168 @@ where T: TrussedClient,
     }
 
     fn user_present(&mut self) -> bool {
-        let user_present = syscall!(self.trussed.confirm_user_present(15_000)).result;
-        user_present.is_ok()
+        let user_present = syscall!(self.trussed.confirm_user_present(self.config.up_timeout)).result;
+        // the platform ends the wait for a cancelled request, that is no consent
+        user_present.is_ok() && !ctaphid_dispatch::cancel::is_cancelled()
+    }
+
+    /// Like `user_present`, but only a strong press of the button counts.
+    fn user_strongly_present(&mut self) -> bool {
+        let user_present = syscall!(self.trussed.request(request::RequestUserConsent {
+            level: consent::Level::Strong,
+            timeout_milliseconds: self.config.up_timeout,
+        })).result;
+        user_present.is_ok() && !ctaphid_dispatch::cancel::is_cancelled()
+    }
+
+    /// Runs an admin command, the same over CTAPHID and APDU.
+    ///
+    /// `destructive` selects the destructive way of rebooting to firmware update.
+    fn exec(&mut self, command: AdminCommand, input: &[u8], destructive: bool, response: &mut Message) -> Result<(), Error> {
+        match command {
+            AdminCommand::Update => {
+                if !self.user_present() {
+                    return Err(Error::UserNotPresent);
+                }
+                if destructive {
+                    R::reboot_to_firmware_update_destructive();
+                } else {
+                    R::reboot_to_firmware_update();
+                }
+            }
+            AdminCommand::Reboot => {
+                R::reboot();
+            }
+            AdminCommand::Rng => {
+                response.extend_from_slice(
+                    &syscall!(self.trussed.random_bytes(57)).bytes.as_slice()
+                ).ok();
+            }
+            AdminCommand::Version => {
+                response.extend_from_slice(&self.version.to_be_bytes()).ok();
+            }
+            AdminCommand::Uuid => {
+                response.extend_from_slice(&self.uuid).ok();
+            }
+            AdminCommand::Info => {
+                let fs = filesystem::Usage::of(self.store.ifs());
+                Self::serialize(&info::Response::new(&self.info, fs), response)?;
+            }
+            AdminCommand::Filesystem => {
+                Self::serialize(&filesystem::Status::of(self.store), response)?;
+            }
+            AdminCommand::FactoryReset => {
+                if !self.user_strongly_present() {
+                    return Err(Error::UserNotPresent);
+                }
+                let store = self.store;
+                // if some data can't be removed, rebooting is still the best option
+                R::reboot_after(|| { filesystem::factory_reset(store).ok(); });
+            }
+            AdminCommand::ConfigList => {
+                Self::serialize(&self.config, response)?;
+            }
+            AdminCommand::ConfigGet => {
+                let config = &self.config;
+                match input {
+                    b"nfc" => Self::serialize(&config.nfc, response)?,
+                    b"led_brightness" => Self::serialize(&config.led_brightness, response)?,
+                    b"up_timeout" => Self::serialize(&config.up_timeout, response)?,
+                    b"apps" => Self::serialize(&config.apps, response)?,
+                    _ => return Err(Error::InvalidSetting),
+                }
+            }
+            AdminCommand::ConfigSet => {
+                let changes = cbor_deserialize(input).map_err(|_| Error::InvalidSetting)?;
+                self.config.change(changes, self.store).map_err(|_| Error::InvalidSetting)?;
+            }
+        }
+        Ok(())
     }
 
+    fn serialize(object: &impl serde::Serialize, response: &mut Message) -> Result<(), Error> {
+        response.resize_to_capacity();
+        match cbor_serialize(object, &mut response[..]).map(|cbor| cbor.len()) {
+            Ok(length) => {
+                response.resize_default(length).ok();
+                Ok(())
+            }
+            Err(_) => {
+                response.clear();
+                Err(Error::NotEnoughMemory)
+            }
+        }
+    }
 
 }
 
-impl<T, R> hid::App for App<T, R>
+impl<T, R, S> hid::App for App<T, R, S>
 where T: TrussedClient,
-      R: Reboot
+      R: Reboot,
+      S: Store,
 {
     fn commands(&self) -> &'static [HidCommand] {
-        &[
-            HidCommand::Wink,
-            HidCommand::Vendor(UPDATE),
-            HidCommand::Vendor(REBOOT),
-            HidCommand::Vendor(RNG),
-            HidCommand::Vendor(VERSION),
-            HidCommand::Vendor(UUID),
-        ]
+        HID_COMMANDS
     }
 
     fn call(&mut self, command: HidCommand, input_data: &Message, response: &mut Message) -> hid::AppResult {
         match command {
-            HidCommand::Vendor(REBOOT) => {
-                R::reboot();
+            HidCommand::Wink => {
+                self.got_wink = true;
+                Ok(())
             }
-            HidCommand::Vendor(UPDATE) => {
-                if self.user_present() {
-                    if input_data.len() > 0 && input_data[0] == 0x01 {
-                        R::reboot_to_firmware_update_destructive();
-                    } else {
-                        R::reboot_to_firmware_update();
-                    }
-                } else {
-                    return Err(hid::Error::InvalidLength);
-                }
+            HidCommand::Vendor(code) => {
+                let command = AdminCommand::from_code(code).ok_or(hid::Error::InvalidCommand)?;
+                let destructive = input_data.first() == Some(&0x01);
+                // CTAPHID has no better errors
+                self.exec(command, input_data, destructive, response).map_err(|_| hid::Error::InvalidLength)
             }
-            HidCommand::Vendor(RNG) => {
-                // Fill the HID packet (57 bytes)
-                response.extend_from_slice(
-                    &syscall!(self.trussed.random_bytes(57)).bytes.as_slice()
-                ).ok();
+            _ => Err(hid::Error::InvalidCommand),
+        }
+    }
+}
+
+/// The same commands as over CTAPHID, as far as they do not need Trussed:
+/// the first byte of abData is the command, the rest is its input.
+impl<T, R, S> EscapeHandler for App<T, R, S>
+where T: TrussedClient,
+      R: Reboot,
+      S: Store,
+{
+    fn escape(&mut self, request: &[u8], response: &mut EscapeResponse) -> Result<(), EscapeError> {
+        let (&command, _input_data) = request.split_first().ok_or(EscapeError::InvalidData)?;
+        let command = HidCommand::try_from(command).map_err(|_| EscapeError::NotSupported)?;
+
+        let admin_command = match command {
+            HidCommand::Vendor(code) => AdminCommand::from_code(code),
+            _ => None,
+        };
+
+        match admin_command {
+            Some(AdminCommand::Reboot) => {
+                R::reboot();
             }
-            HidCommand::Vendor(VERSION) => {
-                // GET VERSION
+            Some(AdminCommand::Version) => {
                 response.extend_from_slice(&self.version.to_be_bytes()).ok();
             }
-            _ => {
+            Some(AdminCommand::Uuid) => {
+                response.extend_from_slice(&self.uuid).ok();
+            }
+            None if command == HidCommand::Wink => {
                 self.got_wink = true;
             }
+            _ => {
+                return Err(EscapeError::NotSupported);
+            }
         }
         Ok(())
     }
 }
 
-impl<T, R> apdu::Aid for App<T, R>
+impl<T, R, S> apdu::Aid for App<T, R, S>
 where T: TrussedClient,
-      R: Reboot
+      R: Reboot,
+      S: Store,
 {
     // Solo management app
     fn aid(&self) -> &'static [u8] {
@@ -133,9 +285,10 @@ where T: TrussedClient,
     }
 }
 
-impl<T, R> apdu::App<CommandSize, ResponseSize> for App<T, R>
+impl<T, R, S> apdu::App<CommandSize, ResponseSize> for App<T, R, S>
 where T: TrussedClient,
-      R: Reboot
+      R: Reboot,
+      S: Store,
 {
 
     fn select(&mut self, _apdu: &Command, _reply: &mut response::Data) -> apdu::Result {
@@ -147,46 +300,21 @@ where T: TrussedClient,
     fn call(&mut self, interface: apdu::Interface, apdu: &Command, reply: &mut response::Data) -> apdu::Result {
         let instruction: u8 = apdu.instruction().into();
 
-        let command: VendorCommand = instruction.try_into().map_err(|_e| Status::InstructionNotSupportedOrInvalid)?;
-
-        match command {
-            REBOOT => {
-                R::reboot();
-            }
-            UPDATE => {
-                // Boot to mcuboot (only when contact interface)
-                if interface == apdu::Interface::Contact && self.user_present()
-                {
-                    if apdu.p1 == 0x01 {
-                        R::reboot_to_firmware_update_destructive();
-                    } else {
-                        R::reboot_to_firmware_update();
-                    }
-                }
-                return Err(Status::ConditionsOfUseNotSatisfied);
-            }
-
-            RNG => {
-                // Random bytes
-                reply.extend_from_slice(&syscall!(self.trussed.random_bytes(57)).bytes.as_slice()).ok();
-            }
-            VERSION => {
-                // Get version
-                reply.extend_from_slice(&self.version.to_be_bytes()[..]).ok();
-            }
-
-            UUID => {
-                // Get UUID
-                reply.extend_from_slice(&self.uuid).ok();
-            }
-
-            _ => {
-                return Err(Status::InstructionNotSupportedOrInvalid);
-            }
+        let command = VendorCommand::try_from(instruction).ok()
+            .and_then(AdminCommand::from_code)
+            .ok_or(Status::InstructionNotSupportedOrInvalid)?;
 
+        // Boot to mcuboot or reset (only when contact interface)
+        let needs_contact = matches!(command, AdminCommand::Update | AdminCommand::FactoryReset);
+        if needs_contact && interface != apdu::Interface::Contact {
+            return Err(Status::ConditionsOfUseNotSatisfied);
         }
-        Ok(())
 
+        self.exec(command, apdu.data(), apdu.p1 == 0x01, reply).map_err(|error| match error {
+            Error::UserNotPresent => Status::ConditionsOfUseNotSatisfied,
+            Error::NotEnoughMemory => Status::NotEnoughMemory,
+            Error::InvalidSetting => Status::IncorrectDataParameter,
+        })
     }
 }
 
@@ -0,0 +1,63 @@
+use ctaphid_dispatch::command::VendorCommand;
+
+/// The admin commands, with the same code as CTAPHID vendor command and as APDU instruction.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// Reboots to firmware update, after a user presence check.
+    Update,
+    Reboot,
+    /// 57 random bytes, to fill a HID packet.
+    Rng,
+    /// The firmware version, as big endian u32.
+    Version,
+    Uuid,
+    /// A CBOR map describing firmware, board and storage.
+    Info,
+    /// A CBOR map of the filesystems' usage, and the sizes of the apps' directories.
+    Filesystem,
+    /// Wipes all app data except the attestation keys, after a strong user presence check,
+    /// then reboots.
+    FactoryReset,
+    /// A CBOR map of all settings.
+    ConfigList,
+    /// One setting's CBOR value, the input is its name.
+    ConfigGet,
+    /// Changes the settings in the input's CBOR map.
+    ConfigSet,
+}
+
+impl Command {
+    pub const ALL: [Command; 11] = [
+        Command::Update,
+        Command::Reboot,
+        Command::Rng,
+        Command::Version,
+        Command::Uuid,
+        Command::Info,
+        Command::Filesystem,
+        Command::FactoryReset,
+        Command::ConfigList,
+        Command::ConfigGet,
+        Command::ConfigSet,
+    ];
+
+    pub const fn code(self) -> VendorCommand {
+        match self {
+            Command::Update => VendorCommand::H51,
+            Command::Reboot => VendorCommand::H53,
+            Command::Rng => VendorCommand::H60,
+            Command::Version => VendorCommand::H61,
+            Command::Uuid => VendorCommand::H62,
+            Command::Info => VendorCommand::H63,
+            Command::Filesystem => VendorCommand::H64,
+            Command::FactoryReset => VendorCommand::H65,
+            Command::ConfigList => VendorCommand::H66,
+            Command::ConfigGet => VendorCommand::H67,
+            Command::ConfigSet => VendorCommand::H68,
+        }
+    }
+
+    pub fn from_code(code: VendorCommand) -> Option<Self> {
+        Self::ALL.iter().copied().find(|command| command.code() == code)
+    }
+}
@@ -0,0 +1,125 @@
+//! Device settings, kept by the admin app in the Trussed store.
+//!
+//! The user presence timeout applies right away, the other settings are
+//! read by the runner at boot, so they apply after the next reboot.
+//! A factory reset restores the defaults.
+
+use heapless::consts;
+use littlefs2::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use trussed::{error::Error, store::{self, Store}, types::Location};
+
+const PATH: &[u8] = b"/admin/cfg";
+
+/// The range of the user presence timeout, in milliseconds.
+const USER_PRESENCE_TIMEOUTS: core::ops::RangeInclusive<u32> = 1_000..=60_000;
+
+/// The settings, serialized as CBOR map with text keys.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    /// Use the NFC interface (unless the NFC field is what powers the device).
+    pub nfc: bool,
+    /// Scales the LED's intensities, 255 is full brightness and 0 turns it off.
+    pub led_brightness: u8,
+    /// How long the admin app waits for the user's presence, in milliseconds.
+    pub up_timeout: u32,
+    pub apps: Apps,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            nfc: true,
+            led_brightness: 255,
+            up_timeout: 15_000,
+            apps: Apps::default(),
+        }
+    }
+}
+
+/// Which of the apps compiled in answer requests; the admin app always does.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Apps {
+    pub fido: bool,
+    pub oath: bool,
+    pub piv: bool,
+    pub ndef: bool,
+}
+
+impl Default for Apps {
+    fn default() -> Self {
+        Self { fido: true, oath: true, piv: true, ndef: true }
+    }
+}
+
+/// The settings to change, all others are kept, for `apps` as well.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Changes {
+    nfc: Option<bool>,
+    led_brightness: Option<u8>,
+    up_timeout: Option<u32>,
+    apps: Option<AppChanges>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AppChanges {
+    fido: Option<bool>,
+    oath: Option<bool>,
+    piv: Option<bool>,
+    ndef: Option<bool>,
+}
+
+impl Apps {
+    fn change(&mut self, changes: AppChanges) {
+        self.fido = changes.fido.unwrap_or(self.fido);
+        self.oath = changes.oath.unwrap_or(self.oath);
+        self.piv = changes.piv.unwrap_or(self.piv);
+        self.ndef = changes.ndef.unwrap_or(self.ndef);
+    }
+}
+
+/// A setting was out of range, or the settings could not be stored.
+pub(crate) struct Invalid;
+
+impl Config {
+    /// The stored settings, or the defaults if there are none (or they can't be read).
+    pub fn load<S: Store>(store: S) -> Self {
+        store::read::<consts::U256>(store, Location::Internal, &PathBuf::from(PATH)).ok()
+            .and_then(|cbor| ctap_types::serde::cbor_deserialize(&cbor).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save<S: Store>(&self, store: S) -> Result<(), Error> {
+        let mut buffer = [0u8; 256];
+        let cbor = ctap_types::serde::cbor_serialize(self, &mut buffer)
+            .map_err(|_| Error::FilesystemWriteFailure)?;
+        store::store(store, Location::Internal, &PathBuf::from(PATH), cbor)
+    }
+
+    /// Applies and stores the changes, all or none of them.
+    pub(crate) fn change<S: Store>(&mut self, changes: Changes, store: S) -> Result<(), Invalid> {
+        let mut config = self.clone();
+        if let Some(nfc) = changes.nfc {
+            config.nfc = nfc;
+        }
+        if let Some(led_brightness) = changes.led_brightness {
+            config.led_brightness = led_brightness;
+        }
+        if let Some(up_timeout) = changes.up_timeout {
+            if !USER_PRESENCE_TIMEOUTS.contains(&up_timeout) {
+                return Err(Invalid);
+            }
+            config.up_timeout = up_timeout;
+        }
+        if let Some(apps) = changes.apps {
+            config.apps.change(apps);
+        }
+        config.save(store).map_err(|_| Invalid)?;
+        *self = config;
+        Ok(())
+    }
+}
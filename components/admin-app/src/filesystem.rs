@@ -0,0 +1,140 @@
+use heapless::{consts, String, Vec};
+use littlefs2::{driver::Storage, fs::Filesystem, path::{Path, PathBuf}};
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use trussed::store::Store;
+
+/// Blocks of a filesystem.
+#[derive(Serialize)]
+pub(crate) struct Usage {
+    pub blocks: usize,
+    pub used: usize,
+    pub free: usize,
+}
+
+impl Usage {
+    pub fn of<S: Storage>(fs: &Filesystem<'_, S>) -> Self {
+        let blocks = fs.total_blocks();
+        let free = fs.available_blocks().unwrap_or(0);
+        Self { blocks, used: blocks - free, free }
+    }
+}
+
+/// Bytes in the top-level directories of the internal filesystem, i.e., per Trussed client
+/// (and `attn` for the attestation keys).
+#[derive(Default)]
+pub(crate) struct Directories(Vec<(String<consts::U16>, usize), consts::U16>);
+
+impl Serialize for Directories {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, size) in self.0.iter() {
+            map.serialize_entry(name.as_str(), size)?;
+        }
+        map.end()
+    }
+}
+
+/// The filesystem status command's response, a CBOR map with text keys.
+#[derive(Serialize)]
+pub(crate) struct Status {
+    internal: Usage,
+    external: Usage,
+    volatile: Usage,
+    directories: Directories,
+}
+
+impl Status {
+    pub fn of<S: Store>(store: S) -> Self {
+        Self {
+            internal: Usage::of(store.ifs()),
+            external: Usage::of(store.efs()),
+            volatile: Usage::of(store.vfs()),
+            directories: directories(store.ifs()),
+        }
+    }
+}
+
+fn is_special(name: &str) -> bool {
+    name == "." || name == ".."
+}
+
+fn directories<S: Storage>(fs: &Filesystem<'_, S>) -> Directories {
+    let mut directories = Directories::default();
+    let root = PathBuf::from(&b"/"[..]);
+    fs.read_dir_and_then(&root, |dir| {
+        for entry in dir {
+            let entry = entry?;
+            let name: &str = entry.file_name().as_ref();
+            if is_special(name) || !entry.metadata().is_dir() {
+                continue;
+            }
+            // client IDs are short, longer names and more directories are left out
+            let mut short_name = String::new();
+            if short_name.push_str(name).is_ok() {
+                directories.0.push((short_name, size(fs, entry.path()))).ok();
+            }
+        }
+        Ok(())
+    }).ok();
+    directories
+}
+
+/// Bytes in the files below `path`.
+fn size<S: Storage>(fs: &Filesystem<'_, S>, path: &Path) -> usize {
+    fs.read_dir_and_then(path, |dir| {
+        let mut total = 0;
+        for entry in dir {
+            let entry = entry?;
+            let name: &str = entry.file_name().as_ref();
+            if is_special(name) {
+                continue;
+            }
+            let metadata = entry.metadata();
+            total += if metadata.is_dir() { size(fs, entry.path()) } else { metadata.len() };
+        }
+        Ok(total)
+    }).unwrap_or(0)
+}
+
+/// The directory that survives a factory reset: the attestation keys and certificates.
+const ATTESTATION: &str = "attn";
+
+/// Removes all app data, everything except the attestation directory.
+///
+/// This includes Trussed's own state (its RNG seed in `/trussed`), which Trussed
+/// regenerates from the hardware RNG on the next boot.
+///
+/// This goes around Trussed, so the service must not run meanwhile, see `Reboot::reboot_after`.
+pub(crate) fn factory_reset<S: Store>(store: S) -> littlefs2::io::Result<()> {
+    let root = PathBuf::from(&b"/"[..]);
+    clear(store.ifs(), &root, &[ATTESTATION])?;
+    clear(store.efs(), &root, &[])?;
+    clear(store.vfs(), &root, &[])
+}
+
+/// Removes the entries below `path`, except those named in `keep`.
+///
+/// Entries are removed one at a time, outside of the directory iteration.
+fn clear<S: Storage>(fs: &Filesystem<'_, S>, path: &Path, keep: &[&str]) -> littlefs2::io::Result<()> {
+    loop {
+        let next = fs.read_dir_and_then(path, |dir| {
+            for entry in dir {
+                let entry = entry?;
+                let name: &str = entry.file_name().as_ref();
+                if is_special(name) || keep.contains(&name) {
+                    continue;
+                }
+                return Ok(Some((PathBuf::from(entry.path()), entry.metadata().is_dir())));
+            }
+            Ok(None)
+        })?;
+        match next {
+            None => return Ok(()),
+            Some((path, true)) => {
+                clear(fs, &path, &[])?;
+                fs.remove_dir(&path)?;
+            }
+            Some((path, false)) => fs.remove(&path)?,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use serde::Serialize;
+
+use crate::filesystem::Usage;
+
+/// How the device runs its NFC interface.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Nfc {
+    /// No NFC chip, or not enabled.
+    Disabled,
+    Enabled,
+    /// Enabled, and powered by the reader's field only.
+    Passive,
+}
+
+impl Nfc {
+    fn as_str(self) -> &'static str {
+        match self {
+            Nfc::Disabled => "disabled",
+            Nfc::Enabled => "enabled",
+            Nfc::Passive => "passive",
+        }
+    }
+}
+
+/// What the runner knows about the device, for the device info command.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    /// The firmware version, e.g. "1.0.2".
+    pub version: &'static str,
+    /// The Cargo features selecting the apps that were compiled in.
+    pub features: &'static [&'static str],
+    /// The board variant, e.g. "solo2", "nk3xn" or "lpcxpresso55".
+    pub board: &'static str,
+    /// Whether the internal storage is encrypted with PRINCE.
+    pub prince: bool,
+    pub nfc: Nfc,
+    /// The secure firmware version in the CFPA, if there is one.
+    pub secure_firmware_version: Option<u32>,
+}
+
+/// The device info command's response, a CBOR map with text keys.
+#[derive(Serialize)]
+pub(crate) struct Response<'a> {
+    version: &'a str,
+    features: &'a [&'a str],
+    board: &'a str,
+    prince: bool,
+    nfc: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secure_fw_version: Option<u32>,
+    /// Usage of the internal filesystem.
+    fs: Usage,
+}
+
+impl<'a> Response<'a> {
+    pub fn new(info: &'a DeviceInfo, fs: Usage) -> Self {
+        Self {
+            version: info.version,
+            features: info.features,
+            board: info.board,
+            prince: info.prince,
+            nfc: info.nfc.as_str(),
+            secure_fw_version: info.secure_firmware_version,
+            fs,
+        }
+    }
+}
@@ -8,3 +8,14 @@
 
 mod admin;
 pub use admin::{App, Reboot};
+
+mod command;
+pub use command::Command;
+
+pub mod config;
+pub use config::Config;
+
+mod filesystem;
+
+mod info;
+pub use info::{DeviceInfo, Nfc};
@@ -14,5 +14,8 @@ pub trait App {
     /// Application must put response in @message, or decide to return an error.
     ///
     /// The response is pre-cleared.
+    ///
+    /// Long running commands should check `crate::cancel::is_cancelled()`,
+    /// and give up early if the host cancelled the request.
     fn call(&mut self, command: Command, request: &Message, response: &mut Message) -> AppResult;
 }
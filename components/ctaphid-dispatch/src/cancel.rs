@@ -0,0 +1,44 @@
+//! Cancellation of the pending request by the host (CTAPHID_CANCEL).
+//!
+//! Apps are called synchronously, and may block for a long time, e.g. while
+//! waiting for user presence. The CTAPHID layer keeps running in interrupts
+//! meanwhile, and signals here that the host is no longer interested in the
+//! response, so the app can give up early.
+//!
+//! The signal is scoped to the request it was given for: the transport numbers
+//! the requests it hands to the dispatcher, and the dispatcher clears the signal
+//! once the app is done, so neither later requests nor other transports (which
+//! share the apps) see a stale cancellation.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Not a request number, for "nothing cancelled".
+const NONE: u32 = u32::MAX;
+
+static REQUEST: AtomicU32 = AtomicU32::new(0);
+static CANCELLED: AtomicU32 = AtomicU32::new(NONE);
+
+/// Numbers the request the transport just handed to the dispatcher.
+pub fn new_request() {
+    let mut request = REQUEST.load(Ordering::Relaxed).wrapping_add(1);
+    if request == NONE {
+        request = 0;
+    }
+    REQUEST.store(request, Ordering::Relaxed);
+}
+
+/// Signals that the host cancelled the pending request.
+pub fn cancel() {
+    CANCELLED.store(REQUEST.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+/// Clears the signal, as the dispatcher does when the app is done with the request,
+/// and the transport when it takes (or drops) the response.
+pub fn reset() {
+    CANCELLED.store(NONE, Ordering::Relaxed);
+}
+
+/// Whether the host cancelled the pending request.
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::Relaxed) == REQUEST.load(Ordering::Relaxed)
+}
@@ -30,6 +30,32 @@ impl Command {
     }
 }
 
+/// A set of commands, e.g. those the apps registered to.
+///
+/// All command codes are below 0x80, so one bit per code suffices.
+#[derive(Copy,Clone,Debug,Default,Eq,PartialEq)]
+pub struct Commands(u128);
+
+impl Commands {
+    pub fn insert(&mut self, command: Command) {
+        self.0 |= 1 << command.into_u8();
+    }
+
+    pub fn contains(&self, command: Command) -> bool {
+        self.0 & (1 << command.into_u8()) != 0
+    }
+}
+
+impl core::iter::FromIterator<Command> for Commands {
+    fn from_iter<I: IntoIterator<Item = Command>>(iter: I) -> Self {
+        let mut commands = Self::default();
+        for command in iter {
+            commands.insert(command);
+        }
+        commands
+    }
+}
+
 impl TryFrom<u8> for Command {
     type Error = ();
 
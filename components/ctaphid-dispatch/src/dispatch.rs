@@ -1,6 +1,7 @@
 
 use interchange::{Interchange, Responder};
-use crate::types::{Command, Message, HidInterchange, Error};
+use crate::command::Commands;
+use crate::types::{Command, Message, HidInterchange, InterchangeResponse, Error};
 use crate::app::App;
 
 pub struct Dispatch {
@@ -17,6 +18,15 @@ impl Dispatch {
         }
     }
 
+    /// The union of the commands the apps registered to.
+    ///
+    /// The CTAPHID layer derives the capabilities in its INIT response from these.
+    pub fn commands(apps: &[&mut dyn App]) -> Commands {
+        apps.iter()
+            .flat_map(|app| app.commands().iter().copied())
+            .collect()
+    }
+
     fn find_app<'a, 'b>(
         command: Command,
         apps: &'a mut [&'b mut dyn App]
@@ -38,9 +48,16 @@ impl Dispatch {
     // Using helper here to take potentially large stack burden off of call chain to application.
     #[inline(never)]
     fn reply_with_error(&mut self, error: Error){
-        self.responder.respond(
-            &Err(error)
-        ).expect("cant respond");
+        self.respond(&Err(error));
+    }
+
+    // The CTAPHID layer cancels the interchange if the host re-synchronizes
+    // while the app is working, then nobody is waiting for the response anymore.
+    fn respond(&mut self, response: &InterchangeResponse) {
+        if self.responder.respond(response).is_err() {
+            info!("request was cancelled, dropping response");
+            self.responder.acknowledge_cancel().ok();
+        }
     }
 
     #[inline(never)]
@@ -61,7 +78,7 @@ impl Dispatch {
             self.reply_with_error(error)
         } else {
             let response = Ok(response_buffer.clone());
-            self.responder.respond(&response).expect("responder failed");
+            self.respond(&response);
         }
     }
 
@@ -81,6 +98,8 @@ impl Dispatch {
             } else {
                 self.reply_with_error(Error::InvalidCommand);
             }
+            // answered, or dropped after a re-synchronization
+            crate::cancel::reset();
         }
 
         self.responder.state() == interchange::State::Responded
@@ -14,6 +14,7 @@ extern crate delog;
 generate_macros!();
 
 pub mod app;
+pub mod cancel;
 pub mod types;
 pub mod command;
 pub mod dispatch;
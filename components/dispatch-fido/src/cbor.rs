@@ -6,6 +6,20 @@ use ctap_types::{
     serde::{cbor_deserialize, error::Error as SerdeError},
 };
 
+// CTAP 2.1 commands without a request in `ctap_types`
+const BIO_ENROLLMENT: u8 = 0x09;
+const SELECTION: u8 = 0x0B;
+const LARGE_BLOBS: u8 = 0x0C;
+const CONFIG: u8 = 0x0D;
+const PREVIEW_BIO_ENROLLMENT: u8 = 0x40;
+
+pub enum CborRequest {
+    /// A request for the authenticator.
+    Authenticator(Request),
+    /// authenticatorSelection, only checking the user's presence.
+    Selection,
+}
+
 pub enum CtapMappingError {
     InvalidCommand(u8),
     ParsingError(SerdeError),
@@ -28,7 +42,7 @@ impl From<CtapMappingError> for AuthenticatorError {
     }
 }
 
-pub fn parse_cbor(data: &[u8]) -> core::result::Result<Request, CtapMappingError> {
+pub fn parse_cbor(data: &[u8]) -> core::result::Result<CborRequest, CtapMappingError> {
 
     if data.len() < 1 {
         return Err(CtapMappingError::ParsingError(SerdeError::DeserializeUnexpectedEnd));
@@ -36,6 +50,30 @@ pub fn parse_cbor(data: &[u8]) -> core::result::Result<Request, CtapMappingError
 
     let operation_u8: u8 = data[0];
 
+    match operation_u8 {
+        SELECTION => {
+            info!("authenticatorSelection");
+            return Ok(CborRequest::Selection);
+        }
+
+        // the authenticator has none of these features, so they are invalid commands
+        BIO_ENROLLMENT | PREVIEW_BIO_ENROLLMENT | LARGE_BLOBS | CONFIG => {
+            info!("unsupported CTAP 2.1 command {:02x}", operation_u8);
+            // the parameters, if any, are a map
+            return match data.get(1) {
+                Some(0xa0..=0xbf) | None => Err(CtapMappingError::InvalidCommand(operation_u8)),
+                Some(_) => Err(CtapMappingError::ParsingError(SerdeError::DeserializeExpectedMap)),
+            };
+        }
+
+        _ => {}
+    }
+
+    parse_authenticator_request(operation_u8, data).map(CborRequest::Authenticator)
+}
+
+fn parse_authenticator_request(operation_u8: u8, data: &[u8]) -> core::result::Result<Request, CtapMappingError> {
+
     let operation = match Operation::try_from(operation_u8) {
         Ok(operation) => {
             operation
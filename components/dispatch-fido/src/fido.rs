@@ -6,19 +6,33 @@ use ctap_types::{
     authenticator::Error as AuthenticatorError,
     authenticator::Request as AuthenticatorRequest,
     serde::{cbor_serialize},
-    ctap1::{Command as U2fCommand},
+    ctap1::{Command as U2fCommand, Response as U2fResponse},
 };
 
-use crate::cbor::{parse_cbor};
+use crate::cbor::{parse_cbor, CborRequest};
+use crate::transport::{self, Transport};
 
 use trussed::client;
 use fido_authenticator::{Authenticator, UserPresence};
 use ctaphid_dispatch::app as hid;
 
+/// How long authenticatorSelection waits for the user's presence.
+const SELECTION_TIMEOUT_MILLISECONDS: u32 = 30_000;
+
+/// NFCCTAP_GETRESPONSE, polling for the response to an NFCCTAP_MSG.
+/// It shares its code with CTAPHID_CANCEL, but means something else.
+const NFCCTAP_GETRESPONSE: u8 = 0x11;
+
 pub struct Fido<UP, T>
 where UP: UserPresence,
 {
     authenticator: Authenticator<UP, T>,
+    /// The authenticator keeps its client to itself, so authenticatorSelection
+    /// checks the user's presence with a client of its own.
+    up: UP,
+    trussed: T,
+    /// Whether the device has an NFC interface, for GetInfo's transports.
+    nfc: bool,
 }
 
 impl<UP, Trussed> Fido<UP, Trussed>
@@ -32,8 +46,8 @@ where UP: UserPresence,
        + client::Ed255
        + client::Totp
 {
-    pub fn new(authenticator: Authenticator<UP, Trussed>) -> Fido<UP, Trussed> {
-        Self { authenticator }
+    pub fn new(authenticator: Authenticator<UP, Trussed>, up: UP, trussed: Trussed, nfc: bool) -> Fido<UP, Trussed> {
+        Self { authenticator, up, trussed, nfc }
     }
 
     fn response_from_object<T: serde::Serialize>(&mut self, object: Option<T>, reply: &mut response::Data) -> app::Result {
@@ -57,27 +71,73 @@ where UP: UserPresence,
         Ok(())
     }
 
+    /// Serializes a U2F response, the status word is added by the caller.
+    fn response_from_u2f(&mut self, response: &U2fResponse, reply: &mut response::Data) -> app::Result {
+        match response.serialize(reply) {
+            Ok(()) => Ok(()),
+            Err(_) => {
+                info!("U2F response does not fit");
+                reply.clear();
+                Err(Status::NotEnoughMemory)
+            }
+        }
+    }
+
+    /// The status word for a U2F request that failed in the authenticator.
+    fn u2f_status(error: AuthenticatorError) -> Status {
+        match error {
+            AuthenticatorError::InvalidCommand => Status::InstructionNotSupportedOrInvalid,
+            AuthenticatorError::InvalidLength => Status::WrongLength,
+            AuthenticatorError::InvalidParameter
+                | AuthenticatorError::InvalidCredential
+                | AuthenticatorError::NoCredentials => Status::IncorrectDataParameter,
+            // test of user presence required
+            AuthenticatorError::OperationDenied
+                | AuthenticatorError::UserActionTimeout
+                | AuthenticatorError::KeepaliveCancel => Status::ConditionsOfUseNotSatisfied,
+            _ => Status::UnspecifiedCheckingError,
+        }
+    }
+
     fn call_authenticator(&mut self, request: &AuthenticatorRequest, reply: &mut response::Data) -> app::Result {
 
         let result = self.authenticator.call(request);
         match &result {
             Err(error) => {
-                info!("error {}", *error as u8);
-                reply.push(*error as u8).ok();
+                // a user presence check the host gave up on fails like any other
+                let error = if ctaphid_dispatch::cancel::is_cancelled() {
+                    AuthenticatorError::KeepaliveCancel
+                } else {
+                    *error
+                };
+                info!("error {}", error as u8);
+                if let AuthenticatorRequest::Ctap1(_) = request {
+                    // U2F has status words instead of CTAP2 status codes
+                    return Err(Self::u2f_status(error));
+                }
+                reply.push(error as u8).ok();
                 Ok(())
             }
 
             Ok(response) => {
                 use ctap_types::authenticator::Response;
                 match response {
-                    Response::Ctap1(_response) => {
-                        todo!("CTAP1 responses");
+                    Response::Ctap1(response) => {
+                        self.response_from_u2f(response, reply)
                     }
 
                     Response::Ctap2(response) => {
                         use ctap_types::authenticator::ctap2::Response;
                         match response {
                             Response::GetInfo(response) => {
+                                // USB is always there, NFC if the device has it, whichever is in use
+                                let mut response = response.clone();
+                                let mut transports = heapless::Vec::new();
+                                if self.nfc {
+                                    transports.push(heapless::String::from("nfc")).ok();
+                                }
+                                transports.push(heapless::String::from("usb")).ok();
+                                response.transports = Some(transports);
                                 self.response_from_object(Some(response), reply)
                             },
 
@@ -115,6 +175,38 @@ where UP: UserPresence,
         }
     }
 
+    #[inline(never)]
+    fn call_authenticator_cbor(&mut self, data: &[u8], reply: &mut response::Data) -> app::Result {
+        match parse_cbor(data) {
+            Ok(CborRequest::Authenticator(request)) => {
+                info!("parsed cbor");
+                self.call_authenticator(&request, reply)
+            }
+            Ok(CborRequest::Selection) => {
+                self.call_selection(reply)
+            }
+            Err(mapping_error) => {
+                let authenticator_error: AuthenticatorError = mapping_error.into();
+                info!("cbor mapping error: {}", authenticator_error as u8);
+                reply.push(authenticator_error as u8).ok();
+                Ok(())
+            }
+        }
+    }
+
+    fn call_selection(&mut self, reply: &mut response::Data) -> app::Result {
+        let status = if self.up.user_present(&mut self.trussed, SELECTION_TIMEOUT_MILLISECONDS) {
+            0
+        } else if ctaphid_dispatch::cancel::is_cancelled() {
+            AuthenticatorError::KeepaliveCancel as u8
+        } else {
+            AuthenticatorError::UserActionTimeout as u8
+        };
+        info!("selection: {}", status);
+        reply.push(status).ok();
+        Ok(())
+    }
+
     #[inline(never)]
     fn call_authenticator_u2f_with_bytes(&mut self, request: &response::Data, reply: &mut response::Data) -> app::Result {
         match &Command::try_from(request) {
@@ -130,14 +222,8 @@ where UP: UserPresence,
     #[inline(never)]
     fn call_authenticator_u2f(&mut self, apdu: &Command, reply: &mut response::Data) -> app::Result {
         let u2f_command = U2fCommand::try_from(apdu)?;
-        let result = self.authenticator.call_u2f(&u2f_command);
-        match result {
-            Ok(u2f_response) => {
-                u2f_response.serialize(reply).unwrap();
-                Ok(())
-            }
-            Err(err)=> Err(err)
-        }
+        let u2f_response = self.authenticator.call_u2f(&u2f_command)?;
+        self.response_from_u2f(&u2f_response, reply)
     }
 
 
@@ -176,32 +262,28 @@ where UP: UserPresence,
 
     fn deselect(&mut self) {}
 
-    fn call(&mut self, _type: app::Interface, apdu: &Command, reply: &mut response::Data) -> app::Result {
+    fn call(&mut self, interface: app::Interface, apdu: &Command, reply: &mut response::Data) -> app::Result {
+        transport::set(interface.into());
         let instruction = apdu.instruction();
 
         match instruction {
             Instruction::Unknown(ins) => {
                 // TODO need to tidy up these ins codes somewhere
                 match ins {
-                    // U2F ins codes
-                    0x00 | 0x01 | 0x02 => {
+                    // U2F ins codes: register, authenticate, version
+                    0x00 | 0x01 | 0x02 | 0x03 => {
                         self.call_authenticator_u2f(apdu, reply)
                     }
+                    NFCCTAP_GETRESPONSE if transport::current() == Transport::Nfc => {
+                        // over NFC, user presence needs no waiting, so every
+                        // request is answered right away, and none is ever pending
+                        info!("no pending response");
+                        Err(Status::ConditionsOfUseNotSatisfied)
+                    }
                     _ => {
                         match FidoCommand::try_from(ins) {
                             Ok(FidoCommand::Cbor) => {
-                                match parse_cbor(apdu.data()) {
-                                    Ok(request) => {
-                                        info!("parsed cbor");
-                                        self.call_authenticator(&request, reply)
-                                    }
-                                    Err(mapping_error) => {
-                                        let authenticator_error: AuthenticatorError = mapping_error.into();
-                                        info!("cbor mapping error: {}", authenticator_error as u8);
-                                        reply.push(authenticator_error as u8).ok();
-                                        Ok(())
-                                    }
-                                }
+                                self.call_authenticator_cbor(apdu.data(), reply)
                             }
                             Ok(FidoCommand::Msg) => {
                                 self.call_authenticator_u2f(apdu, reply)
@@ -250,24 +332,13 @@ where UP: UserPresence,
         if request.len() < 1 {
             return Err(hid::Error::InvalidLength);
         }
+        transport::set(Transport::Usb);
         // info_now!("request: ");
         // blocking::dump_hex(request, request.len());
         match command {
             hid::Command::Cbor => {
-                match parse_cbor(request) {
-                    Ok(request) => {
-                        self.call_authenticator(&request, response).ok();
-                        Ok(())
-                    }
-                    Err(mapping_error) => {
-                        let authenticator_error: AuthenticatorError = mapping_error.into();
-                        info!("authenticator_error: {}", authenticator_error as u8);
-                        response.extend_from_slice(&[
-                            authenticator_error as u8
-                        ]).ok();
-                        Ok(())
-                    }
-                }
+                self.call_authenticator_cbor(request, response).ok();
+                Ok(())
             },
             // hid::Command::Msg is only other registered command.
             _ => {
@@ -13,3 +13,9 @@ pub mod fido;
 pub use fido::*;
 
 pub mod cbor;
+
+pub mod presence;
+pub use presence::CancellableUserPresence;
+
+pub mod transport;
+pub use transport::Transport;
@@ -0,0 +1,30 @@
+use trussed::{client, syscall};
+use fido_authenticator::UserPresence;
+use ctaphid_dispatch::cancel;
+
+use crate::transport::{self, Transport};
+
+/// Asks for user presence like `fido_authenticator::NonSilentAuthenticator`,
+/// but gives up as soon as the host cancels the request.
+///
+/// The platform's user interface ends the wait once the request is cancelled,
+/// which Trussed reports like presence, so the cancellation is checked again.
+///
+/// Over NFC, the tap itself is the user's presence (there is no waiting for
+/// the button while the reader powers the key).
+#[derive(Copy, Clone)]
+pub struct CancellableUserPresence {}
+
+impl UserPresence for CancellableUserPresence {
+    fn user_present<T: client::Client>(self, trussed: &mut T, timeout_milliseconds: u32) -> bool {
+        if transport::current() == Transport::Nfc {
+            return true;
+        }
+        let result = syscall!(trussed.confirm_user_present(timeout_milliseconds)).result;
+        if cancel::is_cancelled() {
+            info!("user presence check cancelled");
+            return false;
+        }
+        result.is_ok()
+    }
+}
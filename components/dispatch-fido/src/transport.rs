@@ -0,0 +1,42 @@
+//! The transport the request being processed arrived over.
+//!
+//! CTAP behaves differently over NFC: the tap is the user's presence, and
+//! there is NFCCTAP_GETRESPONSE. The user presence check is done
+//! deep inside the authenticator, which only knows its Trussed client, so like
+//! cancellation, the transport is signalled here.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use apdu_dispatch::app::Interface;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Transport {
+    /// CTAPHID, or CCID over the contact interface.
+    Usb,
+    Nfc,
+}
+
+impl From<Interface> for Transport {
+    fn from(interface: Interface) -> Self {
+        match interface {
+            Interface::Contact => Transport::Usb,
+            Interface::Contactless => Transport::Nfc,
+        }
+    }
+}
+
+static NFC: AtomicBool = AtomicBool::new(false);
+
+/// Signals the transport of the request about to be processed.
+pub fn set(transport: Transport) {
+    NFC.store(transport == Transport::Nfc, Ordering::Relaxed);
+}
+
+/// The transport of the request being processed.
+pub fn current() -> Transport {
+    if NFC.load(Ordering::Relaxed) {
+        Transport::Nfc
+    } else {
+        Transport::Usb
+    }
+}
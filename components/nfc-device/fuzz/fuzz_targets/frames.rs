@@ -0,0 +1,28 @@
+//! Feeds arbitrary reader frames to the ISO 14443-4 layer, see `Reader::run_steps`.
+//!
+//! The interchange can only be claimed once, so all inputs share one PICC.
+//! Each input ends by checking the PICC recovers, which also resets it for the next.
+//!
+//! Run with `cargo fuzz run frames` in `components/nfc-device`.
+#![no_main]
+
+use std::cell::RefCell;
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+use common::Reader;
+
+thread_local! {
+    static READER: RefCell<Reader> = RefCell::new(Reader::new());
+}
+
+fuzz_target!(|data: &[u8]| {
+    READER.with(|reader| {
+        let mut reader = reader.borrow_mut();
+        reader.run_steps(data);
+        reader.check_recovers();
+    });
+});
@@ -46,8 +46,9 @@ enum Block {
 }
 
 impl Block {
-    fn new(frame: &[u8]) -> Block {
-        let header = frame[0];
+    /// Parses the prologue of a block, or returns None if the frame is not a valid block.
+    fn new(frame: &[u8]) -> Option<Block> {
+        let header = *frame.first()?;
 
         let block_num = (header & 1) != 0;
         let flag = (header & 0x10) != 0;
@@ -56,30 +57,29 @@ impl Block {
         // CID included
         let cid = if (header & 0x08) != 0 {
             offset += 1;
-            Some(frame[1])
+            Some(*frame.get(1)?)
         } else {
             None
         };
 
         if (header & 0xc2) == 0x02 {
 
-            // NAD included
+            // NAD included, after the CID
             let nad = if (header & 0x4) != 0 {
                 offset += 1;
-                if cid.is_some() {
-                    Some(frame[2])
-                } else {
-                    Some(frame[1])
-                }
+                Some(*frame.get(offset - 1)?)
             } else {
                 None
             };
-            Block::IBlock(block_num, nad, cid, flag, offset)
+            Some(Block::IBlock(block_num, nad, cid, flag, offset))
         } else if (header & 0xe2) == 0xa2 {
                                     // Ack or Nack
-            Block::RBlock(block_num, cid, !flag, offset)
+            Some(Block::RBlock(block_num, cid, !flag, offset))
+        } else if (header & 0xc7) == 0xc2 {
+            // DESELECT or WTX
+            Some(Block::SBlock(cid, (0x30 & header) == 0x30))
         } else {
-            Block::SBlock(cid, (0x30 & header) == 0x30)
+            None
         }
     }
 }
@@ -156,7 +156,14 @@ where
     // RBlock(BlockNum, Cid, Ack, ),
     // SBlock(Cid, WtxGranted, ),
     fn handle_block(&mut self, packet: &[u8]) -> Result<(), SourceError> {
-        let block_header = Block::new(packet);
+        let block_header = match Block::new(packet) {
+            Some(block_header) => block_header,
+            None => {
+                // Rule 4 (for the PCD) lets the reader recover by sending R(NAK)
+                info!("Ignoring invalid block");
+                return Err(SourceError::NoActivity);
+            }
+        };
         match block_header {
             Block::IBlock(_block_num, _nad, _cid, chaining, offset) => {
 
@@ -191,11 +198,9 @@ where
                     match self.state.clone() {
                         Iso14443State::Transmitting(last_frame_range, _remaining_data_range) => {
                             info!("Retransmission requested..");
-                            self.send_frame(
-                                &Bytes::try_from_slice(
-                                    &self.buffer[last_frame_range]
-                                ).unwrap()
-                            ).ok();
+                            // the same I-block again, with chaining if the data continues
+                            let (frame, _) = self.construct_iblock(&self.buffer[last_frame_range.start..]);
+                            self.send_frame(&frame).ok();
                         }
                         _ => {
                             info!("No recent transmissions! NAK");
@@ -296,7 +301,7 @@ where
         }
 
         // minus 2 to leave room for crc
-        let frame_size: usize = self.device.frame_size() - 2;
+        let frame_size: usize = core::cmp::min(self.device.frame_size(), 256) - 2;
         let payload_len = core::cmp::min(frame_size - header_length, data.len());
 
         frame.extend_from_slice(&data[0 .. payload_len]).ok();
@@ -343,7 +348,10 @@ where
         };
 
 
-        assert!(packet_len > 0);
+        if packet_len == 0 {
+            info!("Ignoring empty frame");
+            return Err(SourceError::NoActivity);
+        }
 
         // let packet = &self.packet;
         self.handle_block(&packet[.. packet_len as usize])?;
@@ -411,12 +419,13 @@ where
                     ).ok();
                     if data_used != msg.len() {
                         info!("chaining response!");
-                        self.buffer = msg;
-                        self.state = Iso14443State::Transmitting(
-                            0 .. data_used,
-                            data_used .. self.buffer.len()
-                        );
                     }
+                    // kept for retransmissions (rule 11) and chaining (rule 13)
+                    self.buffer = msg;
+                    self.state = Iso14443State::Transmitting(
+                        0 .. data_used,
+                        data_used .. self.buffer.len()
+                    );
                 // } else {
                     // info!("session was dropped! dropping response.");
                 // }
@@ -0,0 +1,215 @@
+//! Plays the reader (PCD) against `Iso14443` through a mock `nfc::Device`,
+//! and the app behind the contactless interchange.
+//!
+//! The contactless interchange can only be claimed once, so each test file sets up a single reader.
+//!
+//! The fuzz target in `fuzz/` includes this module, too.
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use apdu_dispatch::interchanges::{Contactless, Data};
+use interchange::{Interchange, Responder};
+use nfc_device::{traits::nfc, Iso14443};
+
+pub const SELECT: [u8; 4] = [0x00, 0xA4, 0x04, 0x00];
+
+// PCBs with block number 0, without CID or NAD
+pub const I_BLOCK: u8 = 0x02;
+pub const CHAINING: u8 = 0x10;
+pub const R_ACK: u8 = 0xA2;
+pub const R_NAK: u8 = 0xB2;
+pub const S_DESELECT: u8 = 0xC2;
+pub const S_WTX: u8 = 0xF2;
+
+/// Frame sizes the reader can ask for in RATS (FSDI 0 to 8).
+pub const FRAME_SIZES: [usize; 9] = [16, 24, 32, 40, 48, 64, 96, 128, 256];
+
+struct Frames {
+    /// Frames from the reader, and whether each one starts a new session.
+    incoming: VecDeque<(Vec<u8>, bool)>,
+    sent: Vec<Vec<u8>>,
+    frame_size: usize,
+}
+
+/// Hands out the reader's frames, and records the frames the PICC sends.
+#[derive(Clone)]
+pub struct MockDevice {
+    frames: Rc<RefCell<Frames>>,
+}
+
+impl nfc::Device for MockDevice {
+    fn read(&mut self, buf: &mut [u8]) -> Result<nfc::State, nfc::Error> {
+        let (frame, new_session) = self.frames.borrow_mut().incoming.pop_front()
+            .ok_or(nfc::Error::NoActivity)?;
+        buf[..frame.len()].copy_from_slice(&frame);
+        let len = frame.len() as u8;
+        if new_session {
+            Ok(nfc::State::NewSession(len))
+        } else {
+            Ok(nfc::State::Continue(len))
+        }
+    }
+
+    fn send(&mut self, buf: &[u8]) -> Result<(), nfc::Error> {
+        let mut frames = self.frames.borrow_mut();
+        // the device appends two bytes of CRC
+        assert!(
+            buf.len() + 2 <= frames.frame_size,
+            "frame of {} bytes exceeds the frame size {}: {:02X?}", buf.len(), frames.frame_size, buf,
+        );
+        frames.sent.push(buf.to_vec());
+        Ok(())
+    }
+
+    fn frame_size(&self) -> usize {
+        self.frames.borrow().frame_size
+    }
+}
+
+pub struct Reader {
+    picc: Iso14443<MockDevice>,
+    app: Responder<Contactless>,
+    frames: Rc<RefCell<Frames>>,
+}
+
+impl Reader {
+    pub fn new() -> Self {
+        let frames = Rc::new(RefCell::new(Frames {
+            incoming: VecDeque::new(),
+            sent: Vec::new(),
+            frame_size: 256,
+        }));
+        let device = MockDevice { frames: frames.clone() };
+        let (requester, app) = Contactless::claim().expect("interchange already claimed");
+        let picc = Iso14443::new(device, requester);
+        Self { picc, app, frames }
+    }
+
+    /// Sets the frame size (FSD) the reader asked for.
+    pub fn set_frame_size(&mut self, frame_size: usize) {
+        self.frames.borrow_mut().frame_size = frame_size;
+    }
+
+    fn take_sent(&mut self) -> Vec<Vec<u8>> {
+        core::mem::take(&mut self.frames.borrow_mut().sent)
+    }
+
+    /// Polls until the PICC has read the queued frames, as each one raises an interrupt.
+    fn deliver(&mut self, frame: &[u8], new_session: bool) -> Vec<Vec<u8>> {
+        assert!(frame.len() <= 255);
+        self.frames.borrow_mut().incoming.push_back((frame.to_vec(), new_session));
+        while !self.frames.borrow().incoming.is_empty() {
+            self.picc.poll();
+        }
+        self.take_sent()
+    }
+
+    /// Sends a frame, and returns the frames the PICC sends in reply.
+    pub fn send(&mut self, frame: &[u8]) -> Vec<Vec<u8>> {
+        self.deliver(frame, false)
+    }
+
+    /// Sends the first frame after the card has been (re)activated.
+    pub fn activate(&mut self, frame: &[u8]) -> Vec<Vec<u8>> {
+        self.deliver(frame, true)
+    }
+
+    /// Polls once without any new frame, as the runner does once the app responded.
+    pub fn poll(&mut self) -> Vec<Vec<u8>> {
+        self.picc.poll();
+        self.take_sent()
+    }
+
+    /// Lets the wait extension timer fire.
+    pub fn wait_extension(&mut self) -> Vec<Vec<u8>> {
+        self.picc.poll_wait_extensions();
+        self.take_sent()
+    }
+
+    /// Takes the request the app received, if any.
+    pub fn app_request(&mut self) -> Option<Vec<u8>> {
+        self.app.take_request().map(|request| request.to_vec())
+    }
+
+    pub fn app_respond(&mut self, response: &[u8]) {
+        self.app.respond(&Data::try_from_slice(response).unwrap()).ok().unwrap();
+    }
+
+    /// Answers a pending request, with `length` bytes of the request repeated.
+    fn serve_app(&mut self, length: usize) -> Vec<Vec<u8>> {
+        if let Some(request) = self.app_request() {
+            let response: Vec<u8> = request.iter().copied().chain(core::iter::repeat(0x90)).take(length).collect();
+            self.app_respond(&response);
+        }
+        self.poll()
+    }
+
+    /// Interprets arbitrary data as a sequence of steps for the PICC.
+    ///
+    /// Each step is a control byte and a length byte, followed by `length` bytes
+    /// of a frame (shorter if the data ends). The low two bits of the control byte select the step:
+    ///
+    /// - 0: the reader sends the frame
+    /// - 1: the reader activates the card with a frame size from the upper bits, and sends the frame
+    /// - 2: the app answers with `length * 4` bytes
+    /// - 3: the wait extension timer fires
+    ///
+    /// Every frame the PICC sends must be a valid block that fits into the frame size.
+    pub fn run_steps(&mut self, mut data: &[u8]) {
+        while let Some((&control, rest)) = data.split_first() {
+            let length = rest.first().copied().unwrap_or(0) as usize;
+            let rest = rest.get(1..).unwrap_or(&[]);
+            let (frame, rest) = rest.split_at(length.min(rest.len()));
+            data = rest;
+
+            let sent = match control & 0x3 {
+                0 => self.send(frame),
+                1 => {
+                    self.set_frame_size(FRAME_SIZES[(control >> 2) as usize % FRAME_SIZES.len()]);
+                    self.activate(frame)
+                }
+                2 => self.serve_app(length * 4),
+                _ => self.wait_extension(),
+            };
+
+            for block in sent {
+                assert_picc_block(&block);
+            }
+        }
+    }
+
+    /// Checks that a new session works normally, whatever state `run_steps` left the PICC in.
+    pub fn check_recovers(&mut self) {
+        for block in self.serve_app(0) {
+            assert_picc_block(&block);
+        }
+        assert_eq!(self.app_request(), None);
+
+        // Rule C: the PICC starts with block number 1, so it answers with 0
+        self.set_frame_size(256);
+        assert_eq!(self.activate(&[&[I_BLOCK][..], &SELECT].concat()), Vec::<Vec<u8>>::new());
+        assert_eq!(self.app_request().expect("APDU did not reach the app"), SELECT);
+        self.app_respond(&[0x90, 0x00]);
+        assert_eq!(self.poll(), [[I_BLOCK, 0x90, 0x00]]);
+
+        assert_eq!(self.send(&[S_DESELECT]), [[S_DESELECT]]);
+    }
+}
+
+/// Asserts that a frame from the PICC is an I-block, R(ACK), S(DESELECT) or S(WTX),
+/// with the CID byte if indicated.
+pub fn assert_picc_block(block: &[u8]) {
+    let pcb = block[0];
+    let header_length = 1 + (pcb & 0x08 != 0) as usize;
+    let valid = match pcb & 0xf7 {
+        0x02 | 0x03 | 0x12 | 0x13 => block.len() >= header_length,
+        0xa2 | 0xa3 | 0xc2 => block.len() == header_length,
+        // with WTXM
+        0xf2 => block.len() == header_length + 1,
+        _ => false,
+    };
+    assert!(valid, "invalid block from the PICC: {:02X?}", block);
+}
@@ -0,0 +1,41 @@
+//! Random frames from a hostile reader must neither panic nor leave the PICC stuck.
+//!
+//! The same steps as the fuzz target in `fuzz/`, from a fixed seed.
+
+mod common;
+
+use common::Reader;
+use test_support::Random;
+
+// I-blocks, R-blocks and S-blocks, with and without CID and NAD, and invalid PCBs
+const PCBS: [u8; 16] = [
+    0x02, 0x03, 0x12, 0x13, 0x0a, 0x06, 0xa2, 0xa3, 0xb2, 0xb3, 0xaa, 0xc2, 0xf2, 0xfa, 0x00, 0xff,
+];
+
+/// A step for `Reader::run_steps`, mostly short frames with a known PCB so transactions get somewhere.
+fn random_step(random: &mut Random) -> Vec<u8> {
+    let control = random.byte();
+    let length = match random.next() % 4 {
+        0 => random.byte(),
+        _ => random.byte() % 8,
+    };
+    let mut step = vec![control, length];
+    step.extend((0..length).map(|_| random.byte()));
+    if length > 0 && random.next() % 4 != 0 {
+        step[2] = random.pick(&PCBS);
+    }
+    step
+}
+
+#[test]
+fn random_frames() {
+    let mut reader = Reader::new();
+    let mut random = Random(0x5eed_1443);
+
+    for _ in 0..300 {
+        let steps = 1 + random.next() as usize % 40;
+        let data: Vec<u8> = (0..steps).flat_map(|_| random_step(&mut random)).collect();
+        reader.run_steps(&data);
+        reader.check_recovers();
+    }
+}
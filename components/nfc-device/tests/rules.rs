@@ -0,0 +1,126 @@
+//! The PICC side of the block transmission protocol (ISO 14443-4, 7.5.4), rules C - E and 9 - 13.
+//!
+//! The interchange can only be claimed once, so the rules are checked one after another
+//! in a single session.
+
+mod common;
+
+use common::{Reader, CHAINING, I_BLOCK, R_ACK, R_NAK, SELECT, S_DESELECT, S_WTX};
+
+fn apdu(pcb: u8, apdu: &[u8]) -> Vec<u8> {
+    [&[pcb][..], apdu].concat()
+}
+
+const NOTHING: [[u8; 0]; 0] = [];
+
+/// Rules C and D: after activation, the PICC answers with block number 0.
+fn activation(reader: &mut Reader) {
+    assert_eq!(reader.activate(&apdu(I_BLOCK, &SELECT)), NOTHING);
+    assert_eq!(reader.app_request().unwrap(), SELECT);
+    reader.app_respond(&[0x90, 0x00]);
+    assert_eq!(reader.poll(), [[I_BLOCK, 0x90, 0x00]]);
+}
+
+/// Rules 11 and 12, with block number 0 as the PICC's current one.
+fn retransmission(reader: &mut Reader) {
+    // Rule 11: the last block again
+    assert_eq!(reader.send(&[R_NAK]), [[I_BLOCK, 0x90, 0x00]]);
+    assert_eq!(reader.send(&[R_ACK]), [[I_BLOCK, 0x90, 0x00]]);
+
+    // Rule 12: R(NAK) with the other block number is answered with R(ACK)
+    assert_eq!(reader.send(&[R_NAK | 1]), [[R_ACK]]);
+}
+
+/// Rules D and 13, and E: a response split into I-blocks with chaining.
+fn chained_response(reader: &mut Reader) {
+    reader.set_frame_size(16);
+
+    assert_eq!(reader.send(&apdu(I_BLOCK | 1, &SELECT)), NOTHING);
+    assert_eq!(reader.app_request().unwrap(), SELECT);
+    let response: Vec<u8> = (0..30).collect();
+    reader.app_respond(&response);
+
+    // 16 bytes minus the PCB and CRC
+    assert_eq!(reader.poll(), [apdu(I_BLOCK | CHAINING | 1, &response[..13])]);
+    assert_eq!(reader.send(&[R_ACK]), [apdu(I_BLOCK | CHAINING, &response[13..26])]);
+    // retransmitted with chaining, as the response continues
+    assert_eq!(reader.send(&[R_NAK]), [apdu(I_BLOCK | CHAINING, &response[13..26])]);
+    assert_eq!(reader.send(&[R_ACK | 1]), [apdu(I_BLOCK | 1, &response[26..])]);
+    assert_eq!(reader.send(&[R_ACK | 1]), [apdu(I_BLOCK | 1, &response[26..])]);
+
+    reader.set_frame_size(256);
+}
+
+/// Rule D and the R(ACK) for each I-block with chaining from the reader.
+fn chained_request(reader: &mut Reader) {
+    let command: Vec<u8> = SELECT.iter().copied().chain([10].iter().copied()).chain(0..10).collect();
+
+    assert_eq!(reader.send(&apdu(I_BLOCK | CHAINING, &command[..4])), [[R_ACK]]);
+    assert_eq!(reader.app_request(), None);
+    assert_eq!(reader.send(&apdu(I_BLOCK | CHAINING | 1, &command[4..9])), [[R_ACK | 1]]);
+    assert_eq!(reader.send(&apdu(I_BLOCK, &command[9..])), NOTHING);
+
+    assert_eq!(reader.app_request().unwrap(), command);
+    reader.app_respond(&[0x90, 0x00]);
+    assert_eq!(reader.poll(), [[I_BLOCK, 0x90, 0x00]]);
+}
+
+/// Rule 9: S(WTX) while the app is busy, and the response only after the reader's S(WTX).
+fn wait_extension(reader: &mut Reader) {
+    assert_eq!(reader.send(&apdu(I_BLOCK | 1, &SELECT)), NOTHING);
+    assert_eq!(reader.wait_extension(), [[S_WTX, 0x01]]);
+    assert_eq!(reader.send(&[S_WTX, 0x01]), NOTHING);
+    assert_eq!(reader.app_request().unwrap(), SELECT);
+
+    assert_eq!(reader.wait_extension(), [[S_WTX, 0x01]]);
+    reader.app_respond(&[0x90, 0x00]);
+    assert_eq!(reader.send(&[S_WTX, 0x01]), [[I_BLOCK | 1, 0x90, 0x00]]);
+
+    // done
+    assert_eq!(reader.wait_extension(), NOTHING);
+}
+
+/// Malformed frames are ignored, and change nothing.
+fn malformed(reader: &mut Reader) {
+    let frames: [&[u8]; 7] = [
+        &[],
+        // CID indicated, but missing
+        &[I_BLOCK | 0x08],
+        &[R_NAK | 0x08],
+        // CID and NAD indicated, NAD missing
+        &[I_BLOCK | 0x0c, 0x00],
+        // no block type
+        &[0x00],
+        &[0xc6],
+        &[0xff, 0xff],
+    ];
+    for frame in frames.iter() {
+        assert_eq!(reader.send(frame), NOTHING, "{:02X?}", frame);
+    }
+    assert_eq!(reader.app_request(), None);
+
+    // still the same last block and block number
+    assert_eq!(reader.send(&[R_NAK | 1]), [[I_BLOCK | 1, 0x90, 0x00]]);
+}
+
+/// S(DESELECT) is answered, and resets the block number (rule C).
+fn deselect(reader: &mut Reader) {
+    assert_eq!(reader.send(&[S_DESELECT]), [[S_DESELECT]]);
+    assert_eq!(reader.send(&[R_NAK]), [[R_ACK | 1]]);
+    assert_eq!(reader.send(&[R_NAK | 1]), NOTHING);
+}
+
+#[test]
+fn block_rules() {
+    let mut reader = Reader::new();
+
+    activation(&mut reader);
+    retransmission(&mut reader);
+    chained_response(&mut reader);
+    chained_request(&mut reader);
+    wait_extension(&mut reader);
+    malformed(&mut reader);
+    deselect(&mut reader);
+
+    reader.check_recovers();
+}
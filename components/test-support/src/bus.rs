@@ -0,0 +1,85 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use usb_device::{
+    bus::{PollResult, UsbBus},
+    endpoint::{EndpointAddress, EndpointType},
+    Result, UsbDirection, UsbError,
+};
+
+#[derive(Default)]
+struct Endpoints {
+    next_index: [usize; 2],
+    outgoing: HashMap<u8, VecDeque<Vec<u8>>>,
+    incoming: HashMap<u8, Vec<u8>>,
+}
+
+/// An in-memory `UsbBus`: OUT packets are queued, IN endpoints hold at most one packet.
+///
+/// Clones share the endpoints, so a test keeps one to play the host
+/// while the class owns the bus through its allocator.
+#[derive(Clone, Default)]
+pub struct MockBus {
+    endpoints: Arc<Mutex<Endpoints>>,
+}
+
+impl MockBus {
+    /// Queues a packet from the host on an OUT endpoint.
+    pub fn host_write(&self, ep_addr: EndpointAddress, packet: &[u8]) {
+        self.endpoints.lock().unwrap().outgoing
+            .entry(ep_addr.into()).or_default()
+            .push_back(packet.to_vec());
+    }
+
+    /// Takes the packet on an IN endpoint, if there is one.
+    pub fn host_read(&self, ep_addr: EndpointAddress) -> Option<Vec<u8>> {
+        self.endpoints.lock().unwrap().incoming.remove(&u8::from(ep_addr))
+    }
+}
+
+impl UsbBus for MockBus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        _max_packet_size: u16,
+        _interval: u8,
+    ) -> Result<EndpointAddress> {
+        if let Some(ep_addr) = ep_addr {
+            return Ok(ep_addr);
+        }
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let next_index = &mut endpoints.next_index[(ep_dir == UsbDirection::In) as usize];
+        *next_index += 1;
+        Ok(EndpointAddress::from_parts(*next_index, ep_dir))
+    }
+
+    fn enable(&mut self) {}
+    fn reset(&self) {}
+    fn set_device_address(&self, _addr: u8) {}
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        if endpoints.incoming.contains_key(&u8::from(ep_addr)) {
+            return Err(UsbError::WouldBlock);
+        }
+        endpoints.incoming.insert(ep_addr.into(), buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let packet = endpoints.outgoing.get_mut(&u8::from(ep_addr))
+            .and_then(|queue| queue.pop_front())
+            .ok_or(UsbError::WouldBlock)?;
+        buf[..packet.len()].copy_from_slice(&packet);
+        Ok(packet.len())
+    }
+
+    fn set_stalled(&self, _ep_addr: EndpointAddress, _stalled: bool) {}
+    fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool { false }
+    fn suspend(&self) {}
+    fn resume(&self) {}
+    fn poll(&self) -> PollResult { PollResult::None }
+}
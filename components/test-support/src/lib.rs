@@ -0,0 +1,7 @@
+//! Test doubles shared by the tests and fuzz targets of the components.
+
+mod bus;
+pub use bus::MockBus;
+
+mod random;
+pub use random::Random;
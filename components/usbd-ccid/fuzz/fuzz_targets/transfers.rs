@@ -0,0 +1,30 @@
+//! Feeds arbitrary bulk OUT transfers to the CCID class, see `Device::run_steps`.
+//!
+//! The interchange can only be claimed once, so all inputs share one device.
+//! Each input ends by checking the device recovers, which also resets it for the next.
+//!
+//! Run with `cargo fuzz run transfers` in `components/usbd-ccid`.
+#![no_main]
+
+use std::cell::RefCell;
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+use common::{Device, Message};
+
+interchange::interchange! { Transfers: (Message, Message) }
+
+thread_local! {
+    static DEVICE: RefCell<Device<Transfers>> = RefCell::new(Device::new().implements_escape());
+}
+
+fuzz_target!(|data: &[u8]| {
+    DEVICE.with(|device| {
+        let mut device = device.borrow_mut();
+        device.run_steps(data);
+        device.check_recovers();
+    });
+});
@@ -6,6 +6,7 @@ use interchange::{Interchange, Requester};
 
 use crate::{
     constants::*,
+    escape::EscapeHandler,
     types::{
         ClassRequest,
         packet::RawPacket,
@@ -54,6 +55,26 @@ where
         Self { interface_number, string_index, read, /* interrupt, */ pipe }
     }
 
+    /// Accept PC_to_RDR_Escape, which must then be answered via `handle_escape`.
+    ///
+    /// Otherwise, Escape commands are not supported.
+    pub fn implements_escape(mut self) -> Self {
+        self.pipe.escape_enabled = true;
+        self
+    }
+
+    /// Answer a pending Escape command (if any) using `handler`.
+    pub fn handle_escape<H: EscapeHandler + ?Sized>(&mut self, handler: &mut H) {
+        self.pipe.handle_escape(handler);
+    }
+
+    /// The dwMaxCCIDMessageLength we announce: an XfrBlock can carry a request
+    /// of the interchange's full size, e.g. an extended length APDU, and a
+    /// response of the same size comes back in a single DataBlock.
+    pub fn max_msg_length(&self) -> usize {
+        self.pipe.max_msg_length()
+    }
+
     /// Read response from application (if any) and start writing it to
     /// the USB bus.  Should be called before managing Bus.
     pub fn check_for_app_response(&mut self) {
@@ -96,9 +117,12 @@ where
             TransferMode::Bulk as u8,
             Some(self.string_index),
         )?;
+        let mut descriptor = FUNCTIONAL_INTERFACE_DESCRIPTOR;
+        descriptor[MAX_MSG_LENGTH_OFFSET..][..4]
+            .copy_from_slice(&(self.max_msg_length() as u32).to_le_bytes());
         writer.write(
             FUNCTIONAL_INTERFACE,
-            &FUNCTIONAL_INTERFACE_DESCRIPTOR,
+            &descriptor,
         )?;
         writer.endpoint(&self.pipe.write).unwrap();
         writer.endpoint(&self.read).unwrap();
@@ -157,7 +181,10 @@ where
                         ClassRequest::GetDataRates => {
                             transfer.accept_with_static(&DATA_RATE_BPS).ok();
                         },
-                        _ => panic!("unexpected direction for {:?}", &request),
+                        _ => {
+                            info_now!("unexpected direction for {:?}", &request);
+                            transfer.reject().ok();
+                        }
                     }
                 }
 
@@ -191,12 +218,16 @@ where
                             // transfer.reject().ok();
                             // todo!();
                         }
-                        _ => panic!("unexpected direction for {:?}", &request),
+                        _ => {
+                            info_now!("unexpected direction for {:?}", &request);
+                            transfer.reject().ok();
+                        }
                     }
                 }
 
                 Err(()) => {
                     info_now!("unexpected request: {}", request);
+                    transfer.reject().ok();
                 }
             }
         }
@@ -44,11 +44,24 @@ pub const DATA_RATE_BPS: [u8; 4] = [0x80, 0x25, 0x00, 0x00];
 // 254 (as per ICCD spec)
 pub const MAX_IFSD: [u8; 4] = [0xfe, 0x00, 0x00, 0x00];
 
-// "The value shall be between 261 + 10 and 65544 + 10
-// dwMaxCCIDMsgLen 3072
+// Messages other than XfrBlock are reassembled in a buffer of this size,
+// XfrBlock data goes straight into the request (cf. `Ccid::max_msg_length`).
 pub type MAX_MSG_LENGTH_TYPE = <consts::U2048 as core::ops::Add<consts::U1024>>::Output;
 pub const MAX_MSG_LENGTH: usize = MAX_MSG_LENGTH_TYPE::USIZE;
-pub const MAX_MSG_LENGTH_LE: [u8; 4] = [0x00, 0x0C, 0x00, 0x00];
+
+// dwMaxCCIDMessageLength: "The value shall be between 261 + 10 and 65544 + 10",
+// the upper bound being an extended APDU with Lc = 65535 and Le = 65536.
+pub const MAX_DATA_LENGTH: usize = 65544;
+// offset of dwMaxCCIDMessageLength in FUNCTIONAL_INTERFACE_DESCRIPTOR
+pub const MAX_MSG_LENGTH_OFFSET: usize = 42;
+
+// T=0, T=1, command chaining/extended Lc+Le/no logical channels, card issuer's data "Solo B"
+// The historical bytes are category 0x80, followed by compact TLVs:
+// - 73 C0 21 C0: card capabilities, the third byte announcing command chaining (0x80)
+//   and extended Lc and Le fields (0x40)
+// - 56 53 6F 6C 6F 20 42: card issuer's data
+// https://smartcard-atr.apdu.fr/parse?ATR=3B+8C+80+01+80+73+C0+21+C0+56+53+6F+6C+6F+20+42+D4
+pub const ATR: [u8; 17] = [0x3B, 0x8C, 0x80, 0x01, 0x80, 0x73, 0xC0, 0x21, 0xC0, 0x56, 0x53, 0x6F, 0x6C, 0x6F, 0x20, 0x42, 0xD4];
 
 pub const NUM_SLOTS: u8 = 1;
 pub const MAX_BUSY_SLOTS: u8 = 1;
@@ -117,12 +130,9 @@ pub const FUNCTIONAL_INTERFACE_DESCRIPTOR: [u8; 52] = [
     // upper word: 0000 = char level, 0002 = short APDU, 0004 = short+exteded APDU
     0x40, 0x08, 0x04, 0x00,
 
-    // dwMaxCCIDMsgLen (3072)
+    // dwMaxCCIDMsgLen, filled in by `Ccid` from the size of its requests
     // gnuk: 271
-    MAX_MSG_LENGTH_LE[0],
-    MAX_MSG_LENGTH_LE[1],
-    MAX_MSG_LENGTH_LE[2],
-    MAX_MSG_LENGTH_LE[3],
+    0x00, 0x00, 0x00, 0x00,
 
     // bClassGetResponse ("echo"), as per ICCD spec
     0xFF,
@@ -0,0 +1,31 @@
+//! Vendor commands over PC_to_RDR_Escape, cf. CCID_Rev110 6.1.8.
+//!
+//! Readers use Escape for management functions that need no card session,
+//! so the payload is handled synchronously, without going through an app.
+
+use heapless_bytes::consts;
+
+use crate::constants::PACKET_SIZE_TYPE;
+
+/// The abData of RDR_to_PC_Escape, which must fit a single packet.
+pub type EscapeResponse = heapless_bytes::Bytes<
+    <PACKET_SIZE_TYPE as core::ops::Sub<consts::U10>>::Output
+>;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EscapeError {
+    /// The escape command is not known.
+    NotSupported,
+    /// The abData is malformed.
+    InvalidData,
+    /// The handler failed.
+    Failed,
+}
+
+/// Handles the abData of PC_to_RDR_Escape.
+///
+/// Called from `Ccid::handle_escape`, typically with the USB classes locked,
+/// so handlers must not block, e.g. on Trussed syscalls.
+pub trait EscapeHandler {
+    fn escape(&mut self, request: &[u8], response: &mut EscapeResponse) -> Result<(), EscapeError>;
+}
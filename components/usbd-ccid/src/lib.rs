@@ -9,9 +9,11 @@ generate_macros!();
 
 pub mod constants;
 pub mod class;
+pub mod escape;
 pub mod pipe;
 pub mod types;
 
 // pub mod piv;
 
 pub use class::Ccid;
+pub use escape::{EscapeError, EscapeHandler, EscapeResponse};
@@ -1,17 +1,24 @@
 use core::convert::TryFrom;
 
-use heapless_bytes::Bytes;
+use heapless_bytes::{Bytes, Unsigned as _};
 use interchange::{Interchange, Requester};
 
 use crate::{
     constants::*,
+    escape::{EscapeError, EscapeHandler, EscapeResponse},
     types::packet::{
         Chain,
+        ClockCommand,
         Command as PacketCommand,
+        CommandType,
         DataBlock,
         Error as PacketError,
         ExtPacket,
+        IccClock,
+        ProtocolDataT1,
         RawPacket,
+        ResponseType,
+        SetParameters,
         XfrBlock,
 
         ChainedPacket as _,
@@ -28,17 +35,67 @@ pub enum State {
     Processing,
     ReadyToSend,
     Sending,
+    // waiting for `handle_escape`
+    Escaping,
 }
 
+/// What happens to the remaining USB packets of a CCID message.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Reception {
+    /// The message is complete.
+    Complete,
+    /// Reassembled in `ext_packet`.
+    Buffering,
+    /// XfrBlock data, appended to the request as it arrives.
+    Streaming,
+    /// The command has been answered with an error already.
+    Discarding,
+}
+
+/// bError of a failed command, cf. CCID_Rev110 Table 6.2-2
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[allow(dead_code)]
 enum Error {
-    CmdAborted = 0xff,
-    IccMute = 0xfe,
-    XfrParityError = 0xfd,
+    CmdAborted,
+    IccMute,
+    XfrParityError,
+    XfrOverrun,
+    HwError,
     //..
-    CmdSlotBusy = 0xE0,
-    CommandNotSupported = 0x00,
+    CmdSlotBusy,
+    CommandNotSupported,
+    // otherwise, the offset of the bad field in the message
+    BadLength,
+    BadSlot,
+    BadLevelParameter,
+    BadField(u8),
+}
+
+impl Error {
+    fn code(self) -> u8 {
+        match self {
+            Error::CmdAborted => 0xff,
+            Error::IccMute => 0xfe,
+            Error::XfrParityError => 0xfd,
+            Error::XfrOverrun => 0xfc,
+            Error::HwError => 0xfb,
+            Error::CmdSlotBusy => 0xe0,
+            Error::CommandNotSupported => 0x00,
+            Error::BadLength => 1,
+            Error::BadSlot => 5,
+            Error::BadLevelParameter => 8,
+            Error::BadField(offset) => offset,
+        }
+    }
+}
+
+/// bClockStatus in RDR_to_PC_SlotStatus
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ClockStatus {
+    Running = 0,
+    StoppedLow = 1,
+    StoppedHigh = 2,
+    StoppedUnknown = 3,
 }
 
 pub struct Pipe<Bus, I, N>
@@ -53,15 +110,25 @@ where
     state: State,
     interchange: Requester<I>,
     sent: usize,
+    // bytes of the DataBlock in flight that still need to be put into packets
+    block_missing: usize,
     outbox: Option<RawPacket>,
 
     ext_packet: ExtPacket,
     #[allow(dead_code)]
     packet_len: usize,
-    receiving_long: bool,
+    reception: Reception,
     long_packet_missing: usize,
     in_chain: usize,
     pub(crate) started_processing: bool,
+    // CCID 5.3.1: an abort consists of a control request and a bulk
+    // PC_to_RDR_Abort with the same bSeq, which may arrive in either order.
+    abort_requested: Option<u8>,
+    abort_received: Option<u8>,
+    // T=1 parameters as set by the host; as we exchange APDUs, they have no effect
+    parameters: ProtocolDataT1,
+    clock_status: ClockStatus,
+    pub(crate) escape_enabled: bool,
 }
 
 impl<Bus, I, N> Pipe<Bus, I, N>
@@ -82,15 +149,21 @@ where
             seq: 0,
             state: State::Idle,
             sent: 0,
+            block_missing: 0,
             outbox: None,
             interchange: request_pipe,
 
             ext_packet: Default::default(),
             packet_len: 0,
-            receiving_long: false,
+            reception: Reception::Complete,
             long_packet_missing: 0,
             in_chain: 0,
             started_processing: false,
+            abort_requested: None,
+            abort_received: None,
+            parameters: Default::default(),
+            clock_status: ClockStatus::Running,
+            escape_enabled: false,
         }
     }
 
@@ -111,57 +184,161 @@ where
     pub fn handle_packet(&mut self, packet: RawPacket) {
         use crate::types::packet::RawPacketExt;
 
-        // SHOULD CLEAN THIS UP!
-        // The situation is as follows: full 64B USB packet received.
-        // CCID packet signals no command chaining, but data length > 64 - 10.
-        // Then we can expect to receive more USB packets containing only data.
-        // The concatenation of all these is then a valid Command APDU.
-        // (which itself may have command chaining on a higher level, e.g.
-        // when certificates are transmitted, because PIV somehow uses short APDUs
-        // only (can we fix this), so 255B is the maximum)
-        if !self.receiving_long {
-            if packet.len() < 10 {
-                panic!("unexpected short packet");
+        // A CCID message longer than a USB packet continues in the following
+        // packets, ending with a short one. XfrBlock data is appended to the
+        // request as it arrives, so APDUs are only limited by the request size,
+        // (extended length APDUs included); other commands are reassembled in
+        // `ext_packet`.
+        if packet.is_empty() {
+            // stray zero-length packet
+            return;
+        }
+        if self.reception != Reception::Complete {
+            self.continue_message(&packet);
+            return;
+        }
+
+        if packet.len() < 10 {
+            info!("short packet of {} bytes", packet.len());
+            self.reject_message(packet.get(6).copied().unwrap_or(0), Error::BadLength);
+            return;
+        }
+        let pl = packet.packet_len();
+
+        // the pending Escape command is still in `ext_packet`, keep it,
+        // unless the host aborts it
+        let abort = packet[0] == CommandType::Abort as u8 && pl == 0;
+        if self.state == State::Escaping && !abort {
+            info!("escaping, rejecting command");
+            self.reject_message(packet[6], Error::CmdSlotBusy);
+            // the rest of the message follows, unless it is too long anyway
+            if pl <= self.max_msg_length() - 10 && pl + 10 > packet.len() && packet.len() == PACKET_SIZE {
+                self.long_packet_missing = pl + 10 - packet.len();
+                self.reception = Reception::Discarding;
             }
-            self.ext_packet.clear();
-            // TODO check
-            self.ext_packet.extend_from_slice(&packet).unwrap();
-
-            let pl = packet.packet_len();
-            if pl > 54 {
-                self.receiving_long = true;
-                self.in_chain = 1;
-                self.long_packet_missing = pl - 54;
-                self.packet_len = pl;
+            return;
+        }
+
+        self.ext_packet.clear();
+        // cannot fail, as MAX_MSG_LENGTH >= PACKET_SIZE
+        self.ext_packet.extend_from_slice(&packet).ok();
+
+        let streamed = packet[0] == CommandType::XfrBlock as u8;
+        let max_msg_length = if streamed { self.max_msg_length() } else { MAX_MSG_LENGTH };
+        if pl > max_msg_length - 10 {
+            info!("packet length {} exceeds dwMaxCCIDMessageLength", pl);
+            self.reject_message(packet[6], Error::BadLength);
+            return;
+        }
+        if pl + 10 > packet.len() {
+            // a short USB packet ends the transfer
+            if packet.len() < PACKET_SIZE {
+                info!("packet length {} exceeds transfer", pl);
+                self.reject_message(packet[6], Error::BadLength);
                 return;
-            } else {
-                // normal case
             }
-        } else {
-            // TODO check
-            self.ext_packet.extend_from_slice(&packet).ok();
-            self.in_chain += 1;
-            assert!(packet.len() <= self.long_packet_missing);
-            self.long_packet_missing -= packet.len();
-            if self.long_packet_missing > 0 {
-                return;
+            self.in_chain = 1;
+            self.long_packet_missing = pl + 10 - packet.len();
+            self.packet_len = pl;
+            if streamed {
+                // the first packet's data goes into the request right away
+                self.reception = Reception::Streaming;
             } else {
-                // info!("pl {}, p {}, missing {}, in_chain {}", self.packet_len, packet.len(), self.long_packet_missing, self.in_chain).ok();
-                // info!("packet: {:X?}", &self.ext_packet).ok();
-                self.receiving_long = false;
+                self.reception = Reception::Buffering;
+                return;
+            }
+        }
+
+        self.handle_command();
+    }
+
+    /// Handles a USB packet that continues a CCID message.
+    fn continue_message(&mut self, packet: &[u8]) {
+        self.in_chain += 1;
+        let ends_early = packet.len() < PACKET_SIZE && packet.len() < self.long_packet_missing;
+        if packet.len() > self.long_packet_missing || ends_early {
+            info!("got {} bytes, expected {}", packet.len(), self.long_packet_missing);
+            match core::mem::replace(&mut self.reception, Reception::Complete) {
+                Reception::Streaming => self.fail_transaction(Error::BadLength),
+                Reception::Buffering => {
+                    self.reject_message(self.ext_packet[6], Error::BadLength);
+                }
+                // already answered
+                _ => {}
             }
+            return;
+        }
+
+        self.long_packet_missing -= packet.len();
+        match self.reception {
+            // cannot fail, as the packet length is at most MAX_MSG_LENGTH - 10
+            Reception::Buffering => { self.ext_packet.extend_from_slice(packet).ok(); }
+            // on failure, the rest is discarded
+            Reception::Streaming => { self.append_request(packet); }
+            _ => {}
+        }
+        if self.long_packet_missing > 0 {
+            return;
+        }
+
+        match core::mem::replace(&mut self.reception, Reception::Complete) {
+            Reception::Buffering => self.handle_command(),
+            Reception::Streaming => self.complete_transfer(),
+            _ => {}
         }
+    }
 
-        // info!("{:X?}", &packet).ok();
-        // let p = packet.clone();
-        // match PacketCommand::try_from(packet) {
+    /// Handles the command in `ext_packet`.
+    ///
+    /// For a streamed XfrBlock, this is called with its first packet.
+    fn handle_command(&mut self) {
         match PacketCommand::try_from(self.ext_packet.clone()) {
             Ok(command) => {
+                // we have only one slot
+                if command[5] != 0 {
+                    info!("command for unknown slot {}", command[5]);
+                    self.seq = command.seq();
+                    self.send_slot_status_error(Error::BadSlot);
+                    self.discard_rest();
+                    return;
+                }
+
+                if let PacketCommand::Abort(_) = command {
+                    self.handle_abort(command.seq());
+                    return;
+                }
+
+                // We have only one busy slot. Keep the sequence number of the
+                // ongoing transaction, its response still needs it.
+                let busy = match (self.state, &command) {
+                    (State::Processing, _) | (State::ReadyToSend, _) | (State::Escaping, _) => true,
+                    (State::Sending, PacketCommand::XfrBlock(_)) => false,
+                    (State::Sending, _) => true,
+                    _ => false,
+                };
+                if busy {
+                    info!("slot busy in state {:?}, rejecting {:?}", self.state, &command);
+                    self.reject_message(command.seq(), Error::CmdSlotBusy);
+                    self.discard_rest();
+                    return;
+                }
+
                 self.seq = command.seq();
 
+                // until the abort completes, all other commands fail
+                if self.abort_requested.is_some() {
+                    info!("aborting, rejecting {:?}", &command);
+                    self.send_slot_status_error(Error::CmdAborted);
+                    self.discard_rest();
+                    return;
+                }
+
                 // happy path
                 match command {
-                    PacketCommand::PowerOn(_command) => self.send_atr(),
+                    PacketCommand::PowerOn(_command) => {
+                        self.clock_status = ClockStatus::Running;
+                        self.send_atr();
+                    }
 
                     PacketCommand::PowerOff(_command) => self.send_slot_status_ok(),
 
@@ -169,21 +346,56 @@ where
 
                     PacketCommand::XfrBlock(command) => self.handle_transfer(command),
 
-                    PacketCommand::Abort(_command) => {
-                        todo!();
+                    // usually handled above, whatever the state
+                    PacketCommand::Abort(command) => {
+                        self.handle_abort(command.seq());
+                        return;
                     }
+
                     PacketCommand::GetParameters(_command) => self.send_parameters(),
+
+                    PacketCommand::ResetParameters(_command) => {
+                        self.parameters = Default::default();
+                        self.send_parameters();
+                    }
+
+                    PacketCommand::SetParameters(command) => self.set_parameters(command),
+
+                    PacketCommand::IccClock(command) => self.icc_clock(command),
+
+                    PacketCommand::SetDataRateAndClockFrequency(_command) => {
+                        // bNumClockSupported = bNumDataRatesSupported = 0,
+                        // so the values in use are the only ones
+                        self.send_data_rate_and_clock_frequency();
+                    }
+
+                    // the request stays in ext_packet until handled
+                    PacketCommand::Escape(_command) if self.escape_enabled => {
+                        self.state = State::Escaping;
+                    }
+                    PacketCommand::Escape(_command) => {
+                        self.send_error(ResponseType::Escape, Error::CommandNotSupported);
+                    }
+
+                    // we exchange APDUs, have no PIN pad, and no mechanics
+                    PacketCommand::Secure(_command) => {
+                        self.send_error(ResponseType::DataBlock, Error::CommandNotSupported);
+                    }
+                    PacketCommand::T0Apdu(_) |
+                    PacketCommand::Mechanical(_) => {
+                        self.send_slot_status_error(Error::CommandNotSupported);
+                    }
                 }
             }
 
+            // not reachable, as we checked the length above
             Err(PacketError::ShortPacket) => {
-                panic!("short packet!");
+                self.send_slot_status_error(Error::BadLength);
             }
 
             Err(PacketError::UnknownCommand(_p)) => {
                 info!("unknown command {:X?}", &_p);
-                self.seq = self.ext_packet[6];
-                self.send_slot_status_error(Error::CommandNotSupported);
+                self.reject_message(self.ext_packet[6], Error::CommandNotSupported);
             }
         }
     }
@@ -208,76 +420,136 @@ where
 
         // info!("handle xfrblock").ok();
         // info!("{:X?}", &command);
+
+        // if the data continues in further USB packets, we stay in Receiving
+        // until `complete_transfer`
+        let complete = self.reception == Reception::Complete;
+
         match self.state {
 
             State::Idle => {
                 // invariant: BUFFER_SIZE >= PACKET_SIZE
                 match command.chain() {
-                    Chain::BeginsAndEnds => {
+                    Some(Chain::BeginsAndEnds) => {
                         info!("begins and ends");
-                        self.reset_interchange();
-                        let message = self.interchange.request_mut().unwrap();
-                        message.clear();
-                        message.extend_from_slice(command.data()).unwrap();
-                        self.call_app();
-                        self.state = State::Processing;
+                        if self.start_request(command.data()) {
+                            if complete {
+                                self.call_app();
+                            } else {
+                                self.state = State::Receiving;
+                            }
+                        }
                         // self.send_empty_datablock();
                     }
-                    Chain::Begins => {
+                    Some(Chain::Begins) => {
                         info!("begins");
-                        self.reset_interchange();
-                        let message = self.interchange.request_mut().unwrap();
-                        message.clear();
-                        message.extend_from_slice(command.data()).unwrap();
-                        self.state = State::Receiving;
-                        self.send_empty_datablock(Chain::ExpectingMore);
+                        if self.start_request(command.data()) {
+                            self.state = State::Receiving;
+                            if complete {
+                                self.send_empty_datablock(Chain::ExpectingMore);
+                            }
+                        }
+                    }
+                    _ => {
+                        info!("unexpectedly in idle state: {:?}", command.chain());
+                        self.fail_transaction(Error::BadLevelParameter);
                     }
-                    _ =>  panic!("unexpectedly in idle state"),
                 }
             }
 
             State::Receiving => {
                 match command.chain() {
-                    Chain::Continues => {
+                    Some(Chain::Continues) => {
                         info!("continues");
-                        let message = self.interchange.request_mut().unwrap();
-                        assert!(command.data().len() + message.len() <= MAX_MSG_LENGTH);
-                        message.extend_from_slice(command.data()).unwrap();
-                        self.send_empty_datablock(Chain::ExpectingMore);
+                        if self.append_request(command.data()) && complete {
+                            self.send_empty_datablock(Chain::ExpectingMore);
+                        }
                     }
-                    Chain::Ends => {
+                    Some(Chain::Ends) => {
                         info!("ends");
-                        let message = self.interchange.request_mut().unwrap();
-                        assert!(command.data().len() + message.len() <= MAX_MSG_LENGTH);
-                        message.extend_from_slice(command.data()).unwrap();
-                        self.call_app();
-                        self.state = State::Processing;
+                        if self.append_request(command.data()) && complete {
+                            self.call_app();
+                        }
+                    }
+                    _ => {
+                        info!("unexpectedly in receiving state: {:?}", command.chain());
+                        self.fail_transaction(Error::BadLevelParameter);
                     }
-                    _ =>  panic!("unexpectedly in receiving state"),
                 }
             }
 
-            State::Processing => {
+            // rejected in handle_packet
+            State::Processing | State::ReadyToSend | State::Escaping => {
                 // info!("handle xfrblock").ok();
                 // info!("{:X?}", &command).ok();
-                panic!("ccid pipe unexpectedly received command while in processing state: {:?}", &command);
-            }
-
-            State::ReadyToSend => {
-                panic!("unexpectedly in ready-to-send state")
+                self.send_slot_status_error(Error::CmdSlotBusy);
+                self.discard_rest();
             }
 
             State::Sending => {
                 match command.chain() {
-                    Chain::ExpectingMore => {
+                    // the previous block is still on its way
+                    Some(Chain::ExpectingMore) if self.outbox.is_some() || self.block_missing > 0 => {
+                        self.fail_transaction(Error::CmdSlotBusy);
+                    }
+                    Some(Chain::ExpectingMore) => {
                         self.prime_outbox();
                     }
-                    _ =>  panic!("unexpectedly in receiving state"),
+                    _ => {
+                        info!("unexpectedly in sending state: {:?}", command.chain());
+                        self.fail_transaction(Error::BadLevelParameter);
+                    }
                 }
             }
         }
     }
 
+    /// Answers an XfrBlock whose data spanned several USB packets, once all of it arrived.
+    fn complete_transfer(&mut self) {
+        if self.state != State::Receiving {
+            return;
+        }
+        // wLevelParameter, validated in `handle_transfer`
+        let level_parameter = u16::from_le_bytes([self.ext_packet[8], self.ext_packet[9]]);
+        if level_parameter == Chain::BeginsAndEnds as u16 || level_parameter == Chain::Ends as u16 {
+            self.call_app();
+        } else {
+            self.send_empty_datablock(Chain::ExpectingMore);
+        }
+    }
+
+    /// Once a streamed XfrBlock failed, the rest of its data is dropped.
+    fn discard_rest(&mut self) {
+        if self.reception == Reception::Streaming {
+            self.reception = Reception::Discarding;
+        }
+    }
+
+    /// Starts a new request with `data`, see `append_request`.
+    fn start_request(&mut self, data: &[u8]) -> bool {
+        self.reset_interchange();
+        if let Ok(message) = self.interchange.request_mut() {
+            message.clear();
+        }
+        self.append_request(data)
+    }
+
+    /// Appends `data` to the request, failing the transaction if it does not fit.
+    fn append_request(&mut self, data: &[u8]) -> bool {
+        let result = match self.interchange.request_mut() {
+            Ok(message) => message.extend_from_slice(data).map_err(|_| Error::BadLength),
+            Err(_) => Err(Error::HwError),
+        };
+        match result {
+            Ok(()) => true,
+            Err(error) => {
+                info!("could not extend request: {:?}", error);
+                self.fail_transaction(error);
+                false
+            }
+        }
+    }
+
     pub fn send_wait_extension(&mut self) -> bool {
         if self.state == State::Processing {
             // Need to send a wait extension request.
@@ -312,7 +584,11 @@ where
 
     #[inline(never)]
     fn call_app(&mut self) {
-        self.interchange.send_request().expect("could not deposit command");
+        if self.interchange.send_request().is_err() {
+            info!("could not deposit command");
+            self.fail_transaction(Error::HwError);
+            return;
+        }
         self.started_processing = true;
         self.state = State::Processing;
     }
@@ -322,11 +598,13 @@ where
             // info!("processing, checking for response, interchange state {:?}",
             //           self.interchange.state()).ok();
 
-            if interchange::State::Responded == self.interchange.state() {
+            // a wait extension may still be on its way
+            if interchange::State::Responded == self.interchange.state() && self.outbox.is_none() {
 
                 // we should have an open XfrBlock allowance
                 self.state = State::ReadyToSend;
                 self.sent = 0;
+                self.block_missing = 0;
                 self.prime_outbox();
             }
         }
@@ -337,34 +615,69 @@ where
             return;
         }
 
-        if self.outbox.is_some() { panic!(); }
+        if self.outbox.is_some() {
+            info!("outbox still full, not priming");
+            return;
+        }
 
         // if let Some(message) = self.interchange.response() {
             let message: &mut Bytes<N> = unsafe { self.interchange.interchange.rp_mut() };
 
-            let chunk_size = core::cmp::min(PACKET_SIZE - 10, message.len() - self.sent);
-            let chunk = &message[self.sent..][..chunk_size];
-            self.sent += chunk_size;
-            let more = self.sent < message.len();
+            // the DataBlock in flight continues
+            if self.block_missing > 0 {
+                let size = core::cmp::min(PACKET_SIZE, self.block_missing);
+                let mut packet = RawPacket::new();
+                // cannot fail, as size <= PACKET_SIZE
+                packet.extend_from_slice(&message[self.sent..][..size]).ok();
+                self.sent += size;
+                self.block_missing -= size;
+                self.finish_block(message.len());
+
+                self.outbox = Some(packet);
+                self.maybe_send_packet();
+                return;
+            }
+
+            // Each DataBlock carries as much of the response as dwMaxCCIDMessageLength
+            // allows, spread over several USB packets. Only longer responses are chained.
+            let block_size = core::cmp::min(self.max_msg_length() - 10, message.len() - self.sent);
+            let more = self.sent + block_size < message.len();
 
             let chain = match (self.state, more) {
-                (State::ReadyToSend, true) => { self.state = State::Sending; Chain::Begins }
-                (State::ReadyToSend, false) => { self.state = State::Idle; Chain::BeginsAndEnds }
+                (State::ReadyToSend, true) => Chain::Begins,
+                (State::ReadyToSend, false) => Chain::BeginsAndEnds,
                 (State::Sending, true) => Chain::Continues,
-                (State::Sending, false) => { self.state = State::Idle; Chain::Ends }
+                (State::Sending, false) => Chain::Ends,
                 // logically impossible
                 _ => { return; }
             };
+            self.state = State::Sending;
+
+            let chunk_size = core::cmp::min(PACKET_SIZE - 10, block_size);
+            let chunk = &message[self.sent..][..chunk_size];
+            let mut primed_packet: RawPacket = DataBlock::new(self.seq, chain, chunk).into();
+            // dwLength covers the whole block
+            primed_packet[1..5].copy_from_slice(&(block_size as u32).to_le_bytes());
+            self.sent += chunk_size;
+            self.block_missing = block_size - chunk_size;
+            self.finish_block(message.len());
 
-            let primed_packet = DataBlock::new(self.seq, chain, chunk);
             // info!("priming {:?}", &primed_packet).ok();
-            self.outbox = Some(primed_packet.into());
+            self.outbox = Some(primed_packet);
 
             // fast-lane response attempt
             self.maybe_send_packet();
         // }
     }
 
+    /// Once the last packet of a DataBlock is primed, either the response is complete,
+    /// or the host asks for the next block with an XfrBlock.
+    fn finish_block(&mut self, response_len: usize) {
+        if self.block_missing == 0 && self.sent == response_len {
+            self.state = State::Idle;
+        }
+    }
+
     fn send_empty_datablock(&mut self, chain: Chain) {
         let packet = DataBlock::new(self.seq, chain, &[]).into();
         self.send_packet_assuming_possible(packet);
@@ -373,39 +686,147 @@ where
     fn send_slot_status_ok(&mut self) {
         let mut packet = RawPacket::new();
         packet.resize_default(10).ok();
-        packet[0] = 0x81;
+        packet[0] = ResponseType::SlotStatus as u8;
         packet[6] = self.seq;
+        packet[9] = self.clock_status as u8;
         self.send_packet_assuming_possible(packet);
     }
 
     fn send_slot_status_error(&mut self, error: Error) {
+        self.send_error(ResponseType::SlotStatus, error);
+    }
+
+    /// Fails a message outside of the ongoing transaction, which keeps its sequence number.
+    fn reject_message(&mut self, seq: u8, error: Error) {
+        let ongoing_seq = core::mem::replace(&mut self.seq, seq);
+        self.send_slot_status_error(error);
+        self.seq = ongoing_seq;
+    }
+
+    /// Fails the current command with a response of the type the command expects.
+    fn send_error(&mut self, response_type: ResponseType, error: Error) {
         let mut packet = RawPacket::new();
         packet.resize_default(10).ok();
-        packet[0] = 0x6c;
+        packet[0] = response_type as u8;
         packet[6] = self.seq;
+        // bmCommandStatus: failed, bmICCStatus: active (or no ICC, for other slots)
         packet[7] = 1<<6;
-        packet[8] = error as u8;
+        if error == Error::BadSlot {
+            packet[7] |= 2;
+        }
+        packet[8] = error.code();
         self.send_packet_assuming_possible(packet);
     }
 
     fn send_parameters(&mut self) {
         let mut packet = RawPacket::new();
-        packet.resize_default(17).ok();
-        packet[0] = 0x82;
-        packet[1] = 7;
+        packet.resize_default(10 + ProtocolDataT1::LENGTH).ok();
+        packet[0] = ResponseType::Parameters as u8;
+        packet[1] = ProtocolDataT1::LENGTH as u8;
         packet[6] = self.seq;
         packet[9] = 1; // T=1
+        packet[10..].copy_from_slice(&self.parameters.to_bytes());
+        self.send_packet_assuming_possible(packet);
+    }
+
+    fn set_parameters(&mut self, command: SetParameters) {
+        // we only advertise T=1
+        if command.protocol() != 1 {
+            info!("SetParameters for T={}", command.protocol());
+            self.send_error(ResponseType::Parameters, Error::BadField(7));
+            return;
+        }
+        match ProtocolDataT1::try_from_bytes(command.data()) {
+            Ok(parameters) => {
+                self.parameters = parameters;
+                self.send_parameters();
+            }
+            Err(_) if command.data().len() != ProtocolDataT1::LENGTH => {
+                self.send_error(ResponseType::Parameters, Error::BadLength);
+            }
+            Err(offset) => {
+                info!("invalid T=1 parameter at {}", offset);
+                self.send_error(ResponseType::Parameters, Error::BadField(10 + offset as u8));
+            }
+        }
+    }
+
+    fn icc_clock(&mut self, command: IccClock) {
+        match command.clock_command() {
+            Some(ClockCommand::Restart) => {
+                self.clock_status = ClockStatus::Running;
+                self.send_slot_status_ok();
+            }
+            Some(ClockCommand::Stop) => {
+                self.clock_status = match self.parameters.clock_stop {
+                    1 => ClockStatus::StoppedLow,
+                    2 => ClockStatus::StoppedHigh,
+                    3 => ClockStatus::StoppedUnknown,
+                    // stopping the clock is not allowed
+                    _ => {
+                        self.send_slot_status_error(Error::BadField(7));
+                        return;
+                    }
+                };
+                self.send_slot_status_ok();
+            }
+            None => self.send_slot_status_error(Error::BadField(7)),
+        }
+    }
+
+    /// dwMaxCCIDMessageLength: an XfrBlock can carry a complete request,
+    /// which may be an extended length APDU.
+    pub fn max_msg_length(&self) -> usize {
+        core::cmp::min(N::USIZE, MAX_DATA_LENGTH) + 10
+    }
 
-        // just picking the fastest values.
-        //              Fi = 1Mz    Di=1
-        packet[10] = (0b0001 << 4) | (0b0001);
+    /// Answers a pending PC_to_RDR_Escape.
+    pub fn handle_escape<H: EscapeHandler + ?Sized>(&mut self, handler: &mut H) {
+        if self.state != State::Escaping {
+            return;
+        }
+
+        let declared_len = u32::from_le_bytes([
+            self.ext_packet[1], self.ext_packet[2], self.ext_packet[3], self.ext_packet[4],
+        ]) as usize;
+        let len = core::cmp::min(declared_len, self.ext_packet.len() - 10);
+        let request = &self.ext_packet[10..][..len];
+
+        let mut response = EscapeResponse::new();
+        let result = handler.escape(request, &mut response);
+        self.state = State::Idle;
+
+        match result {
+            Ok(()) => {
+                let mut packet = RawPacket::new();
+                packet.resize_default(10 + response.len()).ok();
+                packet[0] = ResponseType::Escape as u8;
+                packet[1..5].copy_from_slice(&(response.len() as u32).to_le_bytes());
+                packet[6] = self.seq;
+                packet[10..].copy_from_slice(&response);
+                self.send_packet_assuming_possible(packet);
+            }
+            Err(error) => {
+                info!("escape failed: {:?}", error);
+                let error = match error {
+                    EscapeError::NotSupported => Error::CommandNotSupported,
+                    // abData
+                    EscapeError::InvalidData => Error::BadField(10),
+                    EscapeError::Failed => Error::HwError,
+                };
+                self.send_error(ResponseType::Escape, error);
+            }
+        }
+    }
 
-        // just taking default value from spec.
-        packet[11] = 0x10;
-        // not sure, taking default.
-        packet[13] = 0x15;
-        // set max waiting time
-        packet[15] = 0xfe;
+    fn send_data_rate_and_clock_frequency(&mut self) {
+        let mut packet = RawPacket::new();
+        packet.resize_default(18).ok();
+        packet[0] = ResponseType::DataRateAndClockFrequency as u8;
+        packet[1] = 8;
+        packet[6] = self.seq;
+        packet[10..14].copy_from_slice(&CLOCK_FREQUENCY_KHZ);
+        packet[14..18].copy_from_slice(&DATA_RATE_BPS);
         self.send_packet_assuming_possible(packet);
     }
 
@@ -424,7 +845,7 @@ where
             // T=0, T=1, command chaining/extended Lc+Le/no logical channels, card issuer's data "Solo B"
             // 3B 8C 80 01 80 73 C0 21 C0 56 53 6F 6C 6F 20 42 D4
             // https://smartcard-atr.apdu.fr/parse?ATR=3B+8C+80+01+80+73+C0+21+C0+56+53+6F+6C+6F+20+42+D4
-            &[0x3B, 0x8C, 0x80, 0x01, 0x80, 0x73, 0xC0, 0x21, 0xC0, 0x56, 0x53, 0x6F, 0x6C, 0x6F, 0x20, 0x42, 0xD4]
+            &ATR
             //
             // Not sure if we also need some TA/TB/TC data as in
             // https://smartcard-atr.apdu.fr/parse?ATR=3B+F8+13+00+00+81+31+FE+15+59+75+62+69+6B+65+79+34+D4
@@ -502,8 +923,14 @@ where
     }
 
     pub fn maybe_send_packet(&mut self) {
+        if self.outbox.is_none() && self.block_missing > 0 {
+            // calls us again
+            self.prime_outbox();
+            return;
+        }
         if let Some(packet) = self.outbox.as_ref() {
-            let needs_zlp = packet.len() == PACKET_SIZE;
+            // only the last packet of a transfer may need a ZLP
+            let needs_zlp = packet.len() == PACKET_SIZE && self.block_missing == 0;
             match self.write.write(packet) {
                 Ok(n) if n == packet.len() => {
                     // if packet.len() > 8 {
@@ -519,7 +946,10 @@ where
                     }
 
                 }
-                Ok(_) => panic!("short write"),
+                Ok(_) => {
+                    info!("short write");
+                    self.reset_transaction();
+                }
 
                 Err(UsbError::WouldBlock) => {
                     // fine, can't write try later
@@ -527,9 +957,16 @@ where
                     info!("waiting to send");
                 },
 
-                Err(_) => panic!("unexpected send error"),
+                Err(_) => {
+                    info!("unexpected send error");
+                    self.reset_transaction();
+                }
             }
         }
+
+        if self.outbox.is_none() {
+            self.maybe_complete_abort();
+        }
     }
 
     // pub fn read_address(&self) -> EndpointAddress {
@@ -540,10 +977,81 @@ where
     //     self.write.address()
     // }
 
-    pub fn expect_abort(&mut self, slot: u8, _seq: u8) {
-        debug_assert!(slot == 0);
-        info!("ABORT expected for seq = {}", _seq);
-        todo!();
+    /// Handles the ABORT control request.
+    pub fn expect_abort(&mut self, slot: u8, seq: u8) {
+        if slot != 0 {
+            info!("ABORT for unknown slot {}", slot);
+            return;
+        }
+        info!("ABORT expected for seq = {}", seq);
+
+        // the host gives up on a partially sent message, too
+        self.reception = Reception::Complete;
+        self.long_packet_missing = 0;
+        if self.abort_received != Some(seq) {
+            self.cancel_transaction();
+        }
+        self.abort_requested = Some(seq);
+        self.maybe_complete_abort();
+    }
+
+    fn handle_abort(&mut self, seq: u8) {
+        info!("PC_to_RDR_Abort for seq = {}", seq);
+        if self.abort_requested != Some(seq) {
+            self.cancel_transaction();
+        }
+        self.abort_received = Some(seq);
+        self.maybe_complete_abort();
+    }
+
+    /// Once both halves of the abort arrived, PC_to_RDR_Abort is answered with the slot status.
+    ///
+    /// The answer waits for the outbox to drain, which may still hold the
+    /// CMD_ABORTED for the aborted command.
+    fn maybe_complete_abort(&mut self) {
+        match (self.abort_requested, self.abort_received) {
+            (Some(requested), Some(received)) if requested == received && self.outbox.is_none() => {
+                self.abort_requested = None;
+                self.abort_received = None;
+                self.seq = received;
+                self.send_slot_status_ok();
+            }
+            _ => {}
+        }
+    }
+
+    /// Drops the ongoing transaction. The host still waits for an answer
+    /// to the ongoing command, which fails with CMD_ABORTED.
+    fn cancel_transaction(&mut self) {
+        let ongoing = self.state != State::Idle;
+        self.reset_transaction();
+        if ongoing {
+            // self.seq is still the one of the ongoing command
+            self.send_slot_status_error(Error::CmdAborted);
+        }
+    }
+
+    /// Drops the ongoing transaction, and answers the current command with `error`.
+    fn fail_transaction(&mut self, error: Error) {
+        self.reset_transaction();
+        self.send_slot_status_error(error);
+    }
+
+    /// Drops the ongoing transaction: its request, the app's pending response, and chaining state.
+    fn reset_transaction(&mut self) {
+        if self.state != State::Idle {
+            info!("cancelling transaction in state {:?}", self.state);
+            self.interchange.cancel().ok();
+            self.interchange.take_response();
+        }
+        self.state = State::Idle;
+        self.started_processing = false;
+        self.sent = 0;
+        self.block_missing = 0;
+        self.outbox = None;
+
+        // the rest of a streamed XfrBlock still arrives
+        self.discard_rest();
     }
 
 }
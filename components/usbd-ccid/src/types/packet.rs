@@ -47,7 +47,7 @@ pub trait PacketWithData: Packet {
         // let len = u32::from_le_bytes(self[1..5].try_into().unwrap()) as usize;
         let declared_len =
             u32::from_le_bytes(self[1..5].try_into().unwrap()) as usize;
-        let len = core::cmp::min(MAX_MSG_LENGTH - 10, declared_len);
+        let len = core::cmp::min(self.len() - 10, declared_len);
         // hprintln!("delcared = {}, len = {}", declared_len, len).ok();
         &self[10..][..len]
     }
@@ -55,17 +55,18 @@ pub trait PacketWithData: Packet {
 
 pub trait ChainedPacket: Packet {
 
+    /// The wLevelParameter, or None if it is invalid.
     #[inline(always)]
-    fn chain(&self) -> Chain {
+    fn chain(&self) -> Option<Chain> {
         let level_parameter = u16::from_le_bytes(self[8..10].try_into().unwrap());
-        match level_parameter {
+        Some(match level_parameter {
             0 => Chain::BeginsAndEnds,
             1 => Chain::Begins,
             2 => Chain::Ends,
             3 => Chain::Continues,
             0x10 => Chain::ExpectingMore,
-            _ => panic!("invalid power select parameter"),
-        }
+            _ => return None,
+        })
     }
 }
 
@@ -124,7 +125,7 @@ impl Into<RawPacket> for DataBlock<'_> {
         let len = self.data.len();
         packet.resize_default(10 + len).ok();
         packet[0] = 0x80;
-        packet[1..][..4].copy_from_slice(&len.to_le_bytes());
+        packet[1..][..4].copy_from_slice(&(len as u32).to_le_bytes());
         packet[5] = 0;
         packet[6] = self.seq;
 
@@ -153,16 +154,28 @@ pub enum CommandType {
     GetParameters = 0x6c,
     XfrBlock = 0x6f,
     Abort = 0x72,
-
-    // unsupported
     ResetParameters = 0x6d,
     SetParameters = 0x61,
-    Escape = 0x6b,//  for vendor commands
     IccClock = 0x7e,
+    // answered with the fixed values from the functional descriptor
+    SetDataRateAndClockFrequency = 0x73,
+    // vendor commands, answered by the runner's `EscapeHandler`
+    Escape = 0x6b,
+
+    // parsed, but unsupported
     T0Apdu = 0x6a,
     Secure = 0x69,
     Mechanical = 0x71,
-    SetDataRateAndClockFrequency = 0x73,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ResponseType {
+    DataBlock = 0x80,
+    SlotStatus = 0x81,
+    Parameters = 0x82,
+    Escape = 0x83,
+    DataRateAndClockFrequency = 0x84,
 }
 
 macro_rules! command_message {
@@ -276,9 +289,139 @@ command_message!(
     GetParameters: 0x6c,
     XfrBlock: 0x6f,
     Abort: 0x72,
+    ResetParameters: 0x6d,
+    SetParameters: 0x61,
+    IccClock: 0x7e,
+    SetDataRateAndClockFrequency: 0x73,
+    Escape: 0x6b,
+    T0Apdu: 0x6a,
+    Secure: 0x69,
+    Mechanical: 0x71,
 );
 
 impl PacketWithData for XfrBlock {}
+impl PacketWithData for SetParameters {}
+impl PacketWithData for SetDataRateAndClockFrequency {}
+impl PacketWithData for Escape {}
+impl PacketWithData for Secure {}
+
+impl SetParameters {
+    /// bProtocolNum: 0 for T=0, 1 for T=1
+    #[inline(always)]
+    pub fn protocol(&self) -> u8 {
+        self[7]
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClockCommand {
+    Restart,
+    Stop,
+}
+
+impl IccClock {
+    /// The bClockCommand, or None if it is invalid.
+    #[inline(always)]
+    pub fn clock_command(&self) -> Option<ClockCommand> {
+        match self[7] {
+            0 => Some(ClockCommand::Restart),
+            1 => Some(ClockCommand::Stop),
+            _ => None,
+        }
+    }
+}
+
+/// abProtocolDataStructure for T=1, cf. CCID_Rev110 6.1.7
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ProtocolDataT1 {
+    /// bmFindexDindex
+    pub fi_di: u8,
+    /// bmTCCKST1: checksum type (bit 0: CRC), convention (bit 1: inverse)
+    pub tcck: u8,
+    /// bGuardTimeT1
+    pub guard_time: u8,
+    /// bmWaitingIntegersT1: BWI (high nibble), CWI (low nibble)
+    pub waiting_integers: u8,
+    /// bClockStop: 0 not allowed, 1 low, 2 high, 3 either
+    pub clock_stop: u8,
+    /// bIFSC
+    pub ifsc: u8,
+    /// bNadValue
+    pub nad: u8,
+}
+
+impl Default for ProtocolDataT1 {
+    fn default() -> Self {
+        Self {
+            // just picking the fastest values.
+            //       Fi = 1Mz    Di=1
+            fi_di: (0b0001 << 4) | (0b0001),
+            // just taking default value from spec.
+            tcck: 0x10,
+            guard_time: 0,
+            // not sure, taking default.
+            waiting_integers: 0x15,
+            clock_stop: 0,
+            // set max waiting time
+            ifsc: 0xfe,
+            nad: 0,
+        }
+    }
+}
+
+impl ProtocolDataT1 {
+    pub const LENGTH: usize = 7;
+
+    pub fn to_bytes(&self) -> [u8; Self::LENGTH] {
+        [
+            self.fi_di,
+            self.tcck,
+            self.guard_time,
+            self.waiting_integers,
+            self.clock_stop,
+            self.ifsc,
+            self.nad,
+        ]
+    }
+
+    /// Parses and validates the structure, on error returns the offset of the bad field.
+    pub fn try_from_bytes(bytes: &[u8]) -> core::result::Result<Self, usize> {
+        if bytes.len() != Self::LENGTH {
+            return Err(0);
+        }
+        let parameters = Self {
+            fi_di: bytes[0],
+            tcck: bytes[1],
+            guard_time: bytes[2],
+            waiting_integers: bytes[3],
+            clock_stop: bytes[4],
+            ifsc: bytes[5],
+            nad: bytes[6],
+        };
+
+        // Fi and Di must be valid indices (ISO 7816-3 Tables 7 and 8)
+        let (fi, di) = (parameters.fi_di >> 4, parameters.fi_di & 0xf);
+        if matches!(fi, 7 | 8 | 14 | 15) || matches!(di, 0 | 10..=15) {
+            return Err(0);
+        }
+        // only direct convention, LRC or CRC
+        if parameters.tcck & !0x01 != 0x10 {
+            return Err(1);
+        }
+        // BWI is at most 9
+        if parameters.waiting_integers >> 4 > 9 {
+            return Err(3);
+        }
+        if parameters.clock_stop > 3 {
+            return Err(4);
+        }
+        // 0xFF is reserved
+        if parameters.ifsc == 0xff {
+            return Err(5);
+        }
+        Ok(parameters)
+    }
+}
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum PowerSelection {
@@ -289,15 +432,16 @@ pub enum PowerSelection {
 }
 
 impl PowerOn {
+    /// The bPowerSelect, or None if it is invalid.
     #[inline(always)]
-    pub fn power_select(&self) -> PowerSelection {
-        match &self[7] {
+    pub fn power_select(&self) -> Option<PowerSelection> {
+        Some(match &self[7] {
             0 => PowerSelection::Automatic,
             1 => PowerSelection::V5,
             2 => PowerSelection::V3_3,
             3 => PowerSelection::V1_8,
-            _ => panic!("invalid power select parameter"),
-        }
+            _ => return None,
+        })
     }
 }
 
@@ -0,0 +1,263 @@
+//! Drives a `Ccid` class through a `MockBus`, playing both the host
+//! and the app behind the interchange.
+//!
+//! Interchanges can only be claimed once, so every test declares its own.
+//!
+//! The fuzz targets in `fuzz/` include this module, too.
+#![allow(dead_code)]
+
+use interchange::{Interchange, Responder};
+use test_support::MockBus;
+use usb_device::{
+    bus::UsbBusAllocator,
+    class::UsbClass,
+    endpoint::EndpointAddress,
+    UsbDirection,
+};
+use usbd_ccid::{
+    constants::{MAX_MSG_LENGTH, MAX_MSG_LENGTH_TYPE, PACKET_SIZE},
+    Ccid, EscapeError, EscapeHandler, EscapeResponse,
+};
+
+pub type Message = heapless_bytes::Bytes<MAX_MSG_LENGTH_TYPE>;
+
+pub const SELECT: [u8; 4] = [0x00, 0xA4, 0x04, 0x00];
+
+/// Echoes the Escape data, if it fits.
+struct EchoEscape;
+
+impl EscapeHandler for EchoEscape {
+    fn escape(&mut self, request: &[u8], response: &mut EscapeResponse) -> core::result::Result<(), EscapeError> {
+        response.extend_from_slice(request).map_err(|_| EscapeError::InvalidData)
+    }
+}
+
+pub struct Device<I>
+where
+    I: 'static + Interchange<REQUEST = Message, RESPONSE = Message>,
+{
+    ccid: Ccid<MockBus, I, MAX_MSG_LENGTH_TYPE>,
+    app: Responder<I>,
+    bus: MockBus,
+}
+
+// the class allocates its bulk OUT endpoint first, then bulk IN
+fn read_address() -> EndpointAddress {
+    EndpointAddress::from_parts(1, UsbDirection::Out)
+}
+
+fn write_address() -> EndpointAddress {
+    EndpointAddress::from_parts(1, UsbDirection::In)
+}
+
+impl<I> Device<I>
+where
+    I: 'static + Interchange<REQUEST = Message, RESPONSE = Message>,
+{
+    pub fn new() -> Self {
+        let bus = MockBus::default();
+        let allocator = Box::leak(Box::new(UsbBusAllocator::new(bus.clone())));
+        let (requester, app) = I::claim().expect("interchange already claimed");
+        let ccid = Ccid::new(allocator, requester);
+        Self { ccid, app, bus }
+    }
+
+    pub fn implements_escape(mut self) -> Self {
+        self.ccid = self.ccid.implements_escape();
+        self
+    }
+
+    pub fn handle_escape(&mut self, handler: &mut impl EscapeHandler) {
+        self.ccid.handle_escape(handler);
+    }
+
+    pub fn max_msg_length(&self) -> usize {
+        self.ccid.max_msg_length()
+    }
+
+    /// Sends one USB packet to the class.
+    pub fn send(&mut self, packet: &[u8]) {
+        assert!(packet.len() <= PACKET_SIZE);
+        self.bus.host_write(read_address(), packet);
+        self.ccid.endpoint_out(read_address());
+    }
+
+    /// Sends a CCID message, split into USB packets.
+    pub fn send_message(&mut self, message: &[u8]) {
+        for packet in message.chunks(PACKET_SIZE) {
+            self.send(packet);
+        }
+    }
+
+    /// Receives one USB packet from the class, if there is one.
+    pub fn receive(&mut self) -> Option<Vec<u8>> {
+        self.ccid.check_for_app_response();
+        let packet = self.bus.host_read(write_address())?;
+        self.ccid.endpoint_in_complete(write_address());
+        Some(packet)
+    }
+
+    /// Receives one CCID message: USB packets up to a short (possibly zero-length) one.
+    pub fn receive_message(&mut self) -> Vec<u8> {
+        let mut message = self.receive().expect("no response");
+        let mut packet_len = message.len();
+        while packet_len == PACKET_SIZE {
+            let packet = self.receive().expect("message ends early");
+            packet_len = packet.len();
+            message.extend_from_slice(&packet);
+        }
+        message
+    }
+
+    /// Takes the request the app received, if any.
+    pub fn app_request(&mut self) -> Option<Vec<u8>> {
+        self.app.take_request().map(|request| request.to_vec())
+    }
+
+    pub fn app_respond(&mut self, response: &[u8]) {
+        self.app.respond(&Message::try_from_slice(response).unwrap()).ok().unwrap();
+    }
+
+    /// Sends an APDU, checks it reaches the app, and that the app's 9000 comes back.
+    pub fn check_apdu(&mut self, seq: u8, apdu: &[u8]) {
+        self.send_message(&xfr_block(seq, 0, apdu));
+        let request = self.app_request().expect("APDU did not reach the app");
+        assert_eq!(request, apdu);
+        self.app_respond(&[0x90, 0x00]);
+
+        let response = self.receive_message();
+        assert_eq!(response, [&header(0x80, 2, seq, [0, 0, 0])[..], &[0x90, 0x00]].concat());
+    }
+
+    /// Checks that the pipe still handles GetSlotStatus.
+    pub fn check_slot_status(&mut self, seq: u8) {
+        self.send(&header(0x65, 0, seq, [0, 0, 0]));
+        let response = self.receive().expect("no slot status");
+        assert_eq!(response, header(0x81, 0, seq, [0, 0, 0]));
+    }
+
+    /// Answers a pending request, with `length` bytes of the request repeated, and a pending Escape.
+    fn serve_app(&mut self, length: usize) {
+        if let Some(request) = self.app_request() {
+            let response: Vec<u8> = request.iter().copied().chain(core::iter::repeat(0x90)).take(length).collect();
+            self.app_respond(&response);
+        }
+        self.handle_escape(&mut EchoEscape);
+    }
+
+    /// Receives everything the class has to send.
+    fn drain(&mut self) {
+        // a response to every packet sent, and a DataBlock of maximal length
+        for _ in 0..2 * (self.max_msg_length() / PACKET_SIZE + 2) {
+            if self.receive().is_none() {
+                return;
+            }
+        }
+        panic!("class keeps sending");
+    }
+
+    /// Interprets arbitrary data as a sequence of steps for the class.
+    ///
+    /// Each step is a control byte and a length byte, followed by a packet
+    /// (zero padded if the data ends). The low two bits of the control byte select the step:
+    ///
+    /// - 0: send `length % (PACKET_SIZE + 1)` bytes of the packet
+    /// - 1: send the packet as a message for slot 0 with `length * 29` bytes of data
+    ///   (the packet repeated), so that it spans several USB packets
+    /// - 2: the app answers, with a response length taken from the packet, and a pending Escape is handled
+    /// - 3: the runner asks for a wait extension
+    ///
+    /// Unless bit 2 is set, everything the class sends is received after the step.
+    pub fn run_steps(&mut self, data: &[u8]) {
+        for step in data.chunks(2 + PACKET_SIZE) {
+            let control = step[0];
+            let length = step.get(1).copied().unwrap_or(0) as usize;
+            let mut packet = step.get(2..).unwrap_or(&[]).to_vec();
+            packet.resize(PACKET_SIZE, 0);
+
+            match control & 0x3 {
+                0 => self.send(&packet[..length % (PACKET_SIZE + 1)]),
+                1 => {
+                    let data_length = length * 29;
+                    let mut message = packet[..10].to_vec();
+                    message[1..5].copy_from_slice(&(data_length as u32).to_le_bytes());
+                    message[5] = 0;
+                    message.extend(packet[10..].iter().copied().cycle().take(data_length));
+                    self.send_message(&message);
+                }
+                2 => {
+                    let length = u16::from_le_bytes([packet[0], packet[1]]) as usize % (MAX_MSG_LENGTH + 1);
+                    self.serve_app(length);
+                }
+                _ => {
+                    self.ccid.send_wait_extension();
+                }
+            }
+
+            if control & 0x4 == 0 {
+                self.drain();
+            }
+        }
+    }
+
+    /// Checks that the class is usable again, whatever state `run_steps` left it in.
+    ///
+    /// The first commands may still be taken for the rest of an earlier message,
+    /// or fail what was going on, but after a few attempts an APDU must get through.
+    pub fn check_recovers(&mut self) {
+        self.drain();
+        self.serve_app(0);
+        self.drain();
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            assert!(attempts <= 3, "class does not recover");
+            self.send_message(&xfr_block(attempts, 0, &SELECT));
+            // may also complete an earlier XfrBlock
+            if let Some(request) = self.app_request() {
+                self.app_respond(&[0x90, 0x00]);
+                let response = self.receive_message();
+                if request == SELECT && response == [&header(0x80, 2, attempts, [0; 3])[..], &[0x90, 0x00]].concat() {
+                    break;
+                }
+            }
+            self.drain();
+        }
+
+        self.check_slot_status(4);
+        self.check_apdu(5, &SELECT);
+        assert_eq!(self.receive(), None);
+    }
+}
+
+/// A CCID header: bMessageType, dwLength, bSlot = 0, bSeq, and three message specific bytes.
+pub fn header(message_type: u8, length: u32, seq: u8, specific: [u8; 3]) -> Vec<u8> {
+    let mut header = vec![message_type];
+    header.extend_from_slice(&length.to_le_bytes());
+    header.extend_from_slice(&[0, seq]);
+    header.extend_from_slice(&specific);
+    header
+}
+
+/// PC_to_RDR_XfrBlock with the given wLevelParameter.
+pub fn xfr_block(seq: u8, level: u16, data: &[u8]) -> Vec<u8> {
+    let [level_low, level_high] = level.to_le_bytes();
+    let mut message = header(0x6f, data.len() as u32, seq, [0, level_low, level_high]);
+    message.extend_from_slice(data);
+    message
+}
+
+/// Asserts a failed RDR_to_PC_SlotStatus with bError `error`.
+pub fn assert_slot_error(response: &[u8], seq: u8, error: u8) {
+    assert_error(response, 0x81, seq, error);
+}
+
+/// Asserts a failed response of the given type, with bError `error`.
+pub fn assert_error(response: &[u8], message_type: u8, seq: u8, error: u8) {
+    assert_eq!(response.len(), 10, "not a failure: {:02X?}", response);
+    assert_eq!(response[0], message_type);
+    assert_eq!(response[6], seq);
+    assert_eq!(response[7] & 0xc0, 0x40, "command did not fail");
+    assert_eq!(response[8], error);
+}
@@ -0,0 +1,99 @@
+//! Escape commands, answered synchronously by an `EscapeHandler`.
+
+mod common;
+
+use common::{assert_error, header, Device, Message};
+use usbd_ccid::{EscapeError, EscapeHandler, EscapeResponse};
+
+const ESCAPE: u8 = 0x6b;
+const RDR_TO_PC_ESCAPE: u8 = 0x83;
+
+interchange::interchange! { Escape: (Message, Message) }
+interchange::interchange! { Busy: (Message, Message) }
+interchange::interchange! { Disabled: (Message, Message) }
+
+/// Echoes its input for command 1, fails for command 2.
+#[derive(Default)]
+struct Echo {
+    calls: usize,
+}
+
+impl EscapeHandler for Echo {
+    fn escape(&mut self, request: &[u8], response: &mut EscapeResponse) -> Result<(), EscapeError> {
+        self.calls += 1;
+        match request.split_first() {
+            Some((&1, data)) => {
+                response.extend_from_slice(data).unwrap();
+                Ok(())
+            }
+            Some((&2, _)) => Err(EscapeError::Failed),
+            Some(_) => Err(EscapeError::NotSupported),
+            None => Err(EscapeError::InvalidData),
+        }
+    }
+}
+
+fn escape(seq: u8, data: &[u8]) -> Vec<u8> {
+    let mut message = header(ESCAPE, data.len() as u32, seq, [0; 3]);
+    message.extend_from_slice(data);
+    message
+}
+
+#[test]
+fn escape_commands() {
+    let mut device = Device::<Escape>::new().implements_escape();
+    let mut handler = Echo::default();
+
+    // nothing pending
+    device.handle_escape(&mut handler);
+    assert_eq!(handler.calls, 0);
+
+    device.send(&escape(1, &[1, 0xca, 0xfe]));
+    assert_eq!(device.receive(), None);
+    device.handle_escape(&mut handler);
+    let mut expected = header(RDR_TO_PC_ESCAPE, 2, 1, [0; 3]);
+    expected.extend_from_slice(&[0xca, 0xfe]);
+    assert_eq!(device.receive().unwrap(), expected);
+
+    device.send(&escape(2, &[2]));
+    device.handle_escape(&mut handler);
+    assert_error(&device.receive().unwrap(), RDR_TO_PC_ESCAPE, 2, 0xfb);
+
+    device.send(&escape(3, &[3]));
+    device.handle_escape(&mut handler);
+    assert_error(&device.receive().unwrap(), RDR_TO_PC_ESCAPE, 3, 0);
+
+    device.send(&escape(4, &[]));
+    device.handle_escape(&mut handler);
+    assert_error(&device.receive().unwrap(), RDR_TO_PC_ESCAPE, 4, 10);
+
+    assert_eq!(handler.calls, 4);
+    device.check_slot_status(5);
+}
+
+#[test]
+fn busy_while_escaping() {
+    let mut device = Device::<Busy>::new().implements_escape();
+    let mut handler = Echo::default();
+
+    device.send(&escape(1, &[1]));
+    device.send(&header(0x65, 0, 2, [0; 3]));
+    // CMD_SLOT_BUSY
+    assert_error(&device.receive().unwrap(), 0x81, 2, 0xe0);
+
+    device.handle_escape(&mut handler);
+    assert_eq!(device.receive().unwrap(), header(RDR_TO_PC_ESCAPE, 0, 1, [0; 3]));
+    device.check_slot_status(3);
+}
+
+#[test]
+fn escape_not_implemented() {
+    let mut device = Device::<Disabled>::new();
+    let mut handler = Echo::default();
+
+    device.send(&escape(1, &[1]));
+    assert_error(&device.receive().unwrap(), RDR_TO_PC_ESCAPE, 1, 0);
+    device.handle_escape(&mut handler);
+    assert_eq!(handler.calls, 0);
+    device.check_slot_status(2);
+}
@@ -0,0 +1,78 @@
+//! Extended length APDUs travel in a single XfrBlock and DataBlock, spanning many USB packets.
+
+mod common;
+
+use common::{assert_slot_error, header, xfr_block, Device, Message};
+use usbd_ccid::constants::MAX_MSG_LENGTH;
+
+const BAD_LENGTH: u8 = 1;
+const CMD_SLOT_BUSY: u8 = 0xE0;
+
+interchange::interchange! { Extended: (Message, Message) }
+interchange::interchange! { Maximal: (Message, Message) }
+interchange::interchange! { Busy: (Message, Message) }
+
+/// An extended length case 4 APDU with `len` data bytes.
+fn extended_apdu(len: usize) -> Vec<u8> {
+    let mut apdu = vec![0x00, 0xDB, 0x3F, 0xFF, 0x00];
+    apdu.extend_from_slice(&(len as u16).to_be_bytes());
+    apdu.extend((0..len).map(|i| i as u8));
+    apdu.extend_from_slice(&[0x00, 0x00]);
+    apdu
+}
+
+#[test]
+fn extended_request_and_response() {
+    let mut device = Device::<Extended>::new();
+
+    let apdu = extended_apdu(2000);
+    device.send_message(&xfr_block(1, 0, &apdu));
+    assert_eq!(device.app_request().unwrap(), apdu);
+
+    let response: Vec<u8> = (0..2000).map(|i| (i / 7) as u8).chain([0x90, 0x00].iter().copied()).collect();
+    device.app_respond(&response);
+    let expected = [&header(0x80, response.len() as u32, 1, [0; 3])[..], &response].concat();
+    assert_eq!(device.receive_message(), expected);
+    assert_eq!(device.receive(), None);
+
+    device.check_slot_status(2);
+}
+
+#[test]
+fn maximal_request() {
+    let mut device = Device::<Maximal>::new();
+    // the request size of the interchange
+    assert_eq!(device.max_msg_length(), MAX_MSG_LENGTH + 10);
+
+    let data = vec![0x42; MAX_MSG_LENGTH];
+    device.send_message(&xfr_block(1, 0, &data));
+    assert_eq!(device.app_request().unwrap(), data);
+    device.app_respond(&data);
+    let expected = [&header(0x80, data.len() as u32, 1, [0; 3])[..], &data].concat();
+    assert_eq!(device.receive_message(), expected);
+
+    let mut message = xfr_block(2, 0, &[0x42; MAX_MSG_LENGTH + 1]);
+    message.truncate(64);
+    device.send(&message);
+    assert_slot_error(&device.receive().unwrap(), 2, BAD_LENGTH);
+
+    device.check_slot_status(3);
+}
+
+#[test]
+fn long_request_while_busy() {
+    let mut device = Device::<Busy>::new();
+
+    device.send_message(&xfr_block(1, 0, &extended_apdu(300)));
+    assert!(device.app_request().is_some());
+
+    // rejected right away, the rest of the message is dropped
+    device.send_message(&xfr_block(2, 0, &extended_apdu(1000)));
+    assert_slot_error(&device.receive().unwrap(), 2, CMD_SLOT_BUSY);
+    assert_eq!(device.receive(), None);
+
+    device.app_respond(&[0x90, 0x00]);
+    assert_eq!(device.receive_message(), [&header(0x80, 2, 1, [0; 3])[..], &[0x90, 0x00]].concat());
+
+    device.check_apdu(3, &extended_apdu(1000));
+}
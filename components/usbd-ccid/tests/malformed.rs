@@ -0,0 +1,197 @@
+//! Malformed packet sequences must fail with a slot error, and leave the pipe usable.
+
+mod common;
+
+use common::{assert_slot_error, header, xfr_block, Device, Message};
+
+// bError values
+const COMMAND_NOT_SUPPORTED: u8 = 0x00;
+const BAD_LENGTH: u8 = 1;
+const BAD_SLOT: u8 = 5;
+const BAD_LEVEL_PARAMETER: u8 = 8;
+const CMD_SLOT_BUSY: u8 = 0xE0;
+const CMD_ABORTED: u8 = 0xFF;
+
+// wLevelParameter values
+const BEGINS: u16 = 1;
+const ENDS: u16 = 2;
+const CONTINUES: u16 = 3;
+const EXPECTING_MORE: u16 = 0x10;
+
+const SELECT: [u8; 4] = [0x00, 0xA4, 0x04, 0x00];
+
+interchange::interchange! { ShortPackets: (Message, Message) }
+interchange::interchange! { LengthMismatch: (Message, Message) }
+interchange::interchange! { LevelParameter: (Message, Message) }
+interchange::interchange! { TooLong: (Message, Message) }
+interchange::interchange! { BusySlot: (Message, Message) }
+interchange::interchange! { AbortedEscape: (Message, Message) }
+interchange::interchange! { UnknownSlot: (Message, Message) }
+interchange::interchange! { UnknownCommand: (Message, Message) }
+
+fn expecting_more(seq: u8) -> Vec<u8> {
+    header(0x80, 0, seq, [0, 0, EXPECTING_MORE as u8])
+}
+
+#[test]
+fn short_packets() {
+    let mut device = Device::<ShortPackets>::new();
+
+    device.send(&[0x65, 0, 0, 0, 0, 0, 7]);
+    assert_slot_error(&device.receive().unwrap(), 7, BAD_LENGTH);
+
+    device.send(&[0x65]);
+    assert_slot_error(&device.receive().unwrap(), 0, BAD_LENGTH);
+
+    // zero-length packets are ignored
+    device.send(&[]);
+    assert_eq!(device.receive(), None);
+
+    device.check_slot_status(8);
+    device.check_apdu(9, &SELECT);
+}
+
+#[test]
+fn length_mismatch() {
+    let mut device = Device::<LengthMismatch>::new();
+
+    // more than dwMaxCCIDMessageLength
+    let mut message = header(0x6f, 4000, 1, [0; 3]);
+    message.resize(64, 0);
+    device.send(&message);
+    assert_slot_error(&device.receive().unwrap(), 1, BAD_LENGTH);
+
+    // more than the transfer
+    let mut message = header(0x6f, 20, 2, [0; 3]);
+    message.extend_from_slice(&[0; 5]);
+    device.send(&message);
+    assert_slot_error(&device.receive().unwrap(), 2, BAD_LENGTH);
+
+    // the continuation is longer than announced
+    let mut message = header(0x6f, 60, 3, [0; 3]);
+    message.resize(64, 0);
+    device.send(&message);
+    assert_eq!(device.receive(), None);
+    device.send(&[0; 10]);
+    assert_slot_error(&device.receive().unwrap(), 3, BAD_LENGTH);
+
+    // the continuation ends early
+    let mut message = header(0x6f, 200, 4, [0; 3]);
+    message.resize(64, 0);
+    device.send(&message);
+    device.send(&[0; 20]);
+    assert_slot_error(&device.receive().unwrap(), 4, BAD_LENGTH);
+
+    device.check_slot_status(5);
+    // spans several packets
+    let apdu = [&SELECT[..], &[100], &[0x42; 100]].concat();
+    device.check_apdu(6, &apdu);
+}
+
+#[test]
+fn level_parameter() {
+    let mut device = Device::<LevelParameter>::new();
+
+    for (seq, level) in [(1, ENDS), (2, CONTINUES), (3, EXPECTING_MORE), (4, 0x55)].iter().copied() {
+        device.send_message(&xfr_block(seq, level, &SELECT));
+        assert_slot_error(&device.receive().unwrap(), seq, BAD_LEVEL_PARAMETER);
+    }
+
+    // invalid in the middle of a chain
+    device.send_message(&xfr_block(5, BEGINS, &SELECT));
+    assert_eq!(device.receive().unwrap(), expecting_more(5));
+    device.send_message(&xfr_block(6, 0x55, &SELECT));
+    assert_slot_error(&device.receive().unwrap(), 6, BAD_LEVEL_PARAMETER);
+    assert_eq!(device.app_request(), None);
+
+    device.check_apdu(7, &SELECT);
+}
+
+#[test]
+fn chained_request_too_long() {
+    let mut device = Device::<TooLong>::new();
+
+    device.send_message(&xfr_block(0, BEGINS, &[0; 54]));
+    assert_eq!(device.receive().unwrap(), expecting_more(0));
+
+    let mut seq = 1;
+    loop {
+        device.send_message(&xfr_block(seq, CONTINUES, &[0; 54]));
+        let response = device.receive().unwrap();
+        if response != expecting_more(seq) {
+            assert_slot_error(&response, seq, BAD_LENGTH);
+            break;
+        }
+        seq += 1;
+        assert!(seq < 100, "request of {} bytes was not rejected", 54 * seq as usize);
+    }
+    assert_eq!(device.app_request(), None);
+
+    device.check_apdu(seq + 1, &SELECT);
+}
+
+#[test]
+fn busy_slot() {
+    let mut device = Device::<BusySlot>::new();
+
+    device.send_message(&xfr_block(1, 0, &SELECT));
+    assert_eq!(device.app_request().unwrap(), SELECT);
+
+    device.send_message(&xfr_block(2, 0, &SELECT));
+    assert_slot_error(&device.receive().unwrap(), 2, CMD_SLOT_BUSY);
+    device.send(&header(0x65, 0, 3, [0; 3]));
+    assert_slot_error(&device.receive().unwrap(), 3, CMD_SLOT_BUSY);
+    device.send(&header(0x99, 0, 4, [0; 3]));
+    assert_slot_error(&device.receive().unwrap(), 4, COMMAND_NOT_SUPPORTED);
+
+    // the response still belongs to the first command
+    device.app_respond(&[0x90, 0x00]);
+    assert_eq!(device.receive_message(), [&header(0x80, 2, 1, [0; 3])[..], &[0x90, 0x00]].concat());
+
+    device.check_apdu(5, &SELECT);
+}
+
+#[test]
+fn aborted_escape() {
+    let mut device = Device::<AbortedEscape>::new().implements_escape();
+
+    let mut escape = header(0x6b, 1, 1, [0; 3]);
+    escape.push(1);
+    device.send(&escape);
+    assert_eq!(device.receive(), None);
+
+    // PC_to_RDR_Abort is not rejected as busy, the Escape fails right away
+    device.send(&header(0x72, 0, 2, [0; 3]));
+    assert_slot_error(&device.receive().unwrap(), 1, CMD_ABORTED);
+    assert_eq!(device.receive(), None);
+
+    // until the ABORT control request arrives, other commands fail
+    device.send(&header(0x65, 0, 3, [0; 3]));
+    assert_slot_error(&device.receive().unwrap(), 3, CMD_ABORTED);
+}
+
+#[test]
+fn unknown_slot() {
+    let mut device = Device::<UnknownSlot>::new();
+
+    let mut message = header(0x65, 0, 1, [0; 3]);
+    message[5] = 1;
+    device.send(&message);
+    let response = device.receive().unwrap();
+    assert_slot_error(&response, 1, BAD_SLOT);
+    // bmICCStatus: no ICC present
+    assert_eq!(response[7] & 0x03, 2);
+
+    device.check_slot_status(2);
+}
+
+#[test]
+fn unknown_command() {
+    let mut device = Device::<UnknownCommand>::new();
+
+    device.send(&header(0x99, 0, 1, [0; 3]));
+    assert_slot_error(&device.receive().unwrap(), 1, COMMAND_NOT_SUPPORTED);
+
+    device.check_slot_status(2);
+    device.check_apdu(3, &SELECT);
+}
@@ -0,0 +1,120 @@
+//! Protocol parameters, clock and data rate commands, for the T=1 protocol we advertise.
+
+mod common;
+
+use common::{assert_error, assert_slot_error, header, Device, Message};
+
+const GET_PARAMETERS: u8 = 0x6c;
+const RESET_PARAMETERS: u8 = 0x6d;
+const SET_PARAMETERS: u8 = 0x61;
+const ICC_CLOCK: u8 = 0x7e;
+const SET_DATA_RATE_AND_CLOCK_FREQUENCY: u8 = 0x73;
+const SECURE: u8 = 0x69;
+const MECHANICAL: u8 = 0x71;
+
+const PARAMETERS: u8 = 0x82;
+const DATA_BLOCK: u8 = 0x80;
+
+const DEFAULT_T1: [u8; 7] = [0x11, 0x10, 0x00, 0x15, 0x00, 0xfe, 0x00];
+
+interchange::interchange! { Parameters: (Message, Message) }
+interchange::interchange! { Clock: (Message, Message) }
+interchange::interchange! { Unsupported: (Message, Message) }
+
+fn set_parameters(seq: u8, protocol: u8, data: &[u8]) -> Vec<u8> {
+    let mut message = header(SET_PARAMETERS, data.len() as u32, seq, [protocol, 0, 0]);
+    message.extend_from_slice(data);
+    message
+}
+
+fn parameters(seq: u8, data: &[u8; 7]) -> Vec<u8> {
+    let mut message = header(PARAMETERS, 7, seq, [0, 0, 1]);
+    message.extend_from_slice(data);
+    message
+}
+
+#[test]
+fn set_and_reset_parameters() {
+    let mut device = Device::<Parameters>::new();
+
+    device.send(&header(GET_PARAMETERS, 0, 1, [0; 3]));
+    assert_eq!(device.receive().unwrap(), parameters(1, &DEFAULT_T1));
+
+    // CRC, BWI = 4, clock stop in either state, IFSC = 32
+    let t1 = [0x11, 0x11, 0x00, 0x45, 0x03, 0x20, 0x00];
+    device.send(&set_parameters(2, 1, &t1));
+    assert_eq!(device.receive().unwrap(), parameters(2, &t1));
+    device.send(&header(GET_PARAMETERS, 0, 3, [0; 3]));
+    assert_eq!(device.receive().unwrap(), parameters(3, &t1));
+
+    // T=0 is not supported
+    device.send(&set_parameters(4, 0, &[0x11, 0x00, 0x00, 0x0a, 0x00]));
+    assert_error(&device.receive().unwrap(), PARAMETERS, 4, 7);
+    // wrong length
+    device.send(&set_parameters(5, 1, &t1[..5]));
+    assert_error(&device.receive().unwrap(), PARAMETERS, 5, 1);
+    // inverse convention
+    device.send(&set_parameters(6, 1, &[0x11, 0x12, 0x00, 0x45, 0x03, 0x20, 0x00]));
+    assert_error(&device.receive().unwrap(), PARAMETERS, 6, 11);
+    // reserved IFSC
+    device.send(&set_parameters(7, 1, &[0x11, 0x10, 0x00, 0x45, 0x03, 0xff, 0x00]));
+    assert_error(&device.receive().unwrap(), PARAMETERS, 7, 15);
+
+    // failures leave the parameters alone
+    device.send(&header(GET_PARAMETERS, 0, 8, [0; 3]));
+    assert_eq!(device.receive().unwrap(), parameters(8, &t1));
+
+    device.send(&header(RESET_PARAMETERS, 0, 9, [0; 3]));
+    assert_eq!(device.receive().unwrap(), parameters(9, &DEFAULT_T1));
+}
+
+#[test]
+fn icc_clock() {
+    let mut device = Device::<Clock>::new();
+
+    // by default, the clock must not be stopped
+    device.send(&header(ICC_CLOCK, 0, 1, [1, 0, 0]));
+    assert_slot_error(&device.receive().unwrap(), 1, 7);
+
+    let mut t1 = DEFAULT_T1;
+    t1[4] = 2;
+    device.send(&set_parameters(2, 1, &t1));
+    assert_eq!(device.receive().unwrap(), parameters(2, &t1));
+
+    // bClockStatus: stopped in state H
+    device.send(&header(ICC_CLOCK, 0, 3, [1, 0, 0]));
+    assert_eq!(device.receive().unwrap(), header(0x81, 0, 3, [0, 0, 2]));
+    device.send(&header(0x65, 0, 4, [0; 3]));
+    assert_eq!(device.receive().unwrap(), header(0x81, 0, 4, [0, 0, 2]));
+
+    device.send(&header(ICC_CLOCK, 0, 5, [0, 0, 0]));
+    assert_eq!(device.receive().unwrap(), header(0x81, 0, 5, [0, 0, 0]));
+
+    device.send(&header(ICC_CLOCK, 0, 6, [2, 0, 0]));
+    assert_slot_error(&device.receive().unwrap(), 6, 7);
+
+    device.check_slot_status(7);
+}
+
+#[test]
+fn data_rate_and_unsupported_commands() {
+    let mut device = Device::<Unsupported>::new();
+
+    // the only supported values, 3.58 MHz and 9600 bps
+    let mut message = header(SET_DATA_RATE_AND_CLOCK_FREQUENCY, 8, 1, [0; 3]);
+    message.extend_from_slice(&[0x40, 0x1f, 0x00, 0x00, 0x00, 0x4b, 0x00, 0x00]);
+    device.send(&message);
+    let mut expected = header(0x84, 8, 1, [0; 3]);
+    expected.extend_from_slice(&[0xfc, 0x0d, 0x00, 0x00, 0x80, 0x25, 0x00, 0x00]);
+    assert_eq!(device.receive().unwrap(), expected);
+
+    let mut message = header(SECURE, 1, 2, [0; 3]);
+    message.push(0);
+    device.send(&message);
+    assert_error(&device.receive().unwrap(), DATA_BLOCK, 2, 0);
+
+    device.send(&header(MECHANICAL, 0, 3, [1, 0, 0]));
+    assert_slot_error(&device.receive().unwrap(), 3, 0);
+
+    device.check_slot_status(4);
+}
@@ -0,0 +1,49 @@
+//! Random bulk OUT transfers must neither panic nor leave the class stuck.
+//!
+//! The same steps as the fuzz targets in `fuzz/`, from a fixed seed.
+
+mod common;
+
+use common::{Device, Message};
+use test_support::Random;
+
+// the PC_to_RDR commands, and an unknown one
+const MESSAGE_TYPES: [u8; 15] = [
+    0x62, 0x63, 0x65, 0x6c, 0x6f, 0x72, 0x6d, 0x61, 0x7e, 0x73, 0x6b, 0x6a, 0x69, 0x71, 0x99,
+];
+// wLevelParameter values, and an invalid one
+const LEVEL_PARAMETERS: [u8; 6] = [0, 1, 2, 3, 0x10, 0x55];
+
+interchange::interchange! { Properties: (Message, Message) }
+
+/// A step for `Device::run_steps`, mostly well-formed so transactions get somewhere.
+fn random_step(random: &mut Random) -> Vec<u8> {
+    let mut step = vec![random.byte(), random.byte()];
+    let mut packet: Vec<u8> = (0..64).map(|_| random.byte()).collect();
+    if random.next() % 4 != 0 {
+        packet[0] = random.pick(&MESSAGE_TYPES);
+        packet[5] = 0;
+        packet[8] = random.pick(&LEVEL_PARAMETERS);
+        packet[9] = 0;
+        if random.next() % 2 == 0 {
+            // dwLength matching the packet
+            let length = (step[1] as usize % 65).saturating_sub(10) as u32;
+            packet[1..5].copy_from_slice(&length.to_le_bytes());
+        }
+    }
+    step.extend_from_slice(&packet);
+    step
+}
+
+#[test]
+fn random_transfers() {
+    let mut device = Device::<Properties>::new().implements_escape();
+    let mut random = Random(0x5eed_1234);
+
+    for _ in 0..300 {
+        let steps = 1 + random.next() as usize % 40;
+        let data: Vec<u8> = (0..steps).flat_map(|_| random_step(&mut random)).collect();
+        device.run_steps(&data);
+        device.check_recovers();
+    }
+}
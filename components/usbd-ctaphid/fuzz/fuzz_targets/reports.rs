@@ -0,0 +1,26 @@
+//! Feeds arbitrary report sequences to the CTAPHID class, see `Device::run_steps`.
+//!
+//! `HidInterchange` can only be claimed once, so all inputs share one device.
+//! Each input ends by checking the device recovers, which also resets it for the next.
+//!
+//! Run with `cargo fuzz run reports` in `components/usbd-ctaphid`.
+#![no_main]
+
+use std::cell::RefCell;
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+thread_local! {
+    static DEVICE: RefCell<common::Device> = RefCell::new(common::Device::new());
+}
+
+fuzz_target!(|data: &[u8]| {
+    DEVICE.with(|device| {
+        let mut device = device.borrow_mut();
+        device.run_steps(data);
+        device.check_recovers();
+    });
+});
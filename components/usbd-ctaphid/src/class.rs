@@ -8,9 +8,10 @@ use crate::{
     types::Status,
     constants::{INTERRUPT_POLL_MILLISECONDS, PACKET_SIZE},
     pipe::Pipe,
+    transport::{self, Packet, Transport},
 };
 
-use ctaphid_dispatch::types::HidInterchange;
+use ctaphid_dispatch::{command::Commands, types::HidInterchange};
 
 use usb_device::{
     bus::{InterfaceNumber, UsbBus, UsbBusAllocator},
@@ -19,12 +20,48 @@ use usb_device::{
     descriptor::{DescriptorWriter},
     endpoint::{EndpointAddress, EndpointIn, EndpointOut},
     Result as UsbResult,
+    UsbError,
 };
 
+/// The interrupt endpoints, as transport for the pipe.
+pub struct Endpoints<'alloc, Bus: UsbBus> {
+    read_endpoint: EndpointOut<'alloc, Bus>,
+    write_endpoint: EndpointIn<'alloc, Bus>,
+}
+
+impl<'alloc, Bus: UsbBus> Endpoints<'alloc, Bus> {
+    pub fn read_address(&self) -> EndpointAddress {
+        self.read_endpoint.address()
+    }
+
+    pub fn write_address(&self) -> EndpointAddress {
+        self.write_endpoint.address()
+    }
+}
+
+impl<'alloc, Bus: UsbBus> Transport for Endpoints<'alloc, Bus> {
+    fn read(&mut self, packet: &mut Packet) -> Result<usize, transport::Error> {
+        // usb-device lists WouldBlock or BufferOverflow as possible errors.
+        self.read_endpoint.read(packet).map_err(|error| match error {
+            UsbError::WouldBlock => transport::Error::WouldBlock,
+            _ => transport::Error::Failed,
+        })
+    }
+
+    fn write(&mut self, packet: &Packet) -> Result<(), transport::Error> {
+        match self.write_endpoint.write(packet) {
+            Ok(PACKET_SIZE) => Ok(()),
+            Err(UsbError::WouldBlock) => Err(transport::Error::WouldBlock),
+            // short write, or something weird
+            _ => Err(transport::Error::Failed),
+        }
+    }
+}
+
 /// Packet-level implementation of the CTAPHID protocol.
 pub struct CtapHid<'alloc, Bus: UsbBus> {
     interface: InterfaceNumber,
-    pipe: Pipe<'alloc, Bus>,
+    pipe: Pipe<Endpoints<'alloc, Bus>>,
 }
 
 impl<'alloc, Bus> CtapHid<'alloc, Bus>
@@ -41,7 +78,8 @@ where
         let write_endpoint: EndpointIn<'alloc, Bus> =
             allocate.interrupt(PACKET_SIZE as u16, INTERRUPT_POLL_MILLISECONDS);
 
-        let pipe = Pipe::new(read_endpoint, write_endpoint, interchange, initial_milliseconds);
+        let endpoints = Endpoints { read_endpoint, write_endpoint };
+        let pipe = Pipe::new(endpoints, interchange, initial_milliseconds);
 
         Self {
             interface: allocate.interface(),
@@ -49,26 +87,19 @@ where
         }
     }
     
-    /// Indicate in INIT response that Wink command is implemented.
-    pub fn implements_wink(mut self) -> Self {
-        self.pipe.implements |= 0x01;
+    /// Set the device version in the INIT response, e.g. from the firmware version.
+    pub fn device_version(mut self, major: u8, minor: u8, build: u8) -> Self {
+        self.pipe = self.pipe.device_version(major, minor, build);
         self
     }
 
-    /// Indicate in INIT response that RawMsg command is implemented.
-    pub fn implements_ctap1(mut self) -> Self {
-        self.pipe.implements &= !0x80;
-        self
-    }
-
-    /// Indicate in INIT response that Cbor command is implemented.
-    pub fn implements_ctap2(mut self) -> Self {
-        self.pipe.implements |= 0x04;
-        self
+    /// Derive the capabilities in the INIT response from the commands the apps registered to.
+    pub fn set_commands(&mut self, commands: Commands) {
+        self.pipe.set_commands(commands);
     }
 
     // implement DerefMut<Target = Pipe> instead
-    pub fn pipe(&mut self) -> &mut Pipe<'alloc, Bus> {
+    pub fn pipe(&mut self) -> &mut Pipe<Endpoints<'alloc, Bus>> {
         &mut self.pipe
     }
 
@@ -116,8 +147,8 @@ const HID_REPORT_DESCRIPTOR: u8 = 0x22;
 
 // cf. https://git.io/Jebh8
 // integers are little-endian
-const FIDO_HID_REPORT_DESCRIPTOR_LENGTH: usize = 34;
-const FIDO_HID_REPORT_DESCRIPTOR: [u8; FIDO_HID_REPORT_DESCRIPTOR_LENGTH] = [
+pub const FIDO_HID_REPORT_DESCRIPTOR_LENGTH: usize = 34;
+pub const FIDO_HID_REPORT_DESCRIPTOR: [u8; FIDO_HID_REPORT_DESCRIPTOR_LENGTH] = [
     // Usage page (vendor defined): 0xF1D0 (FIDO_USAGE_PAGE)
     0x06, 0xD0, 0xF1,
     // Usage ID (vendor defined): 0x1 (FIDO_USAGE_CTAPHID)
@@ -182,8 +213,9 @@ where Bus: UsbBus
             FIDO_HID_REPORT_DESCRIPTOR_LENGTH as u8, 0x00, // 1st HID report descriptor length in bytes as u16-be
         ])?;
 
-        writer.endpoint(&self.pipe.read_endpoint())?;
-        writer.endpoint(&self.pipe.write_endpoint())?;
+        let endpoints = self.pipe.transport();
+        writer.endpoint(&endpoints.read_endpoint)?;
+        writer.endpoint(&endpoints.write_endpoint)?;
 
         Ok(())
     }
@@ -200,14 +232,14 @@ where Bus: UsbBus
     // called when endpoint with given address received a packet
     // TODO: should misbehaving clients be blacklisted?
     fn endpoint_out(&mut self, addr: EndpointAddress) {
-        if addr == self.pipe.read_address() {
+        if addr == self.pipe.transport().read_address() {
             self.pipe.read_and_handle_packet();
         }
     }
 
     // called when endpoint with given address sent a packet
     fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
-        if addr == self.pipe.write_address() {
+        if addr == self.pipe.transport().write_address() {
             self.pipe.maybe_write_packet();
         }
     }
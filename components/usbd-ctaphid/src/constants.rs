@@ -5,3 +5,12 @@ pub const PACKET_SIZE: usize = 64;
 
 // 7609 bytes
 pub const MESSAGE_SIZE: usize = PACKET_SIZE - 7 + 128 * (PACKET_SIZE - 5);
+
+// channels remembered at once, the least recently used one is reclaimed
+pub type MAX_CHANNELS = heapless::consts::U8;
+
+// channels expire this long after INIT allocated or last re-synchronized them
+pub const CHANNEL_LIFETIME_SECONDS: u32 = 60 * 60;
+
+// CTAPHID_LOCK lasts at most this long
+pub const MAX_LOCK_SECONDS: u8 = 10;
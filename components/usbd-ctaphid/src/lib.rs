@@ -20,5 +20,7 @@ pub mod constants;
 pub mod class;
 pub use class::CtapHid;
 pub mod pipe;
+pub mod transport;
+pub use transport::Transport;
 pub mod types;
 
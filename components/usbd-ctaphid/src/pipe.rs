@@ -9,7 +9,11 @@ In the case of multiple clients, the first to get through its initialization
 packet in device idle state locks the device for other channels (they will
 receive busy errors).
 
-No state is maintained between transactions.
+Apart from the channels allocated via INIT, and a possible CTAPHID_LOCK,
+no state is maintained between transactions.
+
+The pipe does not depend on USB, it reads and writes packets via a
+[`Transport`](crate::transport::Transport).
 */
 
 use core::convert::TryInto;
@@ -18,7 +22,7 @@ use core::convert::TryFrom;
 // pub type ContactlessInterchange = iso14443::types::ApduInterchange;
 
 use ctaphid_dispatch::types::HidInterchange;
-use ctaphid_dispatch::command::Command;
+use ctaphid_dispatch::command::{Command, Commands};
 
 use ctap_types::{
     authenticator::Error as AuthenticatorError,
@@ -28,21 +32,17 @@ use ctap_types::{
 
 use interchange::Requester;
 
-// use serde::Serialize;
-use usb_device::{
-    bus::{UsbBus},
-    endpoint::{EndpointAddress, EndpointIn, EndpointOut},
-    UsbError,
-    // Result as UsbResult,
-};
-
 use crate::{
     constants::{
+        CHANNEL_LIFETIME_SECONDS,
+        MAX_CHANNELS,
+        MAX_LOCK_SECONDS,
         // 7609
         MESSAGE_SIZE,
         // 64
         PACKET_SIZE,
     },
+    transport::{self, Packet, Transport},
     types::KeepaliveStatus,
 };
 
@@ -81,6 +81,29 @@ impl Response {
     }
 }
 
+const BROADCAST_CHANNEL: u32 = 0xffff_ffff;
+
+// capability flags in the INIT response
+const CAPABILITY_WINK: u8 = 0x01;
+const CAPABILITY_CBOR: u8 = 0x04;
+const CAPABILITY_NMSG: u8 = 0x08;
+
+/// A channel allocated via INIT on the broadcast channel.
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub struct Channel {
+    id: u32,
+    // when the channel was allocated, or last re-synchronized
+    allocated: u32,
+}
+
+/// CTAPHID_LOCK: the channel has exclusive access to the device.
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub struct Lock {
+    channel: u32,
+    // milliseconds until the lock is released
+    remaining: u32,
+}
+
 #[derive(Copy,Clone,Debug,Eq,PartialEq)]
 pub struct MessageState {
     // sequence number of next continuation packet
@@ -127,10 +150,9 @@ pub enum State {
     Sending((Response, MessageState)),
 }
 
-pub struct Pipe<'alloc, Bus: UsbBus> {
+pub struct Pipe<T: Transport> {
 
-    read_endpoint: EndpointOut<'alloc, Bus>,
-    write_endpoint: EndpointIn<'alloc, Bus>,
+    transport: T,
     state: State,
 
     interchange: Requester<HidInterchange>,
@@ -142,8 +164,16 @@ pub struct Pipe<'alloc, Bus: UsbBus> {
     // TODO: move into "app"
     last_channel: u32,
 
-    // Indicator of implemented commands in INIT response.
-    pub(crate) implements: u8,
+    // allocated channels, least recently used first
+    channels: heapless::Vec<Channel, MAX_CHANNELS>,
+
+    lock: Option<Lock>,
+
+    // capability flags in the INIT response, see `set_commands`
+    capabilities: u8,
+
+    // major, minor and build device version number in the INIT response
+    version: [u8; 3],
 
     // timestamp that gets used for timing out CID's
     pub(crate) last_milliseconds: u32,
@@ -152,60 +182,130 @@ pub struct Pipe<'alloc, Bus: UsbBus> {
     started_processing: bool,
 }
 
-impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
+impl<T: Transport> Pipe<T> {
 
     // pub fn borrow_mut_authenticator(&mut self) -> &mut Authenticator {
     //     &mut self.authenticator
     // }
 
-    pub(crate) fn new(
-        read_endpoint: EndpointOut<'alloc, Bus>,
-        write_endpoint: EndpointIn<'alloc, Bus>,
+    pub fn new(
+        transport: T,
         interchange: Requester<HidInterchange>,
         initial_milliseconds: u32,
     ) -> Self
     {
         Self {
-            read_endpoint,
-            write_endpoint,
+            transport,
             state: State::Idle,
             interchange,
             buffer: [0u8; MESSAGE_SIZE],
             last_channel: 0,
+            channels: heapless::Vec::new(),
+            lock: None,
             // Default to nothing implemented.
-            implements: 0x80,
+            capabilities: CAPABILITY_NMSG,
+            version: [0; 3],
             last_milliseconds: initial_milliseconds,
             started_processing: false,
         }
     }
 
-    pub fn read_address(&self) -> EndpointAddress {
-        self.read_endpoint.address()
+    /// Set the device version in the INIT response, e.g. from the firmware version.
+    pub fn device_version(mut self, major: u8, minor: u8, build: u8) -> Self {
+        self.version = [major, minor, build];
+        self
+    }
+
+    /// Derive the capabilities in the INIT response from the commands the apps
+    /// registered to, see `ctaphid_dispatch::dispatch::Dispatch::commands`.
+    pub fn set_commands(&mut self, commands: Commands) {
+        self.capabilities = 0;
+        if commands.contains(Command::Wink) {
+            self.capabilities |= CAPABILITY_WINK;
+        }
+        if commands.contains(Command::Cbor) {
+            self.capabilities |= CAPABILITY_CBOR;
+        }
+        if !commands.contains(Command::Msg) {
+            self.capabilities |= CAPABILITY_NMSG;
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
     }
 
-    pub fn write_address(&self) -> EndpointAddress {
-        self.write_endpoint.address()
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
     }
 
-    // used to generate the configuration descriptors
-    pub(crate) fn read_endpoint(&self) -> &EndpointOut<'alloc, Bus> {
-        &self.read_endpoint
+    /// Allocates a new channel, reclaiming the least recently used one if the table is full.
+    fn allocate_channel(&mut self) -> u32 {
+        self.last_channel = self.last_channel.wrapping_add(1);
+        // 0 is reserved, and the broadcast channel is not for allocation
+        if self.last_channel == 0 || self.last_channel == BROADCAST_CHANNEL {
+            self.last_channel = 1;
+        }
+
+        let channel = Channel { id: self.last_channel, allocated: self.last_milliseconds };
+        if let Err(channel) = self.channels.push(channel) {
+            // replace the least recently used channel, keeping the order
+            self.channels.rotate_left(1);
+            let last = self.channels.last_mut().unwrap();
+            info!("reclaiming channel {}", last.id);
+            if self.lock.map(|lock| lock.channel) == Some(last.id) {
+                self.lock = None;
+            }
+            *last = channel;
+        }
+        self.last_channel
     }
 
-    // used to generate the configuration descriptors
-    pub(crate) fn write_endpoint(&self) -> &EndpointIn<'alloc, Bus> {
-        &self.write_endpoint
+    /// Drops the channels allocated (or re-synchronized) too long ago,
+    /// except for the one in a transaction, and the one holding the lock.
+    fn expire_channels(&mut self, milliseconds: u32) {
+        let active = match self.state {
+            State::Receiving((request, _)) | State::WaitingOnAuthenticator(request) => Some(request.channel),
+            State::WaitingToSend(response) | State::Sending((response, _)) => Some(response.channel),
+            State::Idle => None,
+        };
+        let locked = self.lock.map(|lock| lock.channel);
+        let lifetime = CHANNEL_LIFETIME_SECONDS * 1000;
+
+        let channels: heapless::Vec<Channel, MAX_CHANNELS> = self.channels.iter().copied()
+            .filter(|channel| {
+                let expired = milliseconds.wrapping_sub(channel.allocated) > lifetime
+                    && Some(channel.id) != active && Some(channel.id) != locked;
+                if expired {
+                    info!("channel {} expired", channel.id);
+                }
+                !expired
+            })
+            .collect();
+        self.channels = channels;
+    }
+
+    /// Checks whether the channel is allocated, and marks it as most recently used.
+    fn use_channel(&mut self, id: u32) -> Option<Channel> {
+        let position = self.channels.iter().position(|channel| channel.id == id)?;
+        self.channels[position..].rotate_left(1);
+        self.channels.last().copied()
     }
 
     fn cancel_ongoing_activity(&mut self) {
         // Remove response if it's there
         if let Some(_response) = self.interchange.take_response() {
+            ctaphid_dispatch::cancel::reset();
         } else {
             // Cancel if there's a request or processing
             match self.interchange.state() {
-                interchange::State::Requested |
+                interchange::State::Requested => {
+                    self.interchange.cancel().ok();
+                }
                 interchange::State::BuildingResponse => {
-                    self.interchange.cancel().expect("canceled");
+                    self.interchange.cancel().ok();
+                    // the app is still working on it
+                    ctaphid_dispatch::cancel::cancel();
                 }
                 _ => {}
             }
@@ -214,14 +314,11 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
         self.state = State::Idle;
     }
 
-    /// This method handles CTAP packets (64 bytes), until it has assembled
-    /// a CTAP message, with which it then calls `dispatch_message`.
-    ///
-    /// During these calls, we can be in states: Idle, Receiving, Dispatching.
-    pub(crate) fn read_and_handle_packet(&mut self) {
+    /// Reads a packet from the transport, and handles it.
+    pub fn read_and_handle_packet(&mut self) {
         // info_now!("got a packet!");
         let mut packet = [0u8; PACKET_SIZE];
-        match self.read_endpoint.read(&mut packet) {
+        match self.transport.read(&mut packet) {
             Ok(PACKET_SIZE) => {},
             Ok(_size) => {
                 // error handling?
@@ -233,15 +330,21 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                 info!("error unexpected size {}", _size);
                 return;
             },
-            // usb-device lists WouldBlock or BufferOverflow as possible errors.
-            // both should not occur here, and we can't do anything anyway.
-            // Err(UsbError::WouldBlock) => { return; },
-            // Err(UsbError::BufferOverflow) => { return; },
+            // should not occur when called for a received packet,
+            // and we can't do anything anyway.
             Err(_error) => {
                 info!("error no {}", _error as i32);
                 return;
             },
         };
+        self.handle_packet(&packet);
+    }
+
+    /// This method handles CTAP packets (64 bytes), until it has assembled
+    /// a CTAP message, with which it then calls `dispatch_message`.
+    ///
+    /// During these calls, we can be in states: Idle, Receiving, Dispatching.
+    pub fn handle_packet(&mut self, packet: &Packet) {
         info!(">> ");
         info!("{}", hex_str!(&packet[..16]));
 
@@ -273,6 +376,43 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
             let timestamp = self.last_milliseconds;
             let current_request = Request { channel, command, length, timestamp};
 
+            // INIT allocates channels on the broadcast channel, and re-synchronizes
+            // allocated ones, everything else needs an allocated channel.
+            let known = channel == BROADCAST_CHANNEL && command == Command::Init
+                || self.use_channel(channel).is_some();
+            if !known {
+                info!("unknown channel {}", channel);
+                self.send_error_now(current_request, AuthenticatorError::InvalidChannel);
+                return;
+            }
+
+            if let Some(lock) = self.lock {
+                if channel != lock.channel {
+                    info!("locked by channel {}", lock.channel);
+                    self.send_error_now(current_request, AuthenticatorError::ChannelBusy);
+                    return;
+                }
+            }
+
+            // CTAPHID_CANCEL has no response of its own, the app answers the
+            // pending request early (CTAP2_ERR_KEEPALIVE_CANCEL for CBOR).
+            if command == Command::Cancel {
+                match self.state {
+                    State::WaitingOnAuthenticator(request) if request.channel == channel => {
+                        info!("cancel");
+                        ctaphid_dispatch::cancel::cancel();
+                    }
+                    State::Receiving((request, _message_state)) if request.channel == channel => {
+                        info!("cancel while receiving");
+                        self.state = State::Idle;
+                    }
+                    _ => {
+                        info!("nothing to cancel");
+                    }
+                }
+                return;
+            }
+
             if !(self.state == State::Idle) {
                 let request = match self.state {
                     State::WaitingOnAuthenticator(request) => {
@@ -332,6 +472,13 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
             // case of continuation packet
             match self.state {
                 State::Receiving((request, mut message_state)) => {
+                    // other channels must not disturb the transaction
+                    if channel != request.channel {
+                        // info_now!("wrong channel for continuation packet, expected {} received {}",
+                        //           request.channel, channel);
+                        info!("Ignore invalid channel");
+                        return;
+                    }
                     let sequence = packet[4];
                     // info_now!("receiving continuation packet {}", sequence);
                     if sequence != message_state.next_sequence {
@@ -342,13 +489,6 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                         self.start_sending_error(request, AuthenticatorError::InvalidSeq);
                         return;
                     }
-                    if channel != request.channel {
-                        // error handling?
-                        // info_now!("wrong channel for continuation packet, expected {} received {}",
-                        //           request.channel, channel);
-                        info!("Ignore invalid channel");
-                        return;
-                    }
 
                     let payload_length = request.length as usize;
                     if message_state.transmitted + (PACKET_SIZE - 5) < payload_length {
@@ -382,9 +522,22 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
         // so its up to the device to timeout those transactions.
         let last = self.last_milliseconds;
         self.last_milliseconds = milliseconds;
+
+        if let Some(lock) = self.lock.as_mut() {
+            // as for requests, lapses (and timer wraparounds) are forgiven
+            let elapsed = milliseconds.wrapping_sub(last);
+            if elapsed <= 200 {
+                lock.remaining = lock.remaining.saturating_sub(elapsed);
+            }
+            if lock.remaining == 0 {
+                info!("lock of channel {} expired", lock.channel);
+                self.lock = None;
+            }
+        }
+        self.expire_channels(milliseconds);
         match &mut self.state {
             State::Receiving((request, _message_state)) => {
-                if (milliseconds - last) > 200 {
+                if milliseconds.wrapping_sub(last) > 200 {
                     // If there's a lapse in `check_timeout(...)` getting called (e.g. due to logging),
                     // this could lead to inaccurate timestamps on requests.  So we'll
                     // just "forgive" requests temporarily if this happens.
@@ -426,15 +579,23 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                         self.start_sending_error(request, AuthenticatorError::InvalidChannel);
                     },
 
-                    // broadcast channel ID - request for assignment
+                    // broadcast channel ID - request for assignment,
+                    // otherwise re-synchronization of an allocated channel
                     cid => {
                         if request.length != 8 {
-                            // error
-                            info!("Invalid length for init.  ignore.");
+                            info!("Invalid length for init.");
+                            self.start_sending_error(request, AuthenticatorError::InvalidLength);
                         } else {
-                            self.last_channel += 1;
+                            let channel = if cid == BROADCAST_CHANNEL {
+                                self.allocate_channel()
+                            } else {
+                                if let Some(channel) = self.channels.iter_mut().find(|channel| channel.id == cid) {
+                                    channel.allocated = self.last_milliseconds;
+                                }
+                                cid
+                            };
                             // info_now!(
-                            //     "assigned channel {}", self.last_channel);
+                            //     "assigned channel {}", channel);
                             let _nonce = &self.buffer[..8];
                             let response = Response {
                                 channel: cid,
@@ -442,21 +603,16 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                                 length: 17,
                             };
 
-                            self.buffer[8..12].copy_from_slice(&self.last_channel.to_be_bytes());
+                            self.buffer[8..12].copy_from_slice(&channel.to_be_bytes());
                             // CTAPHID protocol version
                             self.buffer[12] = 2;
-                            // major device version number
-                            self.buffer[13] = 0;
-                            // minor device version number
-                            self.buffer[14] = 0;
-                            // build device version number
-                            self.buffer[15] = 0;
+                            // major, minor and build device version number
+                            self.buffer[13..16].copy_from_slice(&self.version);
                             // capabilities flags
                             // 0x1: implements WINK
                             // 0x4: implements CBOR
                             // 0x8: does not implement MSG
-                            // self.buffer[16] = 0x01 | 0x08;
-                            self.buffer[16] = self.implements;
+                            self.buffer[16] = self.capabilities;
                             self.start_sending(response);
                         }
                     },
@@ -468,6 +624,25 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                 self.start_sending(response);
             },
 
+            // exclusive access for up to 10 seconds, or release with 0 seconds
+            Command::Lock => {
+                if request.length != 1 {
+                    self.start_sending_error(request, AuthenticatorError::InvalidLength);
+                    return;
+                }
+                let seconds = self.buffer[0];
+                if seconds > MAX_LOCK_SECONDS {
+                    self.start_sending_error(request, AuthenticatorError::InvalidParameter);
+                    return;
+                }
+                self.lock = match seconds {
+                    0 => None,
+                    seconds => Some(Lock { channel: request.channel, remaining: seconds as u32 * 1000 }),
+                };
+                let response = Response::from_request_and_size(request, 0);
+                self.start_sending(response);
+            },
+
             _ => {
                 if self.interchange.state() == interchange::State::Responded {
                     info!("dumping stale response");
@@ -477,6 +652,8 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                     &(request.command, heapless_bytes::Bytes::try_from_slice(&self.buffer[..request.length as usize]).unwrap())
                 ) {
                     Ok(_) => {
+                        // a cancellation of an earlier request does not carry over
+                        ctaphid_dispatch::cancel::new_request();
                         self.state = State::WaitingOnAuthenticator(request);
                         self.started_processing = true;
                     },
@@ -484,7 +661,7 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                         // busy
                         info_now!("STATE: {:?}", self.interchange.state());
                         info!("can't handle more than one authenticator request at a time.");
-                        self.send_error_now(request, AuthenticatorError::ChannelBusy);
+                        self.start_sending_error(request, AuthenticatorError::ChannelBusy);
                     }
                 }
             },
@@ -516,7 +693,7 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                 packet[7] = KeepaliveStatus::Processing as u8;
             }
 
-            self.write_endpoint.write(&packet).ok();
+            self.transport.write(&packet).ok();
 
             true
         } else {
@@ -530,6 +707,7 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
 
 
             if let Some(response) = self.interchange.take_response() {
+                ctaphid_dispatch::cancel::reset();
                 match response {
 
                     Err(ctaphid_dispatch::app::Error::InvalidCommand) => {
@@ -543,6 +721,7 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                     }
                     Err(ctaphid_dispatch::app::Error::NoResponse) => {
                         info!("Got waiting noresponse from authenticator??");
+                        self.state = State::Idle;
                     }
 
                     Ok(message) => {
@@ -582,8 +761,10 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
         self.buffer[0] = last_first_byte;
     }
 
-    // called from poll, and when a packet has been sent
-    pub(crate) fn maybe_write_packet(&mut self) {
+    /// Writes the next packet of a pending response, if any.
+    ///
+    /// Called from poll, and when a packet has been sent.
+    pub fn maybe_write_packet(&mut self) {
 
         match self.state {
             State::WaitingToSend(response) => {
@@ -599,7 +780,6 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                 if fits_in_one_packet {
                     packet[7..][..response.length as usize]
                         .copy_from_slice( &self.buffer[..response.length as usize]);
-                    self.state = State::Idle;
                 } else {
                     packet[7..].copy_from_slice(&self.buffer[..PACKET_SIZE - 7]);
                 }
@@ -607,19 +787,20 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                 // try actually sending
                 // info_now!("attempting to write init packet {:?}, {:?}",
                 //           &packet[..32], &packet[32..]);
-                let result = self.write_endpoint.write(&packet);
+                let result = self.transport.write(&packet);
 
                 match result {
-                    Err(UsbError::WouldBlock) => {
+                    Err(transport::Error::WouldBlock) => {
                         // fine, can't write try later
                         // this shouldn't happen probably
                         info!("hid usb WouldBlock");
                     },
                     Err(_) => {
-                        // info_now!("weird USB errrorrr");
-                        panic!("unexpected error writing packet!");
+                        // the response is lost, the host will retry
+                        info!("error writing packet, dropping the response");
+                        self.state = State::Idle;
                     },
-                    Ok(PACKET_SIZE) => {
+                    Ok(()) => {
                         // goodie, this worked
                         if fits_in_one_packet {
                             self.state = State::Idle;
@@ -633,10 +814,6 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                             // info_now!("State: {:?}", &self.state);
                         }
                     },
-                    Ok(_) => {
-                        // info_now!("short write");
-                        panic!("unexpected size writing packet!");
-                    },
                 };
             },
 
@@ -660,20 +837,21 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                 // try actually sending
                 // info_now!("attempting to write cont packet {:?}, {:?}",
                 //           &packet[..32], &packet[32..]);
-                let result = self.write_endpoint.write(&packet);
+                let result = self.transport.write(&packet);
 
                 match result {
-                    Err(UsbError::WouldBlock) => {
+                    Err(transport::Error::WouldBlock) => {
                         // fine, can't write try later
                         // this shouldn't happen probably
                         // info_now!("can't send seq {}, write endpoint busy",
                         //           message_state.next_sequence);
                     },
                     Err(_) => {
-                        // info_now!("weird USB error");
-                        panic!("unexpected error writing packet!");
+                        // the response is lost, the host will retry
+                        info!("error writing packet, dropping the response");
+                        self.state = State::Idle;
                     },
-                    Ok(PACKET_SIZE) => {
+                    Ok(()) => {
                         // goodie, this worked
                         if last_packet {
                             self.state = State::Idle;
@@ -686,10 +864,6 @@ impl<'alloc, Bus: UsbBus> Pipe<'alloc, Bus> {
                             self.state = State::Sending((response, message_state));
                         }
                     },
-                    Ok(_) => {
-                        debug!("short write");
-                        panic!("unexpected size writing packet!");
-                    },
                 };
             },
 
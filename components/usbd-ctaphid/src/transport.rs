@@ -0,0 +1,30 @@
+/*!
+The link CTAPHID packets travel over.
+
+The [`Pipe`](crate::pipe::Pipe) does the framing, reassembly and keepalives, and only
+needs a way to read and write 64 byte packets. Over USB, these are the HID reports of
+the interrupt endpoints of [`CtapHid`](crate::CtapHid), but any other link carrying
+whole packets works, e.g. a UART or a socket to the PC runner.
+*/
+
+use crate::constants::PACKET_SIZE;
+
+pub type Packet = [u8; PACKET_SIZE];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// Nothing to read, or the previous packet has not been sent yet.
+    WouldBlock,
+    /// The link failed otherwise.
+    Failed,
+}
+
+pub trait Transport {
+    /// Reads the next packet into `packet`, returning its length.
+    ///
+    /// Packets of other lengths than `PACKET_SIZE` are ignored.
+    fn read(&mut self, packet: &mut Packet) -> Result<usize, Error>;
+
+    /// Writes a packet, or returns `WouldBlock` if it has to be retried later.
+    fn write(&mut self, packet: &Packet) -> Result<(), Error>;
+}
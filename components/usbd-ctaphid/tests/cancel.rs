@@ -0,0 +1,94 @@
+//! CTAPHID_CANCEL signals the app working on the request, and has no response of its own.
+
+mod common;
+
+use common::*;
+use ctaphid_dispatch::{cancel, types::Command};
+
+// CTAP2_ERR_KEEPALIVE_CANCEL
+const KEEPALIVE_CANCEL: u8 = 0x2D;
+
+#[test]
+fn cancel_requests() {
+    let mut device = Device::new();
+    let channel = device.init();
+    let other = device.init();
+
+    // nothing to cancel
+    device.send_message(channel, CANCEL, &[]);
+    assert_eq!(device.receive(), None);
+    assert!(!cancel::is_cancelled());
+
+    device.send_message(channel, CBOR, &[0x01, 0xa0]);
+    assert_eq!(device.app_request(), Some((Command::Cbor, vec![0x01, 0xa0])));
+
+    // only the channel of the request can cancel it
+    device.send_message(other, CANCEL, &[]);
+    assert_eq!(device.receive(), None);
+    assert!(!cancel::is_cancelled());
+
+    device.send_message(channel, CANCEL, &[]);
+    assert_eq!(device.receive(), None);
+    assert!(cancel::is_cancelled());
+
+    // the app still answers the request
+    device.app_respond_with(&[KEEPALIVE_CANCEL]);
+    assert_eq!(
+        device.receive_message(),
+        Some(Response { channel, command: CBOR, payload: vec![KEEPALIVE_CANCEL] }),
+    );
+    assert!(!cancel::is_cancelled());
+
+    // a request that is not fully received yet is dropped
+    let mut packet = channel.to_be_bytes().to_vec();
+    packet.extend_from_slice(&[CBOR, 0, 100]);
+    packet.extend_from_slice(&[0x42; 57]);
+    device.send(&packet);
+    device.send_message(channel, CANCEL, &[]);
+    let mut continuation = channel.to_be_bytes().to_vec();
+    continuation.extend_from_slice(&[0]);
+    continuation.extend_from_slice(&[0x42; 43]);
+    device.send(&continuation);
+    assert_eq!(device.receive(), None);
+    assert_eq!(device.app_request(), None);
+
+    // a new request starts out uncancelled
+    device.send_message(channel, CBOR, &[0x04]);
+    assert_eq!(device.app_request(), Some((Command::Cbor, vec![0x04])));
+    assert!(!cancel::is_cancelled());
+    device.app_respond_with(&[0x00]);
+    assert_eq!(
+        device.receive_message(),
+        Some(Response { channel, command: CBOR, payload: vec![0x00] }),
+    );
+
+    device.check_ping(channel, b"still there");
+
+    cancel_then_resync(&mut device, channel);
+}
+
+/// A cancellation followed by INIT on the channel sticks to the cancelled request.
+fn cancel_then_resync(device: &mut Device, channel: u32) {
+    device.send_message(channel, CBOR, &[0x01, 0xa0]);
+    assert_eq!(device.app_request(), Some((Command::Cbor, vec![0x01, 0xa0])));
+    device.send_message(channel, CANCEL, &[]);
+    assert!(cancel::is_cancelled());
+    assert_eq!(device.init_on(channel), Ok(channel));
+
+    // the app has not given up yet, the old request stays cancelled
+    device.expect_error(channel, CBOR, &[0x01, 0xa0], ERR_CHANNEL_BUSY);
+    assert!(cancel::is_cancelled());
+    device.app_respond_cancelled(&[KEEPALIVE_CANCEL]);
+    assert_eq!(device.receive(), None);
+
+    // the next request, which may need the user's presence, is not
+    device.send_message(channel, CBOR, &[0x01, 0xa0]);
+    assert_eq!(device.app_request(), Some((Command::Cbor, vec![0x01, 0xa0])));
+    assert!(!cancel::is_cancelled());
+    device.app_respond_with(&[0x00]);
+    assert_eq!(
+        device.receive_message(),
+        Some(Response { channel, command: CBOR, payload: vec![0x00] }),
+    );
+    assert!(!cancel::is_cancelled());
+}
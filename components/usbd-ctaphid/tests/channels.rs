@@ -0,0 +1,70 @@
+//! Channel allocation and CTAPHID_LOCK.
+
+mod common;
+
+use common::*;
+use ctaphid_dispatch::types::Command;
+use usbd_ctaphid::constants::CHANNEL_LIFETIME_SECONDS;
+
+#[test]
+fn channels_and_lock() {
+    let mut device = Device::new();
+
+    // nothing allocated yet
+    device.expect_error(0x1234_5678, PING, b"ping", ERR_INVALID_CHANNEL);
+    device.expect_error(BROADCAST, PING, b"ping", ERR_INVALID_CHANNEL);
+    assert_eq!(device.init_on(0x1234_5678), Err(ERR_INVALID_CHANNEL));
+
+    let first = device.init();
+    let second = device.init();
+    assert_ne!(first, second);
+    device.check_ping(first, b"first");
+    device.check_ping(second, b"second");
+
+    // re-synchronizing keeps the channel
+    assert_eq!(device.init_on(first), Ok(first));
+
+    // the table is bounded, the least recently used channel goes first
+    device.check_ping(first, b"keep");
+    let more: Vec<u32> = (0..7).map(|_| device.init()).collect();
+    device.expect_error(second, PING, b"gone", ERR_INVALID_CHANNEL);
+    device.check_ping(first, b"still there");
+    for channel in more.iter().copied() {
+        device.check_ping(channel, b"here");
+    }
+
+    // lock parameters
+    device.expect_error(first, LOCK, &[], ERR_INVALID_LEN);
+    device.expect_error(first, LOCK, &[11], ERR_INVALID_PAR);
+
+    // a lock gives exclusive access
+    device.send_message(first, LOCK, &[2]);
+    assert_eq!(device.receive_message(), Some(Response { channel: first, command: LOCK, payload: vec![] }));
+    device.expect_error(more[0], PING, b"locked", ERR_CHANNEL_BUSY);
+    device.expect_error(BROADCAST, INIT, &[0; 8], ERR_CHANNEL_BUSY);
+    device.check_ping(first, b"owner");
+
+    device.send_message(first, CBOR, &[0x04]);
+    assert_eq!(device.app_request(), Some((Command::Cbor, vec![0x04])));
+    device.app_respond_with(&[0x00]);
+    assert_eq!(device.receive_message(), Some(Response { channel: first, command: CBOR, payload: vec![0x00] }));
+
+    // ...until it expires
+    device.advance(2_100);
+    device.check_ping(more[0], b"unlocked");
+
+    // or is released
+    device.send_message(first, LOCK, &[10]);
+    assert_eq!(device.receive_message(), Some(Response { channel: first, command: LOCK, payload: vec![] }));
+    device.expect_error(more[1], PING, b"locked", ERR_CHANNEL_BUSY);
+    device.send_message(first, LOCK, &[0]);
+    assert_eq!(device.receive_message(), Some(Response { channel: first, command: LOCK, payload: vec![] }));
+    device.check_ping(more[1], b"unlocked");
+
+    // channels expire some time after INIT, unless re-synchronized meanwhile
+    device.advance(CHANNEL_LIFETIME_SECONDS * 1000 - 1_000);
+    assert_eq!(device.init_on(first), Ok(first));
+    device.advance(2_000);
+    device.expect_error(more[1], PING, b"expired", ERR_INVALID_CHANNEL);
+    device.check_ping(first, b"re-synchronized");
+}
@@ -0,0 +1,292 @@
+//! Drives a `CtapHid` class through a `MockBus`, playing both the host
+//! and the app behind the interchange.
+//!
+//! `HidInterchange` can only be claimed once per process, so every test file
+//! sets up a single device.
+//!
+//! The fuzz targets in `fuzz/` include this module, too.
+#![allow(dead_code)]
+
+use std::convert::TryInto;
+
+use ctaphid_dispatch::types::{Command, HidInterchange, InterchangeResponse, Message};
+use interchange::{Interchange, Responder};
+use test_support::MockBus;
+use usb_device::{
+    bus::UsbBusAllocator,
+    class::UsbClass,
+    endpoint::EndpointAddress,
+    UsbDirection,
+};
+use usbd_ctaphid::{constants::{MAX_LOCK_SECONDS, MESSAGE_SIZE, PACKET_SIZE}, CtapHid};
+
+pub const BROADCAST: u32 = 0xffff_ffff;
+
+// CTAPHID commands, as sent in the initialization packet
+pub const PING: u8 = 0x81;
+pub const MSG: u8 = 0x83;
+pub const LOCK: u8 = 0x84;
+pub const INIT: u8 = 0x86;
+pub const WINK: u8 = 0x88;
+pub const CBOR: u8 = 0x90;
+pub const CANCEL: u8 = 0x91;
+pub const KEEPALIVE: u8 = 0xBB;
+pub const ERROR: u8 = 0xBF;
+
+// CTAPHID_ERROR codes
+pub const ERR_INVALID_CMD: u8 = 0x01;
+pub const ERR_INVALID_PAR: u8 = 0x02;
+pub const ERR_INVALID_LEN: u8 = 0x03;
+pub const ERR_INVALID_SEQ: u8 = 0x04;
+pub const ERR_CHANNEL_BUSY: u8 = 0x06;
+pub const ERR_INVALID_CHANNEL: u8 = 0x0B;
+
+pub struct Device {
+    ctaphid: CtapHid<'static, MockBus>,
+    app: Responder<HidInterchange>,
+    bus: MockBus,
+    milliseconds: u32,
+    // channels seen in INIT responses, for `run_steps`
+    channels: Vec<u32>,
+}
+
+/// Longest possible response, in reports: an initialization and 128 continuation packets.
+const MAX_RESPONSE_REPORTS: usize = 129;
+
+// the class allocates its interrupt OUT endpoint first, then interrupt IN
+fn read_address() -> EndpointAddress {
+    EndpointAddress::from_parts(1, UsbDirection::Out)
+}
+
+fn write_address() -> EndpointAddress {
+    EndpointAddress::from_parts(1, UsbDirection::In)
+}
+
+/// A CTAPHID response: channel, command and payload.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Response {
+    pub channel: u32,
+    pub command: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Device {
+    pub fn new() -> Self {
+        let bus = MockBus::default();
+        let allocator = Box::leak(Box::new(UsbBusAllocator::new(bus.clone())));
+        let (requester, app) = HidInterchange::claim().expect("interchange already claimed");
+        let mut ctaphid = CtapHid::new(allocator, requester, 0);
+        ctaphid.set_commands([Command::Cbor, Command::Msg, Command::Wink].iter().copied().collect());
+        Self { ctaphid, app, bus, milliseconds: 0, channels: Vec::new() }
+    }
+
+    /// Lets time pass, in steps as the runners' idle loops would.
+    pub fn advance(&mut self, milliseconds: u32) {
+        let end = self.milliseconds + milliseconds;
+        while self.milliseconds < end {
+            self.milliseconds = core::cmp::min(end, self.milliseconds + 10);
+            self.ctaphid.check_timeout(self.milliseconds);
+        }
+    }
+
+    /// Sends one report, padded to the packet size.
+    pub fn send(&mut self, packet: &[u8]) {
+        assert!(packet.len() <= PACKET_SIZE);
+        let mut report = packet.to_vec();
+        report.resize(PACKET_SIZE, 0);
+        self.bus.host_write(read_address(), &report);
+        self.ctaphid.endpoint_out(read_address());
+    }
+
+    /// Sends a message, split into an initialization and continuation packets.
+    pub fn send_message(&mut self, channel: u32, command: u8, payload: &[u8]) {
+        let mut packet = channel.to_be_bytes().to_vec();
+        packet.push(command);
+        packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        let first = core::cmp::min(payload.len(), PACKET_SIZE - 7);
+        packet.extend_from_slice(&payload[..first]);
+        self.send(&packet);
+
+        for (sequence, chunk) in payload[first..].chunks(PACKET_SIZE - 5).enumerate() {
+            let mut packet = channel.to_be_bytes().to_vec();
+            packet.push(sequence as u8);
+            packet.extend_from_slice(chunk);
+            self.send(&packet);
+        }
+    }
+
+    /// Receives one report, if there is one.
+    pub fn receive(&mut self) -> Option<Vec<u8>> {
+        self.ctaphid.check_for_app_response();
+        let packet = self.bus.host_read(write_address())?;
+        self.ctaphid.endpoint_in_complete(write_address());
+        Some(packet)
+    }
+
+    /// Receives one message, skipping keepalives.
+    pub fn receive_message(&mut self) -> Option<Response> {
+        let mut packet = self.receive()?;
+        while packet[4] == KEEPALIVE {
+            packet = self.receive()?;
+        }
+        let channel = u32::from_be_bytes(packet[..4].try_into().unwrap());
+        let command = packet[4];
+        let length = u16::from_be_bytes(packet[5..7].try_into().unwrap()) as usize;
+        let mut payload = packet[7..].to_vec();
+        let mut sequence = 0;
+        while payload.len() < length {
+            let packet = self.receive().expect("message ends early");
+            assert_eq!(&packet[..4], &channel.to_be_bytes());
+            assert_eq!(packet[4], sequence);
+            sequence += 1;
+            payload.extend_from_slice(&packet[5..]);
+        }
+        payload.truncate(length);
+        Some(Response { channel, command, payload })
+    }
+
+    /// Takes the request the app received, if any.
+    pub fn app_request(&mut self) -> Option<(Command, Vec<u8>)> {
+        self.app.take_request().map(|(command, message)| (command, message.to_vec()))
+    }
+
+    pub fn app_respond(&mut self, response: InterchangeResponse) {
+        self.app.respond(&response).ok().unwrap();
+    }
+
+    pub fn app_respond_with(&mut self, payload: &[u8]) {
+        self.app_respond(Ok(Message::try_from_slice(payload).unwrap()));
+    }
+
+    /// Answers a request that was cancelled meanwhile, which the transport no longer takes.
+    pub fn app_respond_cancelled(&mut self, payload: &[u8]) {
+        let response = Ok(Message::try_from_slice(payload).unwrap());
+        assert!(self.app.respond(&response).is_err());
+        self.app.acknowledge_cancel().unwrap();
+    }
+
+    /// Allocates a channel via INIT on the broadcast channel.
+    pub fn init(&mut self) -> u32 {
+        self.init_on(BROADCAST).expect("INIT failed")
+    }
+
+    /// Sends INIT on the given channel, returning the assigned channel.
+    pub fn init_on(&mut self, channel: u32) -> core::result::Result<u32, u8> {
+        let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+        self.send_message(channel, INIT, &nonce);
+        let response = self.receive_message().expect("no INIT response");
+        assert_eq!(response.channel, channel);
+        if response.command == ERROR {
+            return Err(response.payload[0]);
+        }
+        assert_eq!(response.command, INIT);
+        assert_eq!(response.payload.len(), 17);
+        assert_eq!(&response.payload[..8], &nonce);
+        Ok(u32::from_be_bytes(response.payload[8..12].try_into().unwrap()))
+    }
+
+    /// Sends a request, expecting an error.
+    pub fn expect_error(&mut self, channel: u32, command: u8, payload: &[u8], error: u8) {
+        self.send_message(channel, command, payload);
+        assert_eq!(self.receive_message(), Some(Response { channel, command: ERROR, payload: vec![error] }));
+    }
+
+    /// Checks that a PING on the channel is echoed.
+    pub fn check_ping(&mut self, channel: u32, payload: &[u8]) {
+        self.send_message(channel, PING, payload);
+        assert_eq!(self.receive_message(), Some(Response { channel, command: PING, payload: payload.to_vec() }));
+    }
+
+    /// Answers a pending request like a dispatcher would, and acknowledges cancellations.
+    fn serve_app(&mut self, length: usize) {
+        self.app.acknowledge_cancel().ok();
+        if let Some((_command, message)) = self.app.take_request() {
+            let mut response = message.to_vec();
+            response.resize(length, 0xa5);
+            self.app_respond_with(&response);
+        }
+    }
+
+    /// Receives everything the class has to send, remembering allocated channels.
+    fn drain(&mut self) {
+        // a reply to every report sent, and a long response at most
+        for _ in 0..2 * MAX_RESPONSE_REPORTS {
+            match self.receive() {
+                Some(packet) => {
+                    if packet[..4] == BROADCAST.to_be_bytes() && packet[4] == INIT && packet[5..7] == [0, 17] {
+                        if self.channels.len() == 8 {
+                            self.channels.remove(0);
+                        }
+                        self.channels.push(u32::from_be_bytes(packet[15..19].try_into().unwrap()));
+                    }
+                }
+                None => return,
+            }
+        }
+        panic!("class keeps sending");
+    }
+
+    /// Interprets arbitrary data as a sequence of steps for the class.
+    ///
+    /// Each step is a control byte, followed by a report (zero padded if the data ends).
+    /// The low two bits of the control byte select the step:
+    ///
+    /// - 0: send the report
+    /// - 1: send the report on a channel allocated earlier (or broadcast)
+    /// - 2: the app answers, with a response length taken from the report
+    /// - 3: time passes, as many 10 ms steps as the first report byte says
+    ///
+    /// Unless bit 2 is set, everything the class sends is received after the step.
+    pub fn run_steps(&mut self, data: &[u8]) {
+        for step in data.chunks(1 + PACKET_SIZE) {
+            let control = step[0];
+            let mut report = step[1..].to_vec();
+            report.resize(PACKET_SIZE, 0);
+
+            match control & 0x3 {
+                0 => self.send(&report),
+                1 => {
+                    let channel = match self.channels.len() {
+                        0 => BROADCAST,
+                        n => self.channels[report[0] as usize % n],
+                    };
+                    report[..4].copy_from_slice(&channel.to_be_bytes());
+                    self.send(&report);
+                }
+                2 => {
+                    let length = u16::from_be_bytes([report[0], report[1]]) as usize % (MESSAGE_SIZE + 1);
+                    self.serve_app(length);
+                }
+                _ => {
+                    self.advance(10 * report[0] as u32);
+                    self.ctaphid.send_keepalive(false);
+                }
+            }
+
+            if control & 0x4 == 0 {
+                self.drain();
+            }
+        }
+    }
+
+    /// Checks that the class is usable again, whatever state `run_steps` left it in:
+    /// a resync via INIT on the broadcast channel, PING and an app request succeed.
+    pub fn check_recovers(&mut self) {
+        self.drain();
+        self.serve_app(0);
+        self.drain();
+        // locks and partial requests run out
+        self.advance(1000 * (MAX_LOCK_SECONDS as u32 + 1));
+        self.drain();
+
+        let channel = self.init();
+        self.serve_app(0);
+        self.check_ping(channel, b"recovered");
+
+        self.send_message(channel, CBOR, &[0x04]);
+        assert_eq!(self.app_request(), Some((Command::Cbor, vec![0x04])));
+        self.app_respond_with(&[0x00]);
+        assert_eq!(self.receive_message(), Some(Response { channel, command: CBOR, payload: vec![0x00] }));
+        assert_eq!(self.receive(), None);
+    }
+}
@@ -0,0 +1,45 @@
+//! Random report sequences must neither panic nor leave the class stuck.
+//!
+//! The same steps as the fuzz targets in `fuzz/`, from a fixed seed.
+
+mod common;
+
+use common::*;
+use test_support::Random;
+
+// commands and continuation sequence numbers, with the invalid 0xff
+const COMMAND_BYTES: [u8; 13] = [PING, MSG, LOCK, INIT, WINK, CBOR, CANCEL, KEEPALIVE, ERROR, 0xC0, 0, 1, 0xff];
+// around the packet boundaries, and the maximal message size
+const LENGTHS: [u16; 10] = [0, 1, 8, 57, 58, 116, 117, 1000, 7609, 7610];
+
+/// A step for `Device::run_steps`, mostly well-formed so transactions get somewhere.
+fn random_step(random: &mut Random) -> Vec<u8> {
+    let mut step = vec![random.byte()];
+    let mut report: Vec<u8> = (0..64).map(|_| random.byte()).collect();
+    if random.next() % 4 != 0 {
+        report[4] = random.pick(&COMMAND_BYTES);
+        let length = match random.next() % 3 {
+            0 => random.pick(&LENGTHS),
+            _ => random.next() as u16 % 200,
+        };
+        report[5..7].copy_from_slice(&length.to_be_bytes());
+        if report[4] == LOCK {
+            report[7] = random.byte() % 12;
+        }
+    }
+    step.extend_from_slice(&report);
+    step
+}
+
+#[test]
+fn random_reports() {
+    let mut device = Device::new();
+    let mut random = Random(0x5eed_1234);
+
+    for _ in 0..300 {
+        let steps = 1 + random.next() as usize % 40;
+        let data: Vec<u8> = (0..steps).flat_map(|_| random_step(&mut random)).collect();
+        device.run_steps(&data);
+        device.check_recovers();
+    }
+}
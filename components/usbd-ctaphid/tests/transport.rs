@@ -0,0 +1,182 @@
+//! The pipe over a link other than USB: packets are pushed in or read from
+//! the transport, and written out as the link can take them.
+//!
+//! Also checks the INIT response follows the commands the apps registered to.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::rc::Rc;
+
+use ctaphid_dispatch::{command::VendorCommand, types::{Command, HidInterchange, Message}};
+use interchange::Interchange;
+use usbd_ctaphid::{
+    constants::PACKET_SIZE,
+    pipe::Pipe,
+    transport::{self, Packet, Transport},
+};
+
+const BROADCAST: u32 = 0xffff_ffff;
+
+const PING: u8 = 0x81;
+const INIT: u8 = 0x86;
+const CBOR: u8 = 0x90;
+const KEEPALIVE: u8 = 0xBB;
+
+#[derive(Default)]
+struct Wire {
+    to_device: VecDeque<Packet>,
+    from_device: Vec<Packet>,
+    // the link can't take packets for now
+    busy: bool,
+    // the link fails to take packets
+    failed: bool,
+}
+
+/// A link carrying whole packets, like a UART with framing.
+#[derive(Clone, Default)]
+struct Link {
+    wire: Rc<RefCell<Wire>>,
+}
+
+impl Transport for Link {
+    fn read(&mut self, packet: &mut Packet) -> Result<usize, transport::Error> {
+        *packet = self.wire.borrow_mut().to_device.pop_front().ok_or(transport::Error::WouldBlock)?;
+        Ok(PACKET_SIZE)
+    }
+
+    fn write(&mut self, packet: &Packet) -> Result<(), transport::Error> {
+        let mut wire = self.wire.borrow_mut();
+        if wire.failed {
+            return Err(transport::Error::Failed);
+        }
+        if wire.busy {
+            return Err(transport::Error::WouldBlock);
+        }
+        wire.from_device.push(*packet);
+        Ok(())
+    }
+}
+
+fn packets(channel: u32, command: u8, payload: &[u8]) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    let mut packet = [0u8; PACKET_SIZE];
+    packet[..4].copy_from_slice(&channel.to_be_bytes());
+    packet[4] = command;
+    packet[5..7].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+    let first = core::cmp::min(payload.len(), PACKET_SIZE - 7);
+    packet[7..][..first].copy_from_slice(&payload[..first]);
+    packets.push(packet);
+
+    for (sequence, chunk) in payload[first..].chunks(PACKET_SIZE - 5).enumerate() {
+        let mut packet = [0u8; PACKET_SIZE];
+        packet[..4].copy_from_slice(&channel.to_be_bytes());
+        packet[4] = sequence as u8;
+        packet[5..][..chunk.len()].copy_from_slice(chunk);
+        packets.push(packet);
+    }
+    packets
+}
+
+/// Writes out the rest of a response, one packet at a time, as a link driver would.
+fn flush(pipe: &mut Pipe<Link>) -> Vec<Packet> {
+    loop {
+        let written = pipe.transport().wire.borrow().from_device.len();
+        pipe.maybe_write_packet();
+        if pipe.transport().wire.borrow().from_device.len() == written {
+            break;
+        }
+    }
+    core::mem::take(&mut pipe.transport().wire.borrow_mut().from_device)
+}
+
+/// Reassembles a response, checking channel, command and sequence numbers.
+fn payload(packets: &[Packet], channel: u32, command: u8) -> Vec<u8> {
+    assert_eq!(&packets[0][..4], &channel.to_be_bytes());
+    assert_eq!(packets[0][4], command);
+    let length = u16::from_be_bytes(packets[0][5..7].try_into().unwrap()) as usize;
+    let mut payload = packets[0][7..].to_vec();
+    for (sequence, packet) in packets[1..].iter().enumerate() {
+        assert_eq!(&packet[..4], &channel.to_be_bytes());
+        assert_eq!(packet[4] as usize, sequence);
+        payload.extend_from_slice(&packet[5..]);
+    }
+    assert!(payload.len() >= length && payload.len() < length + PACKET_SIZE - 5);
+    payload.truncate(length);
+    payload
+}
+
+#[test]
+fn pipe_over_link() {
+    let (requester, mut app) = HidInterchange::claim().unwrap();
+    let link = Link::default();
+    let mut pipe = Pipe::new(link.clone(), requester, 0).device_version(1, 2, 3);
+
+    // packets pushed in by the link driver
+    let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+    for packet in packets(BROADCAST, INIT, &nonce) {
+        pipe.handle_packet(&packet);
+    }
+    let init = payload(&flush(&mut pipe), BROADCAST, INIT);
+    assert_eq!(&init[..8], &nonce);
+    // protocol and device version, and capabilities: none registered yet, so no MSG
+    assert_eq!(&init[12..], &[2, 1, 2, 3, 0x08]);
+    let channel = u32::from_be_bytes(init[8..12].try_into().unwrap());
+
+    // capabilities follow the apps' commands
+    pipe.set_commands([Command::Cbor, Command::Vendor(VendorCommand::H51)].iter().copied().collect());
+    for packet in packets(channel, INIT, &nonce) {
+        pipe.handle_packet(&packet);
+    }
+    let init = payload(&flush(&mut pipe), channel, INIT);
+    assert_eq!(&init[8..12], &channel.to_be_bytes());
+    assert_eq!(init[16], 0x04 | 0x08);
+    pipe.set_commands([Command::Cbor, Command::Msg, Command::Wink].iter().copied().collect());
+    for packet in packets(channel, INIT, &nonce) {
+        pipe.handle_packet(&packet);
+    }
+    assert_eq!(payload(&flush(&mut pipe), channel, INIT)[16], 0x01 | 0x04);
+
+    // packets read from the link, spanning several packets
+    let ping: Vec<u8> = (0..200).map(|i| i as u8).collect();
+    link.wire.borrow_mut().to_device.extend(packets(channel, PING, &ping));
+    while !link.wire.borrow().to_device.is_empty() {
+        pipe.read_and_handle_packet();
+    }
+    assert_eq!(payload(&flush(&mut pipe), channel, PING), ping);
+
+    // an app request, with a keepalive while waiting
+    for packet in packets(channel, CBOR, &[0x04]) {
+        pipe.handle_packet(&packet);
+    }
+    assert!(pipe.did_start_processing());
+    assert_eq!(app.take_request().map(|(command, request)| (command, request.to_vec())), Some((Command::Cbor, vec![0x04])));
+    assert!(pipe.send_keepalive(true));
+    let keepalive = flush(&mut pipe);
+    assert_eq!(keepalive.len(), 1);
+    assert_eq!(&keepalive[0][..8], &[&channel.to_be_bytes()[..], &[KEEPALIVE, 0, 1, 2]].concat()[..]);
+
+    // the link is busy when the response is ready, it goes out later
+    link.wire.borrow_mut().busy = true;
+    let response: Vec<u8> = (0..100).map(|i| !i as u8).collect();
+    app.respond(&Ok(Message::try_from_slice(&response).unwrap())).ok().unwrap();
+    pipe.handle_response();
+    pipe.maybe_write_packet();
+    assert!(flush(&mut pipe).is_empty());
+    link.wire.borrow_mut().busy = false;
+    assert_eq!(payload(&flush(&mut pipe), channel, CBOR), response);
+    assert!(!pipe.send_keepalive(false));
+
+    // a failing link drops the response, and the pipe carries on
+    link.wire.borrow_mut().failed = true;
+    for packet in packets(channel, PING, &ping) {
+        pipe.handle_packet(&packet);
+    }
+    assert!(flush(&mut pipe).is_empty());
+    link.wire.borrow_mut().failed = false;
+    assert!(flush(&mut pipe).is_empty());
+    for packet in packets(channel, PING, &[0x42]) {
+        pipe.handle_packet(&packet);
+    }
+    assert_eq!(payload(&flush(&mut pipe), channel, PING), [0x42]);
+}
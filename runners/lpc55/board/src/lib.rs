@@ -30,6 +30,7 @@ pub use nk3xn as specifics;
 pub use specifics::{
     button::ThreeButtons,
     led::RgbLed,
+    BOARD,
 };
 
 pub mod clock_controller;
@@ -1,2 +1,4 @@
 pub mod button;
 pub mod led;
+
+pub const BOARD: &str = "solo2";
@@ -6,7 +6,7 @@ use crate::hal::{
     typestates::init_state,
 };
 use crate::traits::buttons::{Press, Edge};
-use crate::traits::rgb_led::RgbLed;
+use crate::traits::rgb_led::{Intensities, RgbLed};
 use trussed::platform::{
     ui,
     reboot,
@@ -52,6 +52,7 @@ RGB: RgbLed,
     rtc: Rtc<init_state::Enabled>,
     buttons: Option<BUTTONS>,
     rgb: Option<RGB>,
+    brightness: u8,
 }
 
 impl<BUTTONS, RGB> UserInterface<BUTTONS, RGB>
@@ -61,12 +62,26 @@ RGB: RgbLed,
 {
     pub fn new(rtc: Rtc<init_state::Enabled>, _buttons: Option<BUTTONS>, rgb: Option<RGB>) -> Self {
         #[cfg(not(feature = "no-buttons"))]
-        let ui = Self { rtc, buttons: _buttons, rgb };
+        let ui = Self { rtc, buttons: _buttons, rgb, brightness: 255 };
         #[cfg(feature = "no-buttons")]
-        let ui = Self { rtc, buttons: None, rgb };
+        let ui = Self { rtc, buttons: None, rgb, brightness: 255 };
 
         ui
     }
+
+    /// Scales all colors, 255 is full brightness and 0 turns the LED off.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
+    fn set_color(&mut self, color: u32) {
+        let brightness = self.brightness as u32;
+        if let Some(rgb) = &mut self.rgb {
+            let scale = |intensity: u8| (intensity as u32 * brightness / 255) as u8;
+            let Intensities { red, green, blue } = color.into();
+            rgb.set(Intensities { red: scale(red), green: scale(green), blue: scale(blue) });
+        }
+    }
 }
 
 impl<BUTTONS, RGB> trussed::platform::UserInterface for UserInterface<BUTTONS,RGB>
@@ -75,6 +90,13 @@ BUTTONS: Press + Edge,
 RGB: RgbLed,
 {
     fn check_user_presence(&mut self) -> consent::Level {
+        // Trussed only ends a consent request on presence, so a request the host
+        // cancelled (CTAPHID_CANCEL) ends with a level that ends every wait.
+        // The apps check for the cancellation afterwards, it never counts as consent.
+        if ctaphid_dispatch::cancel::is_cancelled() {
+            return consent::Level::Strong;
+        }
+
         match &mut self.buttons {
             Some(buttons) => {
 
@@ -104,24 +126,24 @@ RGB: RgbLed,
 
     fn set_status(&mut self, status: ui::Status) {
 
-        if let Some(rgb) = &mut self.rgb {
+        if self.rgb.is_some() {
 
             match status {
                 ui::Status::Idle => {
                     // green
-                    rgb.set(0x00_ff_02.into());
+                    self.set_color(0x00_ff_02);
                 },
                 ui::Status::Processing => {
                     // teal
-                    rgb.set(0x00_ff_5a.into());
+                    self.set_color(0x00_ff_5a);
                 }
                 ui::Status::WaitingForUserPresence => {
                     // orange
-                    rgb.set(0xff_7e_00.into());
+                    self.set_color(0xff_7e_00);
                 },
                 ui::Status::Error => {
                     // Red
-                    rgb.set(0xff_00_00.into());
+                    self.set_color(0xff_00_00);
                 },
             }
 
@@ -149,7 +171,7 @@ RGB: RgbLed,
             // crate::logger::info!("time: {}", time).ok();
             // crate::logger::info!("amp: {}", hex!(amplitude)).ok();
             // crate::logger::info!("color: {}", hex!(color)).ok();
-            self.rgb.as_mut().unwrap().set(color.into());
+            self.set_color(color);
         }
     }
 
@@ -65,6 +65,8 @@ pub struct Config {
 /// For initializing the LPC55 runner safely.
 pub struct Initializer {
     is_nfc_passive: bool,
+    secure_firmware_version: u32,
+    led_brightness: u8,
     // hal: hal::Peripherals,
     syscon: hal::Syscon,
     pmc: hal::Pmc,
@@ -144,6 +146,8 @@ impl Initializer {
         info_now!("making initializer");
         Self {
             is_nfc_passive,
+            secure_firmware_version: 0,
+            led_brightness: 255,
 
             syscon,
             pmc,
@@ -203,7 +207,8 @@ impl Initializer {
         false
     }
 
-    fn validate_cfpa(pfr: &mut Pfr<hal::Enabled>, current_version_maybe: Option<u32>, require_prince: bool) {
+    /// Returns the secure firmware version in the CFPA, after any update.
+    fn validate_cfpa(pfr: &mut Pfr<hal::Enabled>, current_version_maybe: Option<u32>, require_prince: bool) -> u32 {
         let mut cfpa = pfr.read_latest_cfpa().unwrap();
         if let Some(current_version) = current_version_maybe {
             if cfpa.secure_fw_version < current_version || cfpa.ns_fw_version < current_version {
@@ -225,6 +230,8 @@ impl Initializer {
                 cfpa.key_provisioned(hal::peripherals::pfr::KeyType::PrinceRegion2)
             );
         }
+
+        cfpa.secure_fw_version
     }
 
     fn try_enable_fm11nc08 <T: Ctimer<hal::Enabled>>(
@@ -272,6 +279,8 @@ impl Initializer {
         let mut iocon = iocon.enabled(syscon);
         let mut gpio = gpio.enabled(syscon);
 
+        // The IRQ pin is only read here, to tell whether the NFC field powers the device.
+        // Its interrupt is enabled with the NFC chip, once the settings are applied.
         let nfc_irq = if self.config.nfc_enabled {
             let (new_iocon, nfc_irq) = self.enable_low_speed_for_passive_nfc(iocon, &mut gpio);
             iocon = new_iocon;
@@ -393,7 +402,7 @@ impl Initializer {
         }
 
         let mut pfr = pfr.enabled(&clocks).unwrap();
-        Self::validate_cfpa(&mut pfr, self.config.secure_firmware_version, self.config.require_prince);
+        self.secure_firmware_version = Self::validate_cfpa(&mut pfr, self.config.secure_firmware_version, self.config.require_prince);
 
         stages::Basic {
             delay_timer,
@@ -511,11 +520,17 @@ impl Initializer {
 
             // our USB classes (must be allocated in order that they're passed in `.poll(...)` later!)
             let ccid = usbd_ccid::Ccid::new(usb_bus, contact_requester);
+            // Escape commands are answered by the admin app, see `idle`
+            #[cfg(feature = "admin-app")]
+            let ccid = ccid.implements_escape();
             let current_time = basic_stage.perf_timer.elapsed().0/1000;
+            // the capabilities follow the apps, see `init_board`
             let ctaphid = usbd_ctaphid::CtapHid::new(usb_bus, ctaphid_requester, current_time)
-                .implements_ctap1()
-                .implements_ctap2()
-                .implements_wink();
+                .device_version(
+                    build_constants::CARGO_PKG_VERSION_MAJOR,
+                    build_constants::CARGO_PKG_VERSION_MINOR,
+                    build_constants::CARGO_PKG_VERSION_PATCH,
+                );
 
             let serial = usbd_serial::SerialPort::new(usb_bus);
 
@@ -595,10 +610,11 @@ impl Initializer {
         }
     }
 
+    /// The NFC stage is polled while mounting, if it is initialized already.
     pub fn initialize_filesystem(&mut self,
         clock_stage: &mut stages::Clock,
         basic_stage: &mut stages::Basic,
-        nfc_stage: &mut stages::Nfc,
+        nfc_stage: Option<&mut stages::Nfc>,
         flash_stage: &mut stages::Flash,
     ) -> stages::Filesystem {
         use littlefs2::fs::{Allocation, Filesystem};
@@ -606,6 +622,7 @@ impl Initializer {
 
         let syscon = &mut self.syscon;
         let pmc = &mut self.pmc;
+        let mut iso14443 = nfc_stage.and_then(|nfc_stage| nfc_stage.iso14443.as_mut());
         info_now!("making fs");
 
         #[allow(unused_mut)]
@@ -651,7 +668,7 @@ impl Initializer {
 
         let store = types::Store::claim().unwrap();
 
-        if let Some(iso14443) = &mut nfc_stage.iso14443 { iso14443.poll(); }
+        if let Some(iso14443) = iso14443.as_mut() { iso14443.poll(); }
 
         unsafe {
 
@@ -704,7 +721,7 @@ impl Initializer {
                 .reconfigure(clock_stage.clocks, pmc, syscon) };
         }
 
-        if let Some(iso14443) = &mut nfc_stage.iso14443 { iso14443.poll(); }
+        if let Some(iso14443) = iso14443.as_mut() { iso14443.poll(); }
 
         // Cancel any possible outstanding use in delay timer
         basic_stage.delay_timer.cancel().ok();
@@ -715,6 +732,20 @@ impl Initializer {
         }
     }
 
+    /// Applies the settings the admin app keeps in the store, as far as they concern booting.
+    ///
+    /// NFC can't be disabled when it powers the device. Otherwise, this must
+    /// come before `initialize_nfc`, which enables the NFC interrupt.
+    #[cfg(feature = "admin-app")]
+    pub fn apply_settings(&mut self, clock_stage: &mut stages::Clock, filesystem_stage: &stages::Filesystem) {
+        let settings = admin_app::Config::load(filesystem_stage.store);
+        if !settings.nfc && !self.is_nfc_passive {
+            self.config.nfc_enabled = false;
+            clock_stage.nfc_irq = None;
+        }
+        self.led_brightness = settings.led_brightness;
+    }
+
     pub fn initialize_trussed(
         &mut self,
         clock_stage: &mut stages::Clock,
@@ -739,6 +770,7 @@ impl Initializer {
         let three_buttons = basic_stage.three_buttons.take();
 
         let mut solobee_interface = board::trussed::UserInterface::new(rtc, three_buttons, rgb);
+        solobee_interface.set_brightness(self.led_brightness);
         solobee_interface.set_status(trussed::platform::ui::Status::Idle);
 
         let rng = flash_stage.rng.take().unwrap();
@@ -789,13 +821,21 @@ impl Initializer {
             perf_timer,
             pfr,
         );
-        let mut nfc_stage = self.initialize_nfc(
-            &mut clock_stage,
-            &mut basic_stage,
-            flexcomm0,
-            mux,
-            pint
-        );
+        // When powered by the NFC field, NFC comes first, to be polled while mounting.
+        // Otherwise it waits for the settings, which may disable it.
+        let mut nfc_peripherals = Some((flexcomm0, mux, pint));
+        let mut nfc_stage = if self.is_nfc_passive {
+            let (flexcomm0, mux, pint) = nfc_peripherals.take().unwrap();
+            Some(self.initialize_nfc(
+                &mut clock_stage,
+                &mut basic_stage,
+                flexcomm0,
+                mux,
+                pint
+            ))
+        } else {
+            None
+        };
 
         let mut usb_stage = self.initialize_usb(
             &mut clock_stage,
@@ -803,7 +843,6 @@ impl Initializer {
             usbhs,
             usbfs
         );
-        let interfaces_stage = self.initialize_interfaces(&mut nfc_stage, &mut usb_stage);
         let mut flash_stage = self.initialize_flash(
             rng,
             prince,
@@ -812,10 +851,28 @@ impl Initializer {
         let mut filesystem_stage = self.initialize_filesystem(
             &mut clock_stage,
             &mut basic_stage,
-            &mut nfc_stage,
+            nfc_stage.as_mut(),
             &mut flash_stage,
         );
 
+        #[cfg(feature = "admin-app")]
+        self.apply_settings(&mut clock_stage, &filesystem_stage);
+
+        let mut nfc_stage = match nfc_stage {
+            Some(nfc_stage) => nfc_stage,
+            None => {
+                let (flexcomm0, mux, pint) = nfc_peripherals.take().unwrap();
+                self.initialize_nfc(
+                    &mut clock_stage,
+                    &mut basic_stage,
+                    flexcomm0,
+                    mux,
+                    pint
+                )
+            }
+        };
+        let interfaces_stage = self.initialize_interfaces(&mut nfc_stage, &mut usb_stage);
+
         let trussed = self.initialize_trussed(
             &mut clock_stage,
             &mut basic_stage,
@@ -866,6 +923,11 @@ impl Initializer {
         return self.is_nfc_passive;
     }
 
+    /// The secure firmware version in the CFPA.  Requires the basic initialization stage have been done.
+    pub fn secure_firmware_version(&self, _basic_stage: &stages::Basic) -> u32 {
+        self.secure_firmware_version
+    }
+
 }
 
 
@@ -117,7 +117,21 @@ pub fn init_board(
         hal.rtc,
     );
 
-    let _is_passive_mode = initializer.is_in_passive_operation(&everything.clock);
+    #[cfg_attr(not(any(feature = "admin-app", feature = "provisioner-app")), allow(unused_variables))]
+    let is_passive_mode = initializer.is_in_passive_operation(&everything.clock);
+    #[cfg(feature = "admin-app")]
+    let admin_info = admin_app::DeviceInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        features: types::APP_FEATURES,
+        board: board::BOARD,
+        prince: require_prince,
+        nfc: match (&everything.nfc.iso14443, is_passive_mode) {
+            (None, _) => admin_app::Nfc::Disabled,
+            (Some(_), false) => admin_app::Nfc::Enabled,
+            (Some(_), true) => admin_app::Nfc::Passive,
+        },
+        secure_firmware_version: Some(initializer.secure_firmware_version(&everything.basic)),
+    };
     let clock_controller = initializer.get_dynamic_clock_control(&mut everything.clock, &mut everything.basic);
 
     // rgb.turn_off();
@@ -128,18 +142,30 @@ pub fn init_board(
     #[cfg(feature = "provisioner-app")]
     let internal_fs = everything.filesystem.internal_storage_fs;
 
-    let apps = types::Apps::new(
+    let mut apps = types::Apps::new(
         &mut everything.trussed,
+        everything.nfc.iso14443.is_some(),
+        #[cfg(feature = "admin-app")]
+        types::AdminNonPortable {
+            store: everything.filesystem.store.clone(),
+            info: admin_info,
+        },
         #[cfg(feature = "provisioner-app")]
         {
             types::ProvisionerNonPortable {
                 store,
                 stolen_filesystem: internal_fs.as_mut().unwrap(),
-                nfc_powered: _is_passive_mode,
+                nfc_powered: is_passive_mode,
             }
         }
     );
 
+    // advertise in CTAPHID INIT what the apps (selected by features) implement
+    if let Some(usb_classes) = everything.usb.usb_classes.as_mut() {
+        let commands = apps.ctaphid_dispatch(|apps| types::CtaphidDispatch::commands(apps));
+        usb_classes.ctaphid.set_commands(commands);
+    }
+
     (
         everything.interfaces.apdu_dispatch,
         everything.interfaces.ctaphid_dispatch,
@@ -168,6 +168,10 @@ const APP: () = {
                     usb_classes.ctaphid.check_timeout(time/1000);
                     usb_classes.poll();
 
+                    // the admin app answers without syscalls, so this is fine under the lock
+                    #[cfg(feature = "admin-app")]
+                    usb_classes.ccid.handle_escape(&mut apps.admin);
+
                     match usb_classes.ccid.did_start_processing() {
                         usbd_ccid::types::Status::ReceivedData(milliseconds) => {
                             schedule.ccid_wait_extension(
@@ -104,16 +104,24 @@ impl admin_app::Reboot for Lpc55Reboot {
         hal::drivers::flash::FlashGordon::new(flash).erase_page(0).ok();
         hal::raw::SCB::sys_reset()
     }
+    fn reboot_after(f: impl FnOnce()) -> ! {
+        // The Trussed service runs in the OS_EVENT interrupt, pended by the apps' syscalls.
+        // Masked until the reboot, it can't run, whatever else interrupts the admin app
+        // (the periodic UI updates don't touch the filesystems).
+        hal::raw::NVIC::mask(hal::raw::Interrupt::OS_EVENT);
+        f();
+        hal::raw::SCB::sys_reset()
+    }
 }
 
 #[cfg(feature = "admin-app")]
-pub type AdminApp = admin_app::App<TrussedClient, Lpc55Reboot>;
+pub type AdminApp = admin_app::App<TrussedClient, Lpc55Reboot, Store>;
 #[cfg(feature = "piv-authenticator")]
 pub type PivApp = piv_authenticator::Authenticator<apdu_dispatch::command::Size, TrussedClient>;
 #[cfg(feature = "oath-authenticator")]
 pub type OathApp = oath_authenticator::Authenticator<TrussedClient>;
 #[cfg(feature = "fido-authenticator")]
-pub type FidoApp = dispatch_fido::Fido<fido_authenticator::NonSilentAuthenticator, TrussedClient>;
+pub type FidoApp = dispatch_fido::Fido<dispatch_fido::CancellableUserPresence, TrussedClient>;
 #[cfg(feature = "ndef-app")]
 pub type NdefApp = ndef_app::App<'static>;
 #[cfg(feature = "provisioner-app")]
@@ -121,11 +129,28 @@ pub type ProvisionerApp = provisioner_app::Provisioner<Store, FlashStorage, Trus
 
 use apdu_dispatch::{App as ApduApp, command::Size as CommandSize, response::Size as ResponseSize};
 use ctaphid_dispatch::app::{App as CtaphidApp};
+use heapless::Vec;
 
 pub type DynamicClockController = board::clock_controller::DynamicClockController;
 pub type NfcWaitExtender = timer::Timer<ctimer::Ctimer0<hal::typestates::init_state::Enabled>>;
 pub type PerformanceTimer = timer::Timer<ctimer::Ctimer4<hal::typestates::init_state::Enabled>>;
 
+/// The apps compiled in, as selected by Cargo features.
+pub const APP_FEATURES: &[&str] = &[
+    #[cfg(feature = "admin-app")]
+    "admin-app",
+    #[cfg(feature = "fido-authenticator")]
+    "fido-authenticator",
+    #[cfg(feature = "ndef-app")]
+    "ndef-app",
+    #[cfg(feature = "oath-authenticator")]
+    "oath-authenticator",
+    #[cfg(feature = "piv-authenticator")]
+    "piv-authenticator",
+    #[cfg(feature = "provisioner-app")]
+    "provisioner-app",
+];
+
 pub trait TrussedApp: Sized {
 
     /// non-portable resources needed by this Trussed app
@@ -137,22 +162,24 @@ pub trait TrussedApp: Sized {
     fn with_client(trussed: TrussedClient, non_portable: Self::NonPortable) -> Self;
 
     fn with(trussed: &mut trussed::Service<crate::Board>, non_portable: Self::NonPortable) -> Self {
-        let (trussed_requester, trussed_responder) = trussed::pipe::TrussedInterchange::claim()
-            .expect("could not setup TrussedInterchange");
+        Self::with_client(trussed_client(trussed, Self::CLIENT_ID), non_portable)
+    }
+}
 
-        let mut client_id = littlefs2::path::PathBuf::new();
-        client_id.push(Self::CLIENT_ID.try_into().unwrap());
-        assert!(trussed.add_endpoint(trussed_responder, client_id).is_ok());
+/// Connects a new client to the Trussed service.
+fn trussed_client(trussed: &mut trussed::Service<crate::Board>, client_id: &[u8]) -> TrussedClient {
+    let (trussed_requester, trussed_responder) = trussed::pipe::TrussedInterchange::claim()
+        .expect("could not setup TrussedInterchange");
 
-        let syscaller = Syscall::default();
-        let trussed_client = TrussedClient::new(
-            trussed_requester,
-            syscaller,
-        );
+    let mut client_id_path = littlefs2::path::PathBuf::new();
+    client_id_path.push(client_id.try_into().unwrap());
+    assert!(trussed.add_endpoint(trussed_responder, client_id_path).is_ok());
 
-        let app = Self::with_client(trussed_client, non_portable);
-        app
-    }
+    let syscaller = Syscall::default();
+    TrussedClient::new(
+        trussed_requester,
+        syscaller,
+    )
 }
 
 #[cfg(feature = "oath-authenticator")]
@@ -179,10 +206,9 @@ impl TrussedApp for PivApp {
 impl TrussedApp for AdminApp {
     const CLIENT_ID: &'static [u8] = b"admin\0";
 
-    // TODO: declare uuid + version
-    type NonPortable = ();
-    fn with_client(trussed: TrussedClient, _: ()) -> Self {
-        Self::new(trussed, hal::uuid(), build_constants::CARGO_PKG_VERSION)
+    type NonPortable = AdminNonPortable;
+    fn with_client(trussed: TrussedClient, AdminNonPortable { store, info }: Self::NonPortable) -> Self {
+        Self::new(trussed, store, hal::uuid(), build_constants::CARGO_PKG_VERSION, info)
     }
 }
 
@@ -190,17 +216,31 @@ impl TrussedApp for AdminApp {
 impl TrussedApp for FidoApp {
     const CLIENT_ID: &'static [u8] = b"fido\0";
 
-    type NonPortable = ();
-    fn with_client(trussed: TrussedClient, _: ()) -> Self {
+    type NonPortable = FidoNonPortable;
+    fn with_client(trussed: TrussedClient, FidoNonPortable { selection, nfc }: Self::NonPortable) -> Self {
         let authnr = fido_authenticator::Authenticator::new(
             trussed,
-            fido_authenticator::NonSilentAuthenticator {},
+            dispatch_fido::CancellableUserPresence {},
         );
 
-        Self::new(authnr)
+        Self::new(authnr, dispatch_fido::CancellableUserPresence {}, selection, nfc)
     }
 }
 
+#[cfg(feature = "fido-authenticator")]
+pub struct FidoNonPortable {
+    /// The client for the user presence check of authenticatorSelection.
+    pub selection: TrussedClient,
+    /// Whether the device has an NFC interface.
+    pub nfc: bool,
+}
+
+#[cfg(feature = "admin-app")]
+pub struct AdminNonPortable {
+    pub store: Store,
+    pub info: admin_app::DeviceInfo,
+}
+
 pub struct ProvisionerNonPortable {
     pub store: Store,
     pub stolen_filesystem: &'static mut FlashStorage,
@@ -219,6 +259,8 @@ impl TrussedApp for ProvisionerApp {
 }
 
 pub struct Apps {
+    /// Which apps the admin app's settings enable.
+    enabled: admin_app::config::Apps,
     #[cfg(feature = "admin-app")]
     pub admin: AdminApp,
     #[cfg(feature = "fido-authenticator")]
@@ -234,15 +276,29 @@ pub struct Apps {
 }
 
 impl Apps {
+    /// `nfc` tells whether the device has an NFC interface.
+    #[cfg_attr(not(feature = "fido-authenticator"), allow(unused_variables))]
     pub fn new(
         trussed: &mut trussed::Service<crate::Board>,
+        nfc: bool,
+        #[cfg(feature = "admin-app")]
+        admin: AdminNonPortable,
         #[cfg(feature = "provisioner-app")]
         provisioner: ProvisionerNonPortable
     ) -> Self {
+        // without the admin app, there are no settings, and all apps answer
+        #[cfg(not(feature = "admin-app"))]
+        let enabled = admin_app::config::Apps::default();
+        #[cfg(feature = "admin-app")]
+        let enabled = admin_app::Config::load(admin.store).apps;
         #[cfg(feature = "admin-app")]
-        let admin = AdminApp::with(trussed, ());
+        let admin = AdminApp::with(trussed, admin);
         #[cfg(feature = "fido-authenticator")]
-        let fido = FidoApp::with(trussed, ());
+        // the selection client counts towards trussed/clients-N, too
+        let fido = {
+            let selection = trussed_client(trussed, b"fidoup\0");
+            FidoApp::with(trussed, FidoNonPortable { selection, nfc: nfc })
+        };
         #[cfg(feature = "oath-authenticator")]
         let oath = OathApp::with(trussed, ());
         #[cfg(feature = "piv-authenticator")]
@@ -253,6 +309,7 @@ impl Apps {
         let provisioner = ProvisionerApp::with(trussed, provisioner);
 
         Self {
+            enabled,
             #[cfg(feature = "admin-app")]
             admin,
             #[cfg(feature = "fido-authenticator")]
@@ -274,31 +331,41 @@ impl Apps {
                 ApduApp<CommandSize, ResponseSize>
             ]) -> T
     {
-        f(&mut [
-            #[cfg(feature = "ndef-app")]
-            &mut self.ndef,
-            #[cfg(feature = "piv-authenticator")]
-            &mut self.piv,
-            #[cfg(feature = "oath-authenticator")]
-            &mut self.oath,
-            #[cfg(feature = "fido-authenticator")]
-            &mut self.fido,
-            #[cfg(feature = "admin-app")]
-            &mut self.admin,
-            #[cfg(feature = "provisioner-app")]
-            &mut self.provisioner,
-        ])
+        let mut apps: Vec<&mut dyn ApduApp<CommandSize, ResponseSize>, heapless::consts::U8> = Vec::new();
+        #[cfg(feature = "ndef-app")]
+        if self.enabled.ndef {
+            apps.push(&mut self.ndef).ok();
+        }
+        #[cfg(feature = "piv-authenticator")]
+        if self.enabled.piv {
+            apps.push(&mut self.piv).ok();
+        }
+        #[cfg(feature = "oath-authenticator")]
+        if self.enabled.oath {
+            apps.push(&mut self.oath).ok();
+        }
+        #[cfg(feature = "fido-authenticator")]
+        if self.enabled.fido {
+            apps.push(&mut self.fido).ok();
+        }
+        #[cfg(feature = "admin-app")]
+        apps.push(&mut self.admin).ok();
+        #[cfg(feature = "provisioner-app")]
+        apps.push(&mut self.provisioner).ok();
+        f(&mut apps)
     }
 
     pub fn ctaphid_dispatch<F, T>(&mut self, f: F) -> T
     where
         F: FnOnce(&mut [&mut dyn CtaphidApp ]) -> T
     {
-        f(&mut [
-            #[cfg(feature = "fido-authenticator")]
-            &mut self.fido,
-            #[cfg(feature = "admin-app")]
-            &mut self.admin,
-        ])
+        let mut apps: Vec<&mut dyn CtaphidApp, heapless::consts::U8> = Vec::new();
+        #[cfg(feature = "fido-authenticator")]
+        if self.enabled.fido {
+            apps.push(&mut self.fido).ok();
+        }
+        #[cfg(feature = "admin-app")]
+        apps.push(&mut self.admin).ok();
+        f(&mut apps)
     }
 }
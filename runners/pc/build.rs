@@ -0,0 +1,53 @@
+use std::{env, fs::File, io::Write, path::Path};
+
+// littlefs2 takes the filesystem geometry as associated constants (and types),
+// so it can't be a command line option. Instead, every `littlefs_params` value
+// can be overridden at build time, e.g. `SOLO_PC_BLOCK_COUNT=512 cargo build`.
+fn parameter<T: std::str::FromStr>(name: &str, default: T) -> T {
+    let variable = format!("SOLO_PC_{}", name);
+    println!("cargo:rerun-if-env-changed={}", variable);
+    match env::var(&variable) {
+        Ok(value) => value.parse().unwrap_or_else(|_| panic!("{} must be a number", variable)),
+        Err(_) => default,
+    }
+}
+
+/// typenum has named types for 0..=1024 and for powers of two.
+fn typenum(name: &str, value: usize) -> String {
+    assert!(value <= 1024 || value.is_power_of_two(), "{} must be at most 1024 or a power of two", name);
+    format!("generic_array::typenum::U{}", value)
+}
+
+fn main() {
+    let read_size: usize = parameter("READ_SIZE", 16);
+    let write_size: usize = parameter("WRITE_SIZE", 512);
+    let block_size: usize = parameter("BLOCK_SIZE", 512);
+    let block_count: usize = parameter("BLOCK_COUNT", 256);
+    // no wear-leveling by default
+    let block_cycles: isize = parameter("BLOCK_CYCLES", -1);
+    let cache_size: usize = parameter("CACHE_SIZE", 512);
+    let lookaheadwords_size: usize = parameter("LOOKAHEADWORDS_SIZE", 16);
+
+    // the constraints littlefs checks in `lfs_init`
+    assert!(read_size > 0 && write_size > 0, "read and write size must be positive");
+    assert!(cache_size % read_size == 0, "cache size must be a multiple of the read size");
+    assert!(cache_size % write_size == 0, "cache size must be a multiple of the write size");
+    assert!(block_size % cache_size == 0, "block size must be a multiple of the cache size");
+    assert!(block_size >= 128, "block size must be at least 128");
+    assert!(block_count >= 2, "block count must be at least 2");
+    assert!(lookaheadwords_size > 0 && lookaheadwords_size % 2 == 0, "lookahead must be a multiple of 8 bytes");
+
+    let out_dir = env::var("OUT_DIR").expect("No out dir");
+    let dest_path = Path::new(&out_dir).join("build_constants.rs");
+    let mut f = File::create(&dest_path).expect("Could not create file");
+
+    writeln!(&mut f, "pub mod build_constants {{").unwrap();
+    writeln!(&mut f, "    pub const READ_SIZE: usize = {};", read_size).unwrap();
+    writeln!(&mut f, "    pub const WRITE_SIZE: usize = {};", write_size).unwrap();
+    writeln!(&mut f, "    pub const BLOCK_SIZE: usize = {};", block_size).unwrap();
+    writeln!(&mut f, "    pub const BLOCK_COUNT: usize = {};", block_count).unwrap();
+    writeln!(&mut f, "    pub const BLOCK_CYCLES: isize = {};", block_cycles).unwrap();
+    writeln!(&mut f, "    pub type CACHE_SIZE = {};", typenum("cache size", cache_size)).unwrap();
+    writeln!(&mut f, "    pub type LOOKAHEADWORDS_SIZE = {};", typenum("lookahead words", lookaheadwords_size)).unwrap();
+    writeln!(&mut f, "}}").unwrap();
+}
@@ -1,252 +1,357 @@
-use std::{fs::File, io::Write};
-pub use embedded_hal::blocking::rng;
-use littlefs2::{
-    const_ram_storage,
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use interchange::Interchange;
+use structopt::StructOpt;
+
+use solo_pc::{
+    ctaphid::Reports,
+    presence::{Presence, Script},
+    socket,
+    types::{self, Apps},
+    uhid::{Report, Uhid},
+    vpcd::{self, Vpcd},
+    Board, FileFlash, UserInterface, UserPresenceStatus,
 };
-use littlefs2::fs::{Allocation, Filesystem};
-use trussed::types::{LfsResult, LfsStorage};
 
-use trussed::platform::{
-    ui,
-    reboot,
-    consent,
-};
-use trussed::{platform, store};
-use ctap_types::consts;
-
-pub use generic_array::{
-    GenericArray,
-    typenum::{U16, U512},
-};
-
-use generic_array::typenum::{U256, U1022};
-
-
-const SOLO_STATE: &'static str = "solo-state.bin";
-
-#[allow(non_camel_case_types)]
-pub mod littlefs_params {
-    use super::*;
-    pub const READ_SIZE: usize = 16;
-    pub const WRITE_SIZE: usize = 512;
-    pub const BLOCK_SIZE: usize = 512;
-
-    pub const BLOCK_COUNT: usize = 256;
-    // no wear-leveling for now
-    pub const BLOCK_CYCLES: isize = -1;
+#[derive(StructOpt)]
+#[structopt(name = "solo-pc", about = "Runs the Solo 2 apps on the PC")]
+struct Options {
+    /// State file backing the internal filesystem
+    #[structopt(long, default_value = solo_pc::SOLO_STATE, parse(from_os_str))]
+    state: PathBuf,
+
+    /// RNG seed as 64 hex digits, for reproducible keys (default: from the OS)
+    #[structopt(long, parse(try_from_str = parse_seed))]
+    seed: Option<[u8; 32]>,
+
+    /// Expose CTAPHID as a uhid device (needs access to /dev/uhid)
+    #[structopt(long)]
+    uhid: bool,
+
+    /// Listen for raw 64 byte CTAPHID reports on a TCP address (e.g. `localhost:7777`)
+    /// or Unix domain socket path
+    #[structopt(long)]
+    ctaphid_socket: Option<String>,
+
+    /// Connect to vpcd for CCID, e.g. `localhost:35963`
+    #[structopt(long)]
+    vpcd: Option<String>,
+
+    /// Who answers user presence requests: `stdin`, `socket:<address>`, or a script
+    /// such as `deny*2,approve@500,strong` (see the README)
+    #[structopt(long, default_value = "approve")]
+    presence: String,
+}
 
-    pub type CACHE_SIZE = U512;
-    pub type LOOKAHEADWORDS_SIZE = U16;
-    /// TODO: We can't actually be changed currently
-    pub type FILENAME_MAX_PLUS_ONE = U256;
-    pub type PATH_MAX_PLUS_ONE = U256;
-    pub const FILEBYTES_MAX: usize = littlefs2::ll::LFS_FILE_MAX as _;
-    /// TODO: We can't actually be changed currently
-    pub type ATTRBYTES_MAX = U1022;
+/// Where a CTAPHID report came from, and hence where responses go.
+#[derive(Copy, Clone, PartialEq)]
+enum Source {
+    Uhid,
+    Socket,
 }
 
-pub struct FileFlash {
-    state: [u8; 128 * 1024],
+enum Event {
+    Report(Source, Report),
+    SocketConnected(socket::Stream),
+    SocketClosed,
+    Vpcd(vpcd::Message),
 }
-impl FileFlash {
-    pub fn new() -> Self {
-        let mut state = [0u8; 128 * 1024];
-
-        if let Ok(contents) = std::fs::read(SOLO_STATE) {
-            println!("loaded {}", SOLO_STATE);
-            state.copy_from_slice( contents.as_slice() );
-            Self {state}
-        } else {
-            println!("No state yet, creating");
-            Self {state}
-        }
+
+fn presence(presence: &str) -> Result<Presence, String> {
+    if presence == "stdin" {
+        Ok(Presence::stdin())
+    } else if let Some(address) = presence.strip_prefix("socket:") {
+        let presence = Presence::socket(address).map_err(|error| error.to_string())?;
+        println!("listening for user presence on {}", address);
+        Ok(presence)
+    } else {
+        presence.parse::<Script>().map(Presence::Script)
     }
 }
 
-impl littlefs2::driver::Storage for FileFlash {
-    const READ_SIZE: usize = littlefs_params::READ_SIZE;
-    const WRITE_SIZE: usize = littlefs_params::WRITE_SIZE;
-    const BLOCK_SIZE: usize = littlefs_params::BLOCK_SIZE;
-
-    const BLOCK_COUNT: usize = littlefs_params::BLOCK_COUNT;
-    const BLOCK_CYCLES: isize = littlefs_params::BLOCK_CYCLES;
-
-    type CACHE_SIZE = littlefs_params::CACHE_SIZE;
-    type LOOKAHEADWORDS_SIZE = littlefs_params::LOOKAHEADWORDS_SIZE;
-    type FILENAME_MAX_PLUS_ONE = littlefs_params::FILENAME_MAX_PLUS_ONE;
-    type PATH_MAX_PLUS_ONE = littlefs_params::PATH_MAX_PLUS_ONE;
-    const FILEBYTES_MAX: usize = littlefs_params::FILEBYTES_MAX;
-    type ATTRBYTES_MAX = littlefs_params::ATTRBYTES_MAX;
+fn parse_seed(seed: &str) -> Result<[u8; 32], String> {
+    if seed.len() != 64 || !seed.is_ascii() {
+        return Err(String::from("expected 64 hex digits"));
+    }
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&seed[2 * i..][..2], 16).map_err(|error| error.to_string())?;
+    }
+    Ok(bytes)
+}
 
+/// How long to wait for host events before polling the classes again.
+const POLL_MILLISECONDS: u64 = 5;
+
+/// Keepalive period while an app processes a CTAPHID request, as on the LPC55.
+const KEEPALIVE_MILLISECONDS: u64 = 250;
+
+/// The CTAPHID side of the runner.
+///
+/// Besides the main loop, the user interface services it while apps wait for
+/// user presence, so the host keeps getting keepalives (like the USB interrupt
+/// on the LPC55).
+struct Runner {
+    ctaphid: types::CtapHidPipe,
+    uhid: Option<Uhid>,
+    socket: Option<socket::Stream>,
+    source: Source,
+    events: mpsc::Receiver<Event>,
+    // vpcd messages that arrived while waiting for user presence
+    deferred: VecDeque<vpcd::Message>,
+    next_keepalive: Option<Instant>,
+    start: Instant,
+}
 
-    fn read(&self, off: usize, buf: &mut [u8]) -> LfsResult<usize> {
-        for i in 0 .. buf.len() {
-            buf[i] = self.state[i + off];
-        }
-        Ok(buf.len())
+impl Runner {
+    fn milliseconds(&self) -> u32 {
+        self.start.elapsed().as_millis() as u32
     }
 
-    fn write(&mut self, off: usize, data: &[u8]) -> LfsResult<usize> {
-        for i in 0 .. data.len() {
-            self.state[i + off] = data[i];
+    /// Handles host events until a vpcd message arrives, or the timeout elapses.
+    fn receive(&mut self, timeout: Duration) -> Result<Option<vpcd::Message>, mpsc::RecvTimeoutError> {
+        if let Some(message) = self.deferred.pop_front() {
+            return Ok(Some(message));
+        }
+        match self.events.recv_timeout(timeout) {
+            Ok(Event::Vpcd(message)) => Ok(Some(message)),
+            Ok(event) => { self.handle(event); Ok(None) }
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(error) => Err(error),
         }
-        let mut buffer = File::create(SOLO_STATE).unwrap();
-        buffer.write(&self.state).unwrap();
-
-        Ok(data.len())
     }
 
-    fn erase(&mut self, off: usize, len: usize) -> LfsResult<usize> {
-        for i in 0 .. len {
-            self.state[i + off] = 0;
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Report(source, report) => {
+                self.source = source;
+                self.ctaphid.handle_packet(&report);
+            }
+            Event::SocketConnected(stream) => self.socket = Some(stream),
+            Event::SocketClosed => self.socket = None,
+            Event::Vpcd(message) => self.deferred.push_back(message),
         }
-        let mut buffer = File::create(SOLO_STATE).unwrap();
-        buffer.write(&self.state).unwrap();
-        Ok(len)
     }
 
-}
-
-// 8KB of RAM
-const_ram_storage!(
-    name=VolatileStorage,
-    trait=LfsStorage,
-    erase_value=0x00,
-    read_size=1,
-    write_size=1,
-    cache_size_ty=consts::U128,
-    // this is a limitation of littlefs
-    // https://git.io/JeHp9
-    block_size=128,
-    // block_size=128,
-    block_count=8192/128,
-    lookaheadwords_size_ty=consts::U8,
-    filename_max_plus_one_ty=consts::U256,
-    path_max_plus_one_ty=consts::U256,
-    result=LfsResult,
-);
-
-// minimum: 2 blocks
-// TODO: make this optional
-const_ram_storage!(ExternalStorage, 1024);
-
-store!(Store,
-    Internal: FileFlash,
-    External: ExternalStorage,
-    Volatile: VolatileStorage
-);
-
-
-
-// #[derive(Default)]
-// pub struct Rng {
-//     count: u64,
-// }
-
-// impl rng::Read for Rng {
-//     type Error = core::convert::Infallible;
-//     fn read(&mut self, buffer: &mut [u8]) -> core::result::Result<(), Self::Error> {
-//         // bad
-//         for i in 0 .. buffer.len() {
-//             self.count += 1;
-//             buffer[i] = (self.count & 0xff) as u8;
-//         }
-//         Ok(())
-//     }
-// }
-
-
-#[derive(Default)]
-pub struct UserInterface {
-}
-
-impl trussed::platform::UserInterface for UserInterface
-{
-    fn check_user_presence(&mut self) -> consent::Level {
-        consent::Level::Normal
+    /// Handles the pending host events without blocking, deferring vpcd messages.
+    fn handle_pending(&mut self) {
+        while let Ok(event) = self.events.try_recv() {
+            self.handle(event);
+        }
     }
 
-    fn set_status(&mut self, status: ui::Status) {
-
-        println!("Set status: {:?}", status);
+    /// Passes on app responses and keepalives, and sends pending reports to the host.
+    fn poll(&mut self) {
+        let milliseconds = self.milliseconds();
+        self.ctaphid.check_timeout(milliseconds);
+        self.ctaphid.handle_response();
+        self.ctaphid.maybe_write_packet();
 
-    }
-
-    fn refresh(&mut self) {
+        let keepalive_period = Duration::from_millis(KEEPALIVE_MILLISECONDS);
+        if self.ctaphid.did_start_processing() {
+            self.next_keepalive = Some(Instant::now() + keepalive_period);
+        }
+        if self.next_keepalive.map_or(false, |keepalive| keepalive <= Instant::now()) {
+            self.next_keepalive = self.ctaphid.send_keepalive(UserPresenceStatus::waiting())
+                .then(|| Instant::now() + keepalive_period);
+        }
 
+        while let Some(report) = self.ctaphid.transport_mut().take() {
+            match self.source {
+                Source::Uhid => if let Some(uhid) = self.uhid.as_mut() {
+                    uhid.write_report(&report).expect("uhid: could not send report");
+                }
+                Source::Socket => if let Some(stream) = self.socket.as_mut() {
+                    // the client may hang up at any time, the reader thread notices
+                    stream.write_report(&report).ok();
+                }
+            }
+            self.ctaphid.maybe_write_packet();
+        }
     }
+}
 
-    fn uptime(&mut self) -> core::time::Duration {
-        core::time::Duration::from_millis(1000)
+fn main() {
+    let options = Options::from_args();
+    if !options.uhid && options.ctaphid_socket.is_none() && options.vpcd.is_none() {
+        eprintln!("nothing to do, enable at least one of --uhid, --ctaphid-socket and --vpcd (e.g. --vpcd {})", vpcd::DEFAULT_ADDRESS);
+        std::process::exit(1);
     }
-
-    fn reboot(&mut self, to: reboot::To) -> ! {
-        println!("Restart!  ({:?})", to);
-        std::process::exit(25);
+    let presence = presence(&options.presence).unwrap_or_else(|error| {
+        eprintln!("invalid --presence: {}", error);
+        std::process::exit(1);
+    });
+
+    let filesystem = FileFlash::open(&options.state).unwrap_or_else(|error| {
+        eprintln!("could not open state file: {}", error);
+        std::process::exit(1);
+    });
+    let store = solo_pc::init_store(filesystem);
+
+    let seed = options.seed.unwrap_or_else(|| {
+        let mut seed = [0u8; 32];
+        File::open("/dev/urandom")
+            .and_then(|mut urandom| urandom.read_exact(&mut seed))
+            .expect("could not read /dev/urandom");
+        seed
+    });
+    // so runs with OS entropy can be reproduced
+    println!("RNG seed: {}", seed.iter().map(|byte| format!("{:02x}", byte)).collect::<String>());
+
+    let (mut contact_requester, contact_responder) = apdu_dispatch::interchanges::Contact::claim()
+        .expect("could not setup ccid ApduInterchange");
+    // unused, but apdu-dispatch needs both interfaces
+    let (_contactless_requester, contactless_responder) = apdu_dispatch::interchanges::Contactless::claim()
+        .expect("could not setup iso14443 ApduInterchange");
+    let (ctaphid_requester, ctaphid_responder) = ctaphid_dispatch::types::HidInterchange::claim()
+        .expect("could not setup HidInterchange");
+
+    let mut apdu_dispatch = types::ApduDispatch::new(contact_responder, contactless_responder);
+    let mut ctaphid_dispatch = types::CtaphidDispatch::new(ctaphid_responder);
+
+    let start = Instant::now();
+
+    // the capabilities follow the apps, see below
+    let version_part = |part: &str| part.parse().expect("version part does not fit into a byte");
+    let ctaphid: types::CtapHidPipe = usbd_ctaphid::pipe::Pipe::new(Reports::default(), ctaphid_requester, 0)
+        .device_version(
+            version_part(env!("CARGO_PKG_VERSION_MAJOR")),
+            version_part(env!("CARGO_PKG_VERSION_MINOR")),
+            version_part(env!("CARGO_PKG_VERSION_PATCH")),
+        );
+
+    let (events, receiver) = mpsc::channel();
+
+    let uhid = options.uhid.then(|| {
+        // Only 16 bits, so take the upper bits of our semver
+        let version = types::version();
+        let device_release = ((version >> 22) << 8) | ((version >> 6) & 0xff);
+        let uhid = Uhid::create(device_release).expect("could not create uhid device");
+        let mut reader = uhid.try_clone().unwrap();
+        let events = events.clone();
+        thread::spawn(move || loop {
+            match reader.read_report() {
+                Ok(Some(report)) => if events.send(Event::Report(Source::Uhid, report)).is_err() { break },
+                Ok(None) => {}
+                Err(error) => { eprintln!("uhid: {}", error); break }
+            }
+        });
+        println!("created uhid device");
+        uhid
+    });
+
+    if let Some(address) = options.ctaphid_socket.as_ref() {
+        let listener = socket::Listener::bind(address).expect("could not bind CTAPHID socket");
+        let events = events.clone();
+        thread::spawn(move || loop {
+            let mut stream = match listener.accept() {
+                Ok(stream) => stream,
+                Err(error) => { eprintln!("socket: {}", error); break }
+            };
+            let writer = stream.try_clone().unwrap();
+            if events.send(Event::SocketConnected(writer)).is_err() { break }
+            loop {
+                match stream.read_report() {
+                    Ok(Some(report)) => if events.send(Event::Report(Source::Socket, report)).is_err() { return },
+                    Ok(None) => break,
+                    Err(error) => { eprintln!("socket: {}", error); break }
+                }
+            }
+            if events.send(Event::SocketClosed).is_err() { break }
+        });
+        println!("listening for CTAPHID reports on {}", address);
     }
 
-}
-
-platform!(Board,
-    R: chacha20::ChaCha8Rng,
-    S: Store,
-    UI: UserInterface,
-);
-
-fn main () {
-
-    let filesystem = FileFlash::new();
-
-    static mut INTERNAL_STORAGE: Option<FileFlash> = None;
-    unsafe { INTERNAL_STORAGE = Some(filesystem); }
-    static mut INTERNAL_FS_ALLOC: Option<Allocation<FileFlash>> = None;
-    unsafe { INTERNAL_FS_ALLOC = Some(Filesystem::allocate()); }
-
-    static mut EXTERNAL_STORAGE: ExternalStorage = ExternalStorage::new();
-    static mut EXTERNAL_FS_ALLOC: Option<Allocation<ExternalStorage>> = None;
-    unsafe { EXTERNAL_FS_ALLOC = Some(Filesystem::allocate()); }
-
-    static mut VOLATILE_STORAGE: VolatileStorage = VolatileStorage::new();
-    static mut VOLATILE_FS_ALLOC: Option<Allocation<VolatileStorage>> = None;
-    unsafe { VOLATILE_FS_ALLOC = Some(Filesystem::allocate()); }
-
-
-    let store = Store::claim().unwrap();
-
-    let result = store.mount(
-        unsafe { INTERNAL_FS_ALLOC.as_mut().unwrap() },
-        // unsafe { &mut INTERNAL_STORAGE },
-        unsafe { INTERNAL_STORAGE.as_mut().unwrap() },
-        unsafe { EXTERNAL_FS_ALLOC.as_mut().unwrap() },
-        unsafe { &mut EXTERNAL_STORAGE },
-        unsafe { VOLATILE_FS_ALLOC.as_mut().unwrap() },
-        unsafe { &mut VOLATILE_STORAGE },
-        // to trash existing data, set to true
-        false,
-    );
-
-    if result.is_err() {
-        println!("Not yet formatted!  Formatting..");
-        store.mount(
-            unsafe { INTERNAL_FS_ALLOC.as_mut().unwrap() },
-            // unsafe { &mut INTERNAL_STORAGE },
-            unsafe { INTERNAL_STORAGE.as_mut().unwrap() },
-            unsafe { EXTERNAL_FS_ALLOC.as_mut().unwrap() },
-            unsafe { &mut EXTERNAL_STORAGE },
-            unsafe { VOLATILE_FS_ALLOC.as_mut().unwrap() },
-            unsafe { &mut VOLATILE_STORAGE },
-            // to trash existing data, set to true
-            true,
-        ).unwrap();
+    let mut vpcd = options.vpcd.as_ref().map(|address| {
+        let vpcd = Vpcd::connect(address).expect("could not connect to vpcd");
+        let mut reader = vpcd.try_clone().unwrap();
+        let events = events.clone();
+        thread::spawn(move || loop {
+            match reader.read_message() {
+                Ok(message) => if events.send(Event::Vpcd(message)).is_err() { break },
+                Err(error) => { eprintln!("vpcd: {}", error); break }
+            }
+        });
+        println!("connected to vpcd at {}", address);
+        vpcd
+    });
+
+    drop(events);
+
+    let runner = Rc::new(RefCell::new(Runner {
+        ctaphid,
+        uhid,
+        socket: None,
+        source: Source::Uhid,
+        events: receiver,
+        deferred: VecDeque::new(),
+        next_keepalive: None,
+        start,
+    }));
+
+    let mut pc_interface = UserInterface::new(presence);
+    {
+        // Only called from within the dispatchers' polls, when the main loop
+        // does not hold the runner.
+        let runner = runner.clone();
+        pc_interface.set_service(move || {
+            let mut runner = runner.borrow_mut();
+            runner.handle_pending();
+            runner.poll();
+        });
     }
 
-
     use trussed::service::SeedableRng;
-    let rng = chacha20::ChaCha8Rng::from_seed([0u8; 32]);
-    let pc_interface: UserInterface = Default::default();
+    let rng = chacha20::ChaCha8Rng::from_seed(seed);
 
     let board = Board::new(rng, store, pc_interface);
-    let mut _trussed = trussed::service::Service::new(board);
+    let trussed = types::init_trussed(board);
+
+    let mut apps = Apps::new(trussed, store);
+    // advertise in CTAPHID INIT what the apps (selected by features) implement
+    let commands = apps.ctaphid_dispatch(|apps| types::CtaphidDispatch::commands(apps));
+    runner.borrow_mut().ctaphid.set_commands(commands);
+
+    loop {
+        let message = match runner.borrow_mut().receive(Duration::from_millis(POLL_MILLISECONDS)) {
+            Ok(message) => message,
+            Err(_) => break,
+        };
+        if let Some(message) = message {
+            let vpcd = vpcd.as_mut().unwrap();
+            match message {
+                vpcd::Message::GetAtr => vpcd.send(&vpcd::ATR).expect("vpcd: could not send ATR"),
+                vpcd::Message::PowerOff | vpcd::Message::PowerOn | vpcd::Message::Reset => {}
+                vpcd::Message::Apdu(apdu) => {
+                    contact_requester.take_response();
+                    match apdu_dispatch::interchanges::Data::try_from_slice(&apdu) {
+                        Ok(request) => if contact_requester.request(&request).is_err() {
+                            eprintln!("vpcd: dropping APDU, previous one still in flight");
+                        }
+                        // wrong length
+                        Err(_) => vpcd.send(&[0x67, 0x00]).expect("vpcd: could not send response"),
+                    }
+                }
+            }
+        }
+
+        apps.apdu_dispatch(|apps| apdu_dispatch.poll(apps));
+        apps.ctaphid_dispatch(|apps| ctaphid_dispatch.poll(apps));
 
-    println!("hello trussed");
+        if let Some(response) = contact_requester.take_response() {
+            if let Some(vpcd) = vpcd.as_mut() {
+                vpcd.send(&response).expect("vpcd: could not send response");
+            }
+        }
+
+        runner.borrow_mut().poll();
+    }
 }
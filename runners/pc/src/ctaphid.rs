@@ -0,0 +1,32 @@
+//! CTAPHID without a USB bus: the pipe runs directly on the reports.
+//!
+//! Reports from uhid or the socket client are handed to the pipe as they arrive,
+//! and the pipe's reports queue up here until the runner passes them on.
+
+use std::collections::VecDeque;
+
+use usbd_ctaphid::transport::{self, Packet, Transport};
+
+#[derive(Default)]
+pub struct Reports {
+    outgoing: VecDeque<Packet>,
+}
+
+impl Reports {
+    /// Takes the next report for the host, if any.
+    pub fn take(&mut self) -> Option<Packet> {
+        self.outgoing.pop_front()
+    }
+}
+
+impl Transport for Reports {
+    fn read(&mut self, _packet: &mut Packet) -> Result<usize, transport::Error> {
+        // reports are pushed via `Pipe::handle_packet` instead
+        Err(transport::Error::WouldBlock)
+    }
+
+    fn write(&mut self, packet: &Packet) -> Result<(), transport::Error> {
+        self.outgoing.push_back(*packet);
+        Ok(())
+    }
+}
@@ -1 +1,353 @@
+include!(concat!(env!("OUT_DIR"), "/build_constants.rs"));
 
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read},
+    os::unix::fs::FileExt,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+pub use embedded_hal::blocking::rng;
+use littlefs2::{
+    const_ram_storage,
+};
+use littlefs2::fs::{Allocation, Filesystem};
+use trussed::types::{LfsResult, LfsStorage};
+
+use trussed::platform::{
+    ui,
+    reboot,
+    consent,
+};
+use trussed::{platform, store};
+use ctap_types::consts;
+
+use presence::Presence;
+
+pub mod ctaphid;
+pub mod presence;
+pub mod socket;
+pub mod types;
+pub mod uhid;
+pub mod vpcd;
+
+pub use generic_array::{
+    GenericArray,
+    typenum::{U16, U512},
+};
+
+use generic_array::typenum::{U256, U1022};
+
+
+pub const SOLO_STATE: &'static str = "solo-state.bin";
+
+/// Geometry of the internal filesystem, see `build.rs` to change it.
+#[allow(non_camel_case_types)]
+pub mod littlefs_params {
+    use super::*;
+    pub const READ_SIZE: usize = build_constants::READ_SIZE;
+    pub const WRITE_SIZE: usize = build_constants::WRITE_SIZE;
+    pub const BLOCK_SIZE: usize = build_constants::BLOCK_SIZE;
+
+    pub const BLOCK_COUNT: usize = build_constants::BLOCK_COUNT;
+    pub const BLOCK_CYCLES: isize = build_constants::BLOCK_CYCLES;
+
+    pub type CACHE_SIZE = build_constants::CACHE_SIZE;
+    pub type LOOKAHEADWORDS_SIZE = build_constants::LOOKAHEADWORDS_SIZE;
+    /// TODO: We can't actually be changed currently
+    pub type FILENAME_MAX_PLUS_ONE = U256;
+    pub type PATH_MAX_PLUS_ONE = U256;
+    pub const FILEBYTES_MAX: usize = littlefs2::ll::LFS_FILE_MAX as _;
+    /// TODO: We can't actually be changed currently
+    pub type ATTRBYTES_MAX = U1022;
+}
+
+/// Size of the internal flash (and its state file) in bytes.
+pub const FLASH_SIZE: usize = littlefs_params::BLOCK_SIZE * littlefs_params::BLOCK_COUNT;
+
+pub struct FileFlash {
+    state: Vec<u8>,
+    file: Option<File>,
+}
+impl FileFlash {
+    /// Opens the state file at `path`, creating it if necessary.
+    ///
+    /// Fails if an existing state file does not match the filesystem geometry.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).open(path)?;
+        let mut state = vec![0u8; FLASH_SIZE];
+
+        match file.metadata()?.len() as usize {
+            0 => {
+                println!("No state yet, creating {}", path.display());
+                file.set_len(FLASH_SIZE as u64)?;
+            }
+            FLASH_SIZE => {
+                file.read_exact(&mut state)?;
+                println!("loaded {}", path.display());
+            }
+            size => return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "{} has {} bytes, but the filesystem geometry needs {}",
+                path.display(), size, FLASH_SIZE,
+            ))),
+        }
+
+        Ok(Self { state, file: Some(file) })
+    }
+
+    /// Flash that only lives in RAM, e.g. for tests.
+    pub fn in_memory() -> Self {
+        Self { state: vec![0u8; FLASH_SIZE], file: None }
+    }
+
+    /// Writes back the blocks touched by a write or erase, leaving the rest of the file alone.
+    fn flush(&self, off: usize, len: usize) {
+        if let Some(file) = self.file.as_ref() {
+            let block_size = littlefs_params::BLOCK_SIZE;
+            let start = off - off % block_size;
+            let end = (off + len + block_size - 1) / block_size * block_size;
+            file.write_all_at(&self.state[start..end], start as u64)
+                .expect("could not write state file");
+        }
+    }
+}
+
+impl littlefs2::driver::Storage for FileFlash {
+    const READ_SIZE: usize = littlefs_params::READ_SIZE;
+    const WRITE_SIZE: usize = littlefs_params::WRITE_SIZE;
+    const BLOCK_SIZE: usize = littlefs_params::BLOCK_SIZE;
+
+    const BLOCK_COUNT: usize = littlefs_params::BLOCK_COUNT;
+    const BLOCK_CYCLES: isize = littlefs_params::BLOCK_CYCLES;
+
+    type CACHE_SIZE = littlefs_params::CACHE_SIZE;
+    type LOOKAHEADWORDS_SIZE = littlefs_params::LOOKAHEADWORDS_SIZE;
+    type FILENAME_MAX_PLUS_ONE = littlefs_params::FILENAME_MAX_PLUS_ONE;
+    type PATH_MAX_PLUS_ONE = littlefs_params::PATH_MAX_PLUS_ONE;
+    const FILEBYTES_MAX: usize = littlefs_params::FILEBYTES_MAX;
+    type ATTRBYTES_MAX = littlefs_params::ATTRBYTES_MAX;
+
+
+    fn read(&self, off: usize, buf: &mut [u8]) -> LfsResult<usize> {
+        buf.copy_from_slice(&self.state[off..][..buf.len()]);
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, off: usize, data: &[u8]) -> LfsResult<usize> {
+        self.state[off..][..data.len()].copy_from_slice(data);
+        self.flush(off, data.len());
+
+        Ok(data.len())
+    }
+
+    fn erase(&mut self, off: usize, len: usize) -> LfsResult<usize> {
+        for byte in &mut self.state[off..][..len] {
+            *byte = 0;
+        }
+        self.flush(off, len);
+        Ok(len)
+    }
+
+}
+
+// 8KB of RAM
+const_ram_storage!(
+    name=VolatileStorage,
+    trait=LfsStorage,
+    erase_value=0x00,
+    read_size=1,
+    write_size=1,
+    cache_size_ty=consts::U128,
+    // this is a limitation of littlefs
+    // https://git.io/JeHp9
+    block_size=128,
+    // block_size=128,
+    block_count=8192/128,
+    lookaheadwords_size_ty=consts::U8,
+    filename_max_plus_one_ty=consts::U256,
+    path_max_plus_one_ty=consts::U256,
+    result=LfsResult,
+);
+
+// minimum: 2 blocks
+// TODO: make this optional
+const_ram_storage!(ExternalStorage, 1024);
+
+store!(Store,
+    Internal: FileFlash,
+    External: ExternalStorage,
+    Volatile: VolatileStorage
+);
+
+
+
+// #[derive(Default)]
+// pub struct Rng {
+//     count: u64,
+// }
+
+// impl rng::Read for Rng {
+//     type Error = core::convert::Infallible;
+//     fn read(&mut self, buffer: &mut [u8]) -> core::result::Result<(), Self::Error> {
+//         // bad
+//         for i in 0 .. buffer.len() {
+//             self.count += 1;
+//             buffer[i] = (self.count & 0xff) as u8;
+//         }
+//         Ok(())
+//     }
+// }
+
+
+// Mirrors the LPC55 board: set while a user presence check is ongoing,
+// so CTAPHID keepalives can report UpNeeded.
+static WAITING: AtomicBool = AtomicBool::new(false);
+pub struct UserPresenceStatus {}
+impl UserPresenceStatus {
+    pub(crate) fn set_waiting(waiting: bool) {
+        WAITING.store(waiting, Ordering::Relaxed);
+    }
+    pub fn waiting() -> bool {
+        WAITING.load(Ordering::Relaxed)
+    }
+}
+
+/// Answers user presence requests from a [`Presence`] source.
+///
+/// The default approves every request immediately, as the tests need.
+pub struct UserInterface {
+    start: Instant,
+    presence: Presence,
+    request: Option<presence::Request>,
+    // The LPC55 services USB in interrupts while waiting for the button,
+    // here the runner passes in a closure to do the same.
+    service: Option<Box<dyn FnMut()>>,
+}
+
+impl Default for UserInterface {
+    fn default() -> Self {
+        Self::new(Presence::default())
+    }
+}
+
+impl UserInterface {
+    pub fn new(presence: Presence) -> Self {
+        Self { start: Instant::now(), presence, request: None, service: None }
+    }
+
+    /// Sets a closure that is called repeatedly while waiting for user presence.
+    pub fn set_service(&mut self, service: impl FnMut() + 'static) {
+        self.service = Some(Box::new(service));
+    }
+}
+
+impl trussed::platform::UserInterface for UserInterface
+{
+    fn check_user_presence(&mut self) -> consent::Level {
+        let presence = &mut self.presence;
+        let request = self.request.get_or_insert_with(|| presence.begin());
+
+        UserPresenceStatus::set_waiting(true);
+        if let Some(service) = self.service.as_mut() {
+            service();
+        }
+        thread::sleep(Duration::from_millis(1));
+        let level = presence.poll(request);
+        UserPresenceStatus::set_waiting(false);
+
+        // as on the LPC55: a cancelled request ends every wait,
+        // and the apps don't take it for consent
+        if ctaphid_dispatch::cancel::is_cancelled() {
+            self.request = None;
+            return consent::Level::Strong;
+        }
+
+        if level != consent::Level::None {
+            self.request = None;
+        }
+        level
+    }
+
+    fn set_status(&mut self, status: ui::Status) {
+
+        println!("Set status: {:?}", status);
+
+        // every wait is a new request, even if the previous one timed out
+        if let ui::Status::WaitingForUserPresence = status {
+            self.request = Some(self.presence.begin());
+        } else {
+            self.request = None;
+        }
+    }
+
+    fn refresh(&mut self) {
+
+    }
+
+    fn uptime(&mut self) -> core::time::Duration {
+        self.start.elapsed()
+    }
+
+    fn reboot(&mut self, to: reboot::To) -> ! {
+        println!("Restart!  ({:?})", to);
+        std::process::exit(25);
+    }
+
+}
+
+platform!(Board,
+    R: chacha20::ChaCha8Rng,
+    S: Store,
+    UI: UserInterface,
+);
+
+/// Mounts the three filesystems, formatting them if the internal flash does
+/// not contain a valid littlefs yet.
+pub fn init_store(filesystem: FileFlash) -> Store {
+    static mut INTERNAL_STORAGE: Option<FileFlash> = None;
+    unsafe { INTERNAL_STORAGE = Some(filesystem); }
+    static mut INTERNAL_FS_ALLOC: Option<Allocation<FileFlash>> = None;
+    unsafe { INTERNAL_FS_ALLOC = Some(Filesystem::allocate()); }
+
+    static mut EXTERNAL_STORAGE: ExternalStorage = ExternalStorage::new();
+    static mut EXTERNAL_FS_ALLOC: Option<Allocation<ExternalStorage>> = None;
+    unsafe { EXTERNAL_FS_ALLOC = Some(Filesystem::allocate()); }
+
+    static mut VOLATILE_STORAGE: VolatileStorage = VolatileStorage::new();
+    static mut VOLATILE_FS_ALLOC: Option<Allocation<VolatileStorage>> = None;
+    unsafe { VOLATILE_FS_ALLOC = Some(Filesystem::allocate()); }
+
+
+    let store = Store::claim().unwrap();
+
+    let result = store.mount(
+        unsafe { INTERNAL_FS_ALLOC.as_mut().unwrap() },
+        // unsafe { &mut INTERNAL_STORAGE },
+        unsafe { INTERNAL_STORAGE.as_mut().unwrap() },
+        unsafe { EXTERNAL_FS_ALLOC.as_mut().unwrap() },
+        unsafe { &mut EXTERNAL_STORAGE },
+        unsafe { VOLATILE_FS_ALLOC.as_mut().unwrap() },
+        unsafe { &mut VOLATILE_STORAGE },
+        // to trash existing data, set to true
+        false,
+    );
+
+    if result.is_err() {
+        println!("Not yet formatted!  Formatting..");
+        store.mount(
+            unsafe { INTERNAL_FS_ALLOC.as_mut().unwrap() },
+            // unsafe { &mut INTERNAL_STORAGE },
+            unsafe { INTERNAL_STORAGE.as_mut().unwrap() },
+            unsafe { EXTERNAL_FS_ALLOC.as_mut().unwrap() },
+            unsafe { &mut EXTERNAL_STORAGE },
+            unsafe { VOLATILE_FS_ALLOC.as_mut().unwrap() },
+            unsafe { &mut VOLATILE_STORAGE },
+            // to trash existing data, set to true
+            true,
+        ).unwrap();
+    }
+
+    store
+}
@@ -0,0 +1,201 @@
+//! Simulated user presence, i.e. who presses the button, and when.
+//!
+//! Answers either follow a [`Script`], or arrive interactively, as lines
+//! (`approve`, `strong` or `deny`) from stdin or a socket. Interactive answers
+//! are queued, so an answer sent ahead of time is used for the next request.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use trussed::platform::consent;
+
+use crate::socket;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Answer {
+    /// Never press the button, so the request times out.
+    Deny,
+    /// Press the button.
+    Approve,
+    /// Press the button in a way that counts as strong consent.
+    Strong,
+}
+
+impl Answer {
+    fn level(self) -> consent::Level {
+        match self {
+            Answer::Deny => consent::Level::None,
+            Answer::Approve => consent::Level::Normal,
+            Answer::Strong => consent::Level::Strong,
+        }
+    }
+}
+
+impl FromStr for Answer {
+    type Err = String;
+
+    fn from_str(answer: &str) -> Result<Self, String> {
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "deny" | "n" => Ok(Answer::Deny),
+            "approve" | "y" => Ok(Answer::Approve),
+            "strong" | "s" => Ok(Answer::Strong),
+            other => Err(format!("unknown answer {:?}, expected approve, strong or deny", other)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Step {
+    answer: Answer,
+    delay: Duration,
+    count: usize,
+}
+
+/// Answers for the upcoming requests, one step per request.
+///
+/// Steps are separated by commas, and have the form `answer[*count][@milliseconds]`.
+/// For instance, `deny*2,approve@500,strong` denies the next two requests,
+/// approves the third after 500 ms, and then answers Strong.
+/// The last step repeats forever.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Script {
+    steps: VecDeque<Step>,
+}
+
+impl Default for Script {
+    /// Approves every request immediately.
+    fn default() -> Self {
+        "approve".parse().unwrap()
+    }
+}
+
+impl FromStr for Script {
+    type Err = String;
+
+    fn from_str(script: &str) -> Result<Self, String> {
+        let mut steps = VecDeque::new();
+        for step in script.split(',') {
+            let mut parts = step.splitn(2, '@');
+            let step = parts.next().unwrap();
+            let delay = match parts.next() {
+                Some(delay) => delay.trim().trim_end_matches("ms").parse()
+                    .map_err(|_| format!("invalid delay {:?}", delay))?,
+                None => 0,
+            };
+
+            let mut parts = step.splitn(2, '*');
+            let answer = parts.next().unwrap().parse()?;
+            let count = match parts.next() {
+                Some(count) => match count.trim().parse() {
+                    Ok(count) if count > 0 => count,
+                    _ => return Err(format!("invalid count {:?}", count)),
+                },
+                None => 1,
+            };
+
+            steps.push_back(Step { answer, delay: Duration::from_millis(delay), count });
+        }
+        Ok(Self { steps })
+    }
+}
+
+impl Script {
+    fn next(&mut self) -> (Answer, Duration) {
+        let last = self.steps.len() == 1;
+        let step = self.steps.front_mut().unwrap();
+        let next = (step.answer, step.delay);
+        if !last {
+            step.count -= 1;
+            if step.count == 0 {
+                self.steps.pop_front();
+            }
+        }
+        next
+    }
+}
+
+/// The state of an ongoing request: the answer, and when it is given.
+#[derive(Default)]
+pub struct Request {
+    answer: Option<(Answer, Instant)>,
+}
+
+pub enum Presence {
+    Script(Script),
+    Interactive(mpsc::Receiver<Answer>),
+}
+
+impl Default for Presence {
+    fn default() -> Self {
+        Presence::Script(Script::default())
+    }
+}
+
+impl Presence {
+    /// Reads answers from stdin, prompting for each request.
+    pub fn stdin() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                match line.map(|line| line.parse::<Answer>()) {
+                    Ok(Ok(answer)) => if sender.send(answer).is_err() { break },
+                    Ok(Err(error)) => eprintln!("{}", error),
+                    Err(_) => break,
+                }
+            }
+        });
+        Presence::Interactive(receiver)
+    }
+
+    /// Reads answers from clients of a TCP or Unix domain socket, one at a time.
+    pub fn socket(address: &str) -> io::Result<Self> {
+        let listener = socket::Listener::bind(address)?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || loop {
+            let stream = match listener.accept() {
+                Ok(stream) => stream,
+                Err(error) => { eprintln!("presence: {}", error); break }
+            };
+            for line in BufReader::new(stream).lines() {
+                match line.map(|line| line.parse::<Answer>()) {
+                    Ok(Ok(answer)) => if sender.send(answer).is_err() { return },
+                    Ok(Err(error)) => eprintln!("presence: {}", error),
+                    Err(_) => break,
+                }
+            }
+        });
+        Ok(Presence::Interactive(receiver))
+    }
+
+    /// Starts answering a new request.
+    pub fn begin(&mut self) -> Request {
+        match self {
+            Presence::Script(script) => {
+                let (answer, delay) = script.next();
+                Request { answer: Some((answer, Instant::now() + delay)) }
+            }
+            Presence::Interactive(_) => {
+                println!("User presence requested, answer with approve, strong or deny");
+                Request::default()
+            }
+        }
+    }
+
+    /// The current level of consent for an ongoing request.
+    pub fn poll(&mut self, request: &mut Request) -> consent::Level {
+        if let Presence::Interactive(receiver) = self {
+            if request.answer.is_none() {
+                request.answer = receiver.try_recv().ok().map(|answer| (answer, Instant::now()));
+            }
+        }
+        match request.answer {
+            Some((answer, at)) if at <= Instant::now() => answer.level(),
+            _ => consent::Level::None,
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! CTAPHID reports over a plain Unix domain or TCP socket.
+//!
+//! There is no framing: both directions are sequences of 64 byte CTAPHID
+//! reports, exactly as they would travel over the HID interrupt endpoints.
+//! Only one client is served at a time.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use crate::uhid::Report;
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Listener {
+    /// Binds to `address` if it is a socket address (`localhost:7777`),
+    /// and to a Unix domain socket at the path `address` otherwise.
+    pub fn bind(address: &str) -> io::Result<Self> {
+        if address.to_socket_addrs().is_ok() {
+            return Ok(Listener::Tcp(TcpListener::bind(address)?));
+        }
+
+        // clean up after a previous run, but only if it's a socket
+        let path = Path::new(address);
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(Listener::Unix(UnixListener::bind(path)?))
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        Ok(match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                Stream::Tcp(stream)
+            }
+            Listener::Unix(listener) => Stream::Unix(listener.accept()?.0),
+        })
+    }
+}
+
+impl Stream {
+    /// A second handle, one is read from a separate thread.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Stream::Tcp(stream) => Stream::Tcp(stream.try_clone()?),
+            Stream::Unix(stream) => Stream::Unix(stream.try_clone()?),
+        })
+    }
+
+    /// Blocks until the next report, returning `None` once the client hangs up.
+    pub fn read_report(&mut self) -> io::Result<Option<Report>> {
+        let mut report = [0u8; core::mem::size_of::<Report>()];
+        match self.read_exact(&mut report) {
+            Ok(()) => Ok(Some(report)),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn write_report(&mut self, report: &[u8]) -> io::Result<()> {
+        self.write_all(report)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use core::convert::TryInto;
+
+use interchange::Interchange;
+
+use crate::{Board, Store};
+
+pub type Trussed = trussed::Service<Board>;
+pub type TrussedClient = trussed::ClientImplementation<Syscall>;
+
+pub type ApduDispatch = apdu_dispatch::dispatch::ApduDispatch;
+pub type CtaphidDispatch = ctaphid_dispatch::dispatch::Dispatch;
+
+pub type CtapHidPipe = usbd_ctaphid::pipe::Pipe<crate::ctaphid::Reports>;
+
+/// There is no OS_EVENT interrupt to pend on the PC, so the service lives
+/// in a static and syscalls process it on the spot.
+static mut TRUSSED: Option<Trussed> = None;
+
+pub fn init_trussed(board: Board) -> &'static mut Trussed {
+    unsafe {
+        TRUSSED = Some(trussed::service::Service::new(board));
+        TRUSSED.as_mut().unwrap()
+    }
+}
+
+#[derive(Default)]
+pub struct Syscall {}
+
+impl trussed::client::Syscall for Syscall {
+    #[inline]
+    fn syscall(&mut self) {
+        unsafe { TRUSSED.as_mut() }
+            .expect("Trussed service not initialized")
+            .process();
+    }
+}
+
+/// Fixed UUID of the simulated device.
+pub const UUID: [u8; 16] = *b"solo-pc-simulatr";
+
+/// Firmware version, encoded like the lpc55 runner's `CARGO_PKG_VERSION`.
+pub fn version() -> u32 {
+    let major: u32 = env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap();
+    let minor: u32 = env!("CARGO_PKG_VERSION_MINOR").parse().unwrap();
+    let patch: u32 = env!("CARGO_PKG_VERSION_PATCH").parse().unwrap();
+    (major << 22) | (minor << 6) | patch
+}
+
+pub struct PcReboot {}
+impl admin_app::Reboot for PcReboot {
+    fn reboot() -> ! {
+        println!("Reboot requested, exiting");
+        std::process::exit(25);
+    }
+    fn reboot_to_firmware_update() -> ! {
+        println!("Reboot to firmware update requested, exiting");
+        std::process::exit(25);
+    }
+    fn reboot_to_firmware_update_destructive() -> ! {
+        println!("Destructive reboot to firmware update requested, exiting");
+        std::process::exit(25);
+    }
+}
+
+#[cfg(feature = "admin-app")]
+pub type AdminApp = admin_app::App<TrussedClient, PcReboot, Store>;
+#[cfg(feature = "piv-authenticator")]
+pub type PivApp = piv_authenticator::Authenticator<apdu_dispatch::command::Size, TrussedClient>;
+#[cfg(feature = "oath-authenticator")]
+pub type OathApp = oath_authenticator::Authenticator<TrussedClient>;
+#[cfg(feature = "fido-authenticator")]
+pub type FidoApp = dispatch_fido::Fido<dispatch_fido::CancellableUserPresence, TrussedClient>;
+#[cfg(feature = "ndef-app")]
+pub type NdefApp = ndef_app::App<'static>;
+
+/// The apps compiled in, as selected by Cargo features.
+pub const APP_FEATURES: &[&str] = &[
+    #[cfg(feature = "admin-app")]
+    "admin-app",
+    #[cfg(feature = "fido-authenticator")]
+    "fido-authenticator",
+    #[cfg(feature = "ndef-app")]
+    "ndef-app",
+    #[cfg(feature = "oath-authenticator")]
+    "oath-authenticator",
+    #[cfg(feature = "piv-authenticator")]
+    "piv-authenticator",
+];
+
+use apdu_dispatch::{App as ApduApp, command::Size as CommandSize, response::Size as ResponseSize};
+use ctaphid_dispatch::app::{App as CtaphidApp};
+use heapless::Vec;
+
+pub trait TrussedApp: Sized {
+
+    /// non-portable resources needed by this Trussed app
+    type NonPortable;
+
+    /// the desired client ID
+    const CLIENT_ID: &'static [u8];
+
+    fn with_client(trussed: TrussedClient, non_portable: Self::NonPortable) -> Self;
+
+    fn with(trussed: &mut Trussed, non_portable: Self::NonPortable) -> Self {
+        Self::with_client(trussed_client(trussed, Self::CLIENT_ID), non_portable)
+    }
+}
+
+/// Connects a new client to the Trussed service.
+fn trussed_client(trussed: &mut Trussed, client_id: &[u8]) -> TrussedClient {
+    let (trussed_requester, trussed_responder) = trussed::pipe::TrussedInterchange::claim()
+        .expect("could not setup TrussedInterchange");
+
+    let mut client_id_path = littlefs2::path::PathBuf::new();
+    client_id_path.push(client_id.try_into().unwrap());
+    assert!(trussed.add_endpoint(trussed_responder, client_id_path).is_ok());
+
+    let syscaller = Syscall::default();
+    TrussedClient::new(
+        trussed_requester,
+        syscaller,
+    )
+}
+
+#[cfg(feature = "oath-authenticator")]
+impl TrussedApp for OathApp {
+    const CLIENT_ID: &'static [u8] = b"oath\0";
+
+    type NonPortable = ();
+    fn with_client(trussed: TrussedClient, _: ()) -> Self {
+        Self::new(trussed)
+    }
+}
+
+#[cfg(feature = "piv-authenticator")]
+impl TrussedApp for PivApp {
+    const CLIENT_ID: &'static [u8] = b"piv\0";
+
+    type NonPortable = ();
+    fn with_client(trussed: TrussedClient, _: ()) -> Self {
+        Self::new(trussed)
+    }
+}
+
+#[cfg(feature = "admin-app")]
+impl TrussedApp for AdminApp {
+    const CLIENT_ID: &'static [u8] = b"admin\0";
+
+    type NonPortable = Store;
+    fn with_client(trussed: TrussedClient, store: Store) -> Self {
+        let info = admin_app::DeviceInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            features: APP_FEATURES,
+            board: "pc",
+            prince: false,
+            nfc: admin_app::Nfc::Disabled,
+            secure_firmware_version: None,
+        };
+        Self::new(trussed, store, UUID, version(), info)
+    }
+}
+
+#[cfg(feature = "fido-authenticator")]
+impl TrussedApp for FidoApp {
+    const CLIENT_ID: &'static [u8] = b"fido\0";
+
+    type NonPortable = FidoNonPortable;
+    fn with_client(trussed: TrussedClient, FidoNonPortable { selection, nfc }: Self::NonPortable) -> Self {
+        let authnr = fido_authenticator::Authenticator::new(
+            trussed,
+            dispatch_fido::CancellableUserPresence {},
+        );
+
+        Self::new(authnr, dispatch_fido::CancellableUserPresence {}, selection, nfc)
+    }
+}
+
+#[cfg(feature = "fido-authenticator")]
+pub struct FidoNonPortable {
+    /// The client for the user presence check of authenticatorSelection.
+    pub selection: TrussedClient,
+    /// Whether the device has an NFC interface.
+    pub nfc: bool,
+}
+
+pub struct Apps {
+    /// Which apps the admin app's settings enable.
+    enabled: admin_app::config::Apps,
+    #[cfg(feature = "admin-app")]
+    pub admin: AdminApp,
+    #[cfg(feature = "fido-authenticator")]
+    pub fido: FidoApp,
+    #[cfg(feature = "oath-authenticator")]
+    pub oath: OathApp,
+    #[cfg(feature = "ndef-app")]
+    pub ndef: NdefApp,
+    #[cfg(feature = "piv-authenticator")]
+    pub piv: PivApp,
+}
+
+impl Apps {
+    /// The store is for apps that inspect it directly.
+    ///
+    /// The simulated device has a contactless interface, so it counts as having NFC.
+    #[cfg_attr(not(feature = "admin-app"), allow(unused_variables))]
+    pub fn new(trussed: &mut Trussed, store: Store) -> Self {
+        // without the admin app, there are no settings, and all apps answer
+        #[cfg(not(feature = "admin-app"))]
+        let enabled = admin_app::config::Apps::default();
+        #[cfg(feature = "admin-app")]
+        let enabled = admin_app::Config::load(store).apps;
+        #[cfg(feature = "admin-app")]
+        let admin = AdminApp::with(trussed, store);
+        #[cfg(feature = "fido-authenticator")]
+        // the selection client counts towards trussed/clients-N, too
+        let fido = {
+            let selection = trussed_client(trussed, b"fidoup\0");
+            FidoApp::with(trussed, FidoNonPortable { selection, nfc: true })
+        };
+        #[cfg(feature = "oath-authenticator")]
+        let oath = OathApp::with(trussed, ());
+        #[cfg(feature = "piv-authenticator")]
+        let piv = PivApp::with(trussed, ());
+        #[cfg(feature = "ndef-app")]
+        let ndef = NdefApp::new();
+
+        Self {
+            enabled,
+            #[cfg(feature = "admin-app")]
+            admin,
+            #[cfg(feature = "fido-authenticator")]
+            fido,
+            #[cfg(feature = "oath-authenticator")]
+            oath,
+            #[cfg(feature = "ndef-app")]
+            ndef,
+            #[cfg(feature = "piv-authenticator")]
+            piv,
+        }
+    }
+
+    pub fn apdu_dispatch<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut [&mut dyn
+                ApduApp<CommandSize, ResponseSize>
+            ]) -> T
+    {
+        let mut apps: Vec<&mut dyn ApduApp<CommandSize, ResponseSize>, heapless::consts::U8> = Vec::new();
+        #[cfg(feature = "ndef-app")]
+        if self.enabled.ndef {
+            apps.push(&mut self.ndef).ok();
+        }
+        #[cfg(feature = "piv-authenticator")]
+        if self.enabled.piv {
+            apps.push(&mut self.piv).ok();
+        }
+        #[cfg(feature = "oath-authenticator")]
+        if self.enabled.oath {
+            apps.push(&mut self.oath).ok();
+        }
+        #[cfg(feature = "fido-authenticator")]
+        if self.enabled.fido {
+            apps.push(&mut self.fido).ok();
+        }
+        #[cfg(feature = "admin-app")]
+        apps.push(&mut self.admin).ok();
+        f(&mut apps)
+    }
+
+    pub fn ctaphid_dispatch<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut [&mut dyn CtaphidApp ]) -> T
+    {
+        let mut apps: Vec<&mut dyn CtaphidApp, heapless::consts::U8> = Vec::new();
+        #[cfg(feature = "fido-authenticator")]
+        if self.enabled.fido {
+            apps.push(&mut self.fido).ok();
+        }
+        #[cfg(feature = "admin-app")]
+        apps.push(&mut self.admin).ok();
+        f(&mut apps)
+    }
+}
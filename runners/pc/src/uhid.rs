@@ -0,0 +1,88 @@
+//! CTAPHID reports via the Linux `/dev/uhid` interface.
+//!
+//! The kernel exposes the created device as a regular hidraw FIDO device,
+//! so browsers and tools like `fido2-token` pick up the simulator.
+
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+
+use uhid_virt::{Bus, CreateParams, InputEvent, OutputEvent, StreamError, UHID_EVENT_SIZE};
+use usbd_ctaphid::{
+    class::FIDO_HID_REPORT_DESCRIPTOR,
+    constants::PACKET_SIZE,
+};
+
+pub type Report = [u8; PACKET_SIZE];
+
+/// pid.codes test VID/PID, as used by the Solo 2 firmware
+const VENDOR_ID: u32 = 0x1209;
+const PRODUCT_ID: u32 = 0xbeee;
+
+pub struct Uhid {
+    file: File,
+}
+
+impl Uhid {
+    pub fn create(version: u32) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/uhid")?;
+
+        let params = CreateParams {
+            name: String::from("Solo 2 (PC)"),
+            phys: String::new(),
+            uniq: String::new(),
+            bus: Bus::USB,
+            vendor: VENDOR_ID,
+            product: PRODUCT_ID,
+            version,
+            country: 0,
+            rd_data: FIDO_HID_REPORT_DESCRIPTOR.to_vec(),
+        };
+        let event: [u8; UHID_EVENT_SIZE] = InputEvent::Create(params).into();
+        file.write_all(&event)?;
+
+        Ok(Self { file })
+    }
+
+    /// A second handle for a reader thread.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self { file: self.file.try_clone()? })
+    }
+
+    /// Blocks until the next event, returning the report if it is an output report.
+    pub fn read_report(&mut self) -> io::Result<Option<Report>> {
+        let mut event = [0u8; UHID_EVENT_SIZE];
+        self.file.read_exact(&mut event)?;
+
+        match OutputEvent::try_from(event) {
+            Ok(OutputEvent::Output { data }) => {
+                // hidraw writers prefix the (unused) report number
+                let data = match data.len() {
+                    len if len == PACKET_SIZE + 1 => &data[1..],
+                    _ => &data[..],
+                };
+                if data.len() != PACKET_SIZE {
+                    println!("uhid: ignoring report of length {}", data.len());
+                    return Ok(None);
+                }
+                let mut report = [0u8; PACKET_SIZE];
+                report.copy_from_slice(data);
+                Ok(Some(report))
+            }
+            Ok(_) => Ok(None),
+            Err(StreamError::Io(error)) => Err(error),
+            Err(StreamError::UnknownEventType(kind)) => {
+                println!("uhid: ignoring unknown event type {}", kind);
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn write_report(&mut self, report: &[u8]) -> io::Result<()> {
+        let event: [u8; UHID_EVENT_SIZE] = InputEvent::Input { data: report }.into();
+        self.file.write_all(&event)
+    }
+}
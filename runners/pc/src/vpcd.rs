@@ -0,0 +1,71 @@
+//! CCID via the virtual smart card reader of vsmartcard.
+//!
+//! `vpcd` listens on a TCP port (35963 by default) and the virtual card
+//! connects to it. Every message is prefixed by its length (two bytes,
+//! big-endian). One-byte messages are control messages, everything else
+//! is a command APDU to be answered by a response APDU.
+//!
+//! cf. http://frankmorgner.github.io/vsmartcard/virtualsmartcard/api.html
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+pub const DEFAULT_ADDRESS: &str = "localhost:35963";
+
+pub use usbd_ccid::constants::ATR;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    PowerOff,
+    PowerOn,
+    Reset,
+    GetAtr,
+    Apdu(Vec<u8>),
+}
+
+pub struct Vpcd {
+    stream: TcpStream,
+}
+
+impl Vpcd {
+    pub fn connect(address: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(Self { stream })
+    }
+
+    /// A second handle for a reader thread.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self { stream: self.stream.try_clone()? })
+    }
+
+    /// Blocks until the next message. Unknown control messages are skipped.
+    pub fn read_message(&mut self) -> io::Result<Message> {
+        loop {
+            let mut length = [0u8; 2];
+            self.stream.read_exact(&mut length)?;
+            let mut data = vec![0u8; u16::from_be_bytes(length) as usize];
+            self.stream.read_exact(&mut data)?;
+
+            let message = match data[..] {
+                [] => continue,
+                [0x00] => Message::PowerOff,
+                [0x01] => Message::PowerOn,
+                [0x02] => Message::Reset,
+                [0x04] => Message::GetAtr,
+                [control] => {
+                    println!("vpcd: ignoring unknown control message {:02x}", control);
+                    continue;
+                }
+                _ => Message::Apdu(data),
+            };
+            return Ok(message);
+        }
+    }
+
+    pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let length = data.len() as u16;
+        self.stream.write_all(&length.to_be_bytes())?;
+        self.stream.write_all(data)
+    }
+}
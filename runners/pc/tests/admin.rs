@@ -0,0 +1,235 @@
+mod common;
+
+use common::{map, select, split_status, text, Command, Device, VendorCommand};
+use littlefs2::path::PathBuf;
+use serde_cbor::Value;
+
+const ADMIN_AID: [u8; 9] = [0xA0, 0x00, 0x00, 0x08, 0x47, 0x00, 0x00, 0x00, 0x01];
+
+const RNG: VendorCommand = VendorCommand::H60;
+const VERSION: VendorCommand = VendorCommand::H61;
+const UUID: VendorCommand = VendorCommand::H62;
+const INFO: VendorCommand = VendorCommand::H63;
+const FILESYSTEM: VendorCommand = VendorCommand::H64;
+const FACTORY_RESET: VendorCommand = VendorCommand::H65;
+const CONFIG_LIST: VendorCommand = VendorCommand::H66;
+const CONFIG_GET: VendorCommand = VendorCommand::H67;
+const CONFIG_SET: VendorCommand = VendorCommand::H68;
+
+/// A file of the FIDO app, to show up in the directory sizes.
+fn store_fido_file(store: solo_pc::Store) {
+    trussed::store::store(
+        store,
+        trussed::types::Location::Internal,
+        &PathBuf::from(&b"/fido/dat/file"[..]),
+        &[0x42; 100],
+    ).unwrap();
+}
+
+#[test]
+fn vendor_commands() {
+    let mut device = Device::with_store(store_fido_file);
+
+    // over CTAPHID
+    let version = device.ctaphid(Command::Vendor(VERSION), &[]).unwrap();
+    assert_eq!(version, solo_pc::types::version().to_be_bytes());
+
+    let random = device.ctaphid(Command::Vendor(RNG), &[]).unwrap();
+    assert_eq!(random.len(), 57);
+    let more_random = device.ctaphid(Command::Vendor(RNG), &[]).unwrap();
+    assert_ne!(random, more_random);
+
+    assert!(!device.apps.admin.wink());
+    assert_eq!(device.ctaphid(Command::Wink, &[]).unwrap(), b"");
+    assert!(device.apps.admin.wink());
+    assert!(!device.apps.admin.wink());
+
+    // no app registers for LOCK
+    assert_eq!(device.ctaphid(Command::Lock, &[0x01]), Err(common::HidError::InvalidCommand));
+
+    // over CCID
+    assert_eq!(split_status(&device.apdu(&select(&ADMIN_AID))).1, [0x90, 0x00]);
+
+    let response = device.apdu(&[0x00, VERSION as u8, 0x00, 0x00, 0x00]);
+    let (version, status) = split_status(&response);
+    assert_eq!(status, [0x90, 0x00]);
+    assert_eq!(version, solo_pc::types::version().to_be_bytes());
+
+    let response = device.apdu(&[0x00, UUID as u8, 0x00, 0x00, 0x00]);
+    let (uuid, status) = split_status(&response);
+    assert_eq!(status, [0x90, 0x00]);
+    assert_eq!(uuid, solo_pc::types::UUID);
+
+    let response = device.apdu(&[0x00, RNG as u8, 0x00, 0x00, 0x00]);
+    let (random, status) = split_status(&response);
+    assert_eq!(status, [0x90, 0x00]);
+    assert_eq!(random.len(), 57);
+
+    // not a vendor command
+    let response = device.apdu(&[0x00, 0x20, 0x00, 0x00, 0x00]);
+    assert_eq!(split_status(&response).1, [0x6D, 0x00]);
+
+    transport_parity(&mut device);
+    device_info(&mut device);
+    filesystem_status(&mut device);
+    factory_reset_needs_contact(&mut device);
+    settings(&mut device);
+}
+
+fn settings(device: &mut Device) {
+    let list = |device: &mut Device| {
+        map_entries(serde_cbor::from_slice(&device.ctaphid(Command::Vendor(CONFIG_LIST), &[]).unwrap()).unwrap())
+    };
+    let defaults = list(device);
+    assert_eq!(defaults[&text("nfc")], Value::Bool(true));
+    assert_eq!(defaults[&text("led_brightness")], Value::Integer(255));
+    assert_eq!(defaults[&text("up_timeout")], Value::Integer(15_000));
+    let apps = map_entries(defaults[&text("apps")].clone());
+    assert!(apps.values().all(|enabled| *enabled == Value::Bool(true)));
+
+    let up_timeout = device.ctaphid(Command::Vendor(CONFIG_GET), b"up_timeout").unwrap();
+    assert_eq!(serde_cbor::from_slice::<Value>(&up_timeout).unwrap(), Value::Integer(15_000));
+    assert!(device.ctaphid(Command::Vendor(CONFIG_GET), b"colour").is_err());
+
+    // over CCID, only some of the settings
+    let set = |device: &mut Device, changes: Value| {
+        let changes = serde_cbor::to_vec(&changes).unwrap();
+        let mut apdu = vec![0x00, CONFIG_SET as u8, 0x00, 0x00, changes.len() as u8];
+        apdu.extend_from_slice(&changes);
+        split_status(&device.apdu(&apdu)).1
+    };
+    let changes = map(vec![
+        (text("led_brightness"), Value::Integer(100)),
+        (text("apps"), map(vec![(text("piv"), Value::Bool(false))])),
+    ]);
+    assert_eq!(set(device, changes), [0x90, 0x00]);
+
+    let changed = list(device);
+    assert_eq!(changed[&text("led_brightness")], Value::Integer(100));
+    assert_eq!(changed[&text("nfc")], Value::Bool(true));
+    let apps = map_entries(changed[&text("apps")].clone());
+    assert_eq!(apps[&text("piv")], Value::Bool(false));
+    assert_eq!(apps[&text("fido")], Value::Bool(true));
+
+    // the apps left out keep their setting
+    let changes = map(vec![(text("apps"), map(vec![(text("oath"), Value::Bool(false))]))]);
+    assert_eq!(set(device, changes), [0x90, 0x00]);
+    let changed = list(device);
+    let apps = map_entries(changed[&text("apps")].clone());
+    assert_eq!(apps[&text("oath")], Value::Bool(false));
+    assert_eq!(apps[&text("piv")], Value::Bool(false));
+    assert_eq!(apps[&text("fido")], Value::Bool(true));
+    assert_eq!(apps[&text("ndef")], Value::Bool(true));
+
+    // all or nothing
+    let changes = map(vec![
+        (text("nfc"), Value::Bool(false)),
+        (text("up_timeout"), Value::Integer(0)),
+    ]);
+    assert_eq!(set(device, changes), [0x6A, 0x80]);
+    assert_eq!(set(device, map(vec![(text("colour"), text("red"))])), [0x6A, 0x80]);
+    assert_eq!(list(device), changed);
+}
+
+/// Like the firmware update, a factory reset is refused over NFC, and leaves the data alone.
+fn factory_reset_needs_contact(device: &mut Device) {
+    assert_eq!(split_status(&device.apdu_contactless(&select(&ADMIN_AID))).1, [0x90, 0x00]);
+    let response = device.apdu_contactless(&[0x00, FACTORY_RESET as u8, 0x00, 0x00, 0x00]);
+    assert_eq!(response, [0x69, 0x85]);
+
+    let response = device.apdu(&[0x00, FILESYSTEM as u8, 0x00, 0x00, 0x00]);
+    let (status, sw) = split_status(&response);
+    assert_eq!(sw, [0x90, 0x00]);
+    let status = map_entries(serde_cbor::from_slice(status).unwrap());
+    let directories = map_entries(status[&text("directories")].clone());
+    assert!(directories.contains_key(&text("fido")));
+}
+
+fn map_entries(value: Value) -> std::collections::BTreeMap<Value, Value> {
+    match value {
+        Value::Map(map) => map,
+        other => panic!("not a map: {:?}", other),
+    }
+}
+
+fn filesystem_status(device: &mut Device) {
+    let response = device.apdu(&[0x00, FILESYSTEM as u8, 0x00, 0x00, 0x00]);
+    let (status, sw) = split_status(&response);
+    assert_eq!(sw, [0x90, 0x00]);
+    let status = map_entries(serde_cbor::from_slice(status).unwrap());
+
+    for filesystem in ["internal", "external", "volatile"].iter() {
+        let usage = map_entries(status[&text(filesystem)].clone());
+        match (&usage[&text("blocks")], &usage[&text("used")], &usage[&text("free")]) {
+            (Value::Integer(blocks), Value::Integer(used), Value::Integer(free)) => {
+                assert_eq!(used + free, *blocks, "{}", filesystem);
+                assert!(*used > 0, "{}", filesystem);
+            }
+            other => panic!("{}: not integers: {:?}", filesystem, other),
+        }
+    }
+    let internal = map_entries(status[&text("internal")].clone());
+    assert_eq!(internal[&text("blocks")], Value::Integer(solo_pc::littlefs_params::BLOCK_COUNT as i128));
+
+    let directories = map_entries(status[&text("directories")].clone());
+    match directories.get(&text("fido")) {
+        Some(Value::Integer(size)) => assert!(*size >= 100),
+        other => panic!("no size for the FIDO app's directory: {:?}", other),
+    }
+}
+
+fn device_info(device: &mut Device) {
+    let info: Value = serde_cbor::from_slice(&device.ctaphid(Command::Vendor(INFO), &[]).unwrap()).unwrap();
+    let info = match info {
+        Value::Map(info) => info,
+        other => panic!("not a map: {:?}", other),
+    };
+    let field = |key: &str| info.get(&text(key)).unwrap_or_else(|| panic!("missing {}", key)).clone();
+
+    assert_eq!(field("version"), text(env!("CARGO_PKG_VERSION")));
+    assert_eq!(field("board"), text("pc"));
+    assert_eq!(field("prince"), Value::Bool(false));
+    assert_eq!(field("nfc"), text("disabled"));
+    assert!(matches!(field("features"), Value::Array(features) if features.contains(&text("admin-app"))));
+    // no CFPA on the PC
+    assert!(info.get(&text("secure_fw_version")).is_none());
+
+    let fs = match field("fs") {
+        Value::Map(fs) => fs,
+        other => panic!("not a map: {:?}", other),
+    };
+    let blocks = fs[&text("blocks")].clone();
+    assert_eq!(blocks, Value::Integer(solo_pc::littlefs_params::BLOCK_COUNT as i128));
+    match fs[&text("free")] {
+        Value::Integer(free) => assert!(free > 0 && free < solo_pc::littlefs_params::BLOCK_COUNT as i128),
+        ref other => panic!("free blocks not an integer: {:?}", other),
+    }
+}
+
+/// Every admin command that does not reboot answers the same over CTAPHID and APDU.
+fn transport_parity(device: &mut Device) {
+    for &command in admin_app::Command::ALL.iter() {
+        let code = command.code();
+        let hid = match command {
+            admin_app::Command::Update
+                | admin_app::Command::Reboot
+                | admin_app::Command::FactoryReset
+                | admin_app::Command::ConfigGet
+                | admin_app::Command::ConfigSet => continue,
+            _ => device.ctaphid(Command::Vendor(code), &[]).unwrap(),
+        };
+        let response = device.apdu(&[0x00, code as u8, 0x00, 0x00, 0x00]);
+        let (apdu, status) = split_status(&response);
+        assert_eq!(status, [0x90, 0x00], "{:?}", command);
+
+        if command == admin_app::Command::Rng {
+            assert_eq!(hid.len(), apdu.len());
+        } else {
+            assert_eq!(hid, apdu, "{:?}", command);
+        }
+    }
+
+    // UUID used to be taken for a wink over CTAPHID
+    assert_eq!(device.ctaphid(Command::Vendor(UUID), &[]).unwrap(), solo_pc::types::UUID);
+    assert!(!device.apps.admin.wink());
+}
@@ -0,0 +1,43 @@
+//! The host cancelling a request (CTAPHID_CANCEL) aborts a pending user presence check.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{map, text, Device};
+use serde_cbor::Value;
+use solo_pc::{presence::Presence, UserInterface};
+
+const MAKE_CREDENTIAL: u8 = 0x01;
+
+// CTAP2_ERR_KEEPALIVE_CANCEL
+const KEEPALIVE_CANCEL: u8 = 0x2D;
+
+#[test]
+fn cancel_user_presence() {
+    // nobody presses the button, the host gives up after a while
+    let mut user_interface = UserInterface::new(Presence::Script("deny".parse().unwrap()));
+    let mut waiting_since = None;
+    user_interface.set_service(move || {
+        let since = *waiting_since.get_or_insert_with(Instant::now);
+        if since.elapsed() > Duration::from_millis(300) {
+            ctaphid_dispatch::cancel::cancel();
+        }
+    });
+    let mut device = Device::with_user_interface(user_interface);
+
+    let make_credential = map(vec![
+        (1, Value::Bytes(vec![0x11; 32])),
+        (2, map(vec![(text("id"), text("example.com")), (text("name"), text("Example"))])),
+        (3, map(vec![(text("id"), Value::Bytes(b"alice".to_vec())), (text("name"), text("alice"))])),
+        (4, Value::Array(vec![map(vec![(text("alg"), Value::from(-7)), (text("type"), text("public-key"))])])),
+    ]);
+    let start = Instant::now();
+    let (status, response) = device.ctap2(MAKE_CREDENTIAL, Some(make_credential));
+    assert_eq!(status, KEEPALIVE_CANCEL);
+    assert_eq!(response, None);
+    // long before the user presence timeout
+    assert!(start.elapsed() < Duration::from_secs(5));
+    // and the cancellation does not linger, for the other transports
+    assert!(!ctaphid_dispatch::cancel::is_cancelled());
+}
@@ -0,0 +1,158 @@
+//! Wires up the full app stack in-process, on RAM-backed storage.
+//!
+//! Interchanges and the store can only be claimed once per process,
+//! so every test binary gets exactly one `Device`.
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+
+use interchange::{Interchange, Requester};
+use serde_cbor::Value;
+use trussed::service::SeedableRng;
+
+use solo_pc::{
+    types::{self, Apps},
+    Board, FileFlash, Store, UserInterface,
+};
+
+pub use ctaphid_dispatch::command::{Command, VendorCommand};
+pub use ctaphid_dispatch::types::Error as HidError;
+
+pub struct Device {
+    pub apps: Apps,
+    apdu_dispatch: types::ApduDispatch,
+    ctaphid_dispatch: types::CtaphidDispatch,
+    contact: Requester<apdu_dispatch::interchanges::Contact>,
+    contactless: Requester<apdu_dispatch::interchanges::Contactless>,
+    ctaphid: Requester<ctaphid_dispatch::types::HidInterchange>,
+}
+
+impl Device {
+    pub fn new() -> Self {
+        Self::with_store(|_| {})
+    }
+
+    /// Allows populating the freshly formatted store before any app runs.
+    pub fn with_store(prepare: impl FnOnce(Store)) -> Self {
+        Self::with(prepare, UserInterface::default())
+    }
+
+    /// Answers user presence checks with the given user interface.
+    pub fn with_user_interface(user_interface: UserInterface) -> Self {
+        Self::with(|_| {}, user_interface)
+    }
+
+    fn with(prepare: impl FnOnce(Store), user_interface: UserInterface) -> Self {
+        let store = solo_pc::init_store(FileFlash::in_memory());
+        prepare(store);
+
+        let rng = chacha20::ChaCha8Rng::from_seed([0u8; 32]);
+        let board = Board::new(rng, store, user_interface);
+        let trussed = types::init_trussed(board);
+        let apps = Apps::new(trussed, store);
+
+        let (contact, contact_responder) = apdu_dispatch::interchanges::Contact::claim().unwrap();
+        let (contactless, contactless_responder) = apdu_dispatch::interchanges::Contactless::claim().unwrap();
+        let (ctaphid, ctaphid_responder) = ctaphid_dispatch::types::HidInterchange::claim().unwrap();
+
+        Self {
+            apps,
+            apdu_dispatch: types::ApduDispatch::new(contact_responder, contactless_responder),
+            ctaphid_dispatch: types::CtaphidDispatch::new(ctaphid_responder),
+            contact,
+            contactless,
+            ctaphid,
+        }
+    }
+
+    /// Sends a CTAPHID message, as the USB class would after reassembly.
+    pub fn ctaphid(&mut self, command: Command, request: &[u8]) -> Result<Vec<u8>, HidError> {
+        let message = ctaphid_dispatch::types::Message::try_from_slice(request).unwrap();
+        self.ctaphid.request(&(command, message)).expect("HID interchange busy");
+
+        let Self { apps, ctaphid_dispatch, .. } = self;
+        apps.ctaphid_dispatch(|apps| ctaphid_dispatch.poll(apps));
+
+        self.ctaphid
+            .take_response()
+            .expect("no CTAPHID response")
+            .map(|message| message.to_vec())
+    }
+
+    /// Sends a CTAP2 command via CTAPHID CBOR, returning the status byte
+    /// and the decoded response map (if any).
+    pub fn ctap2(&mut self, operation: u8, parameters: Option<Value>) -> (u8, Option<Value>) {
+        let mut request = vec![operation];
+        if let Some(parameters) = parameters {
+            request.extend_from_slice(&serde_cbor::to_vec(&parameters).unwrap());
+        }
+        let response = self.ctaphid(Command::Cbor, &request).unwrap();
+        let value = match response.len() {
+            0 => panic!("empty CTAP2 response"),
+            1 => None,
+            _ => Some(serde_cbor::from_slice(&response[1..]).expect("invalid CBOR in response")),
+        };
+        (response[0], value)
+    }
+
+    /// Sends a command APDU via the contact (CCID) interface, returning data and status word.
+    pub fn apdu(&mut self, apdu: &[u8]) -> Vec<u8> {
+        let request = apdu_dispatch::interchanges::Data::try_from_slice(apdu).unwrap();
+        self.contact.request(&request).expect("contact interchange busy");
+
+        let Self { apps, apdu_dispatch, .. } = self;
+        apps.apdu_dispatch(|apps| apdu_dispatch.poll(apps));
+
+        self.contact.take_response().expect("no contact response").to_vec()
+    }
+
+    /// Sends a command APDU via the contactless (NFC) interface, returning data and status word.
+    pub fn apdu_contactless(&mut self, apdu: &[u8]) -> Vec<u8> {
+        let request = apdu_dispatch::interchanges::Data::try_from_slice(apdu).unwrap();
+        self.contactless.request(&request).expect("contactless interchange busy");
+
+        let Self { apps, apdu_dispatch, .. } = self;
+        apps.apdu_dispatch(|apps| apdu_dispatch.poll(apps));
+
+        self.contactless.take_response().expect("no contactless response").to_vec()
+    }
+}
+
+/// SELECT by AID, with Le.
+pub fn select(aid: &[u8]) -> Vec<u8> {
+    let mut apdu = vec![0x00, 0xA4, 0x04, 0x00, aid.len() as u8];
+    apdu.extend_from_slice(aid);
+    apdu.push(0x00);
+    apdu
+}
+
+/// Splits a response APDU into data and status word.
+pub fn split_status(response: &[u8]) -> (&[u8], [u8; 2]) {
+    assert!(response.len() >= 2, "response without status word");
+    let (data, status) = response.split_at(response.len() - 2);
+    (data, [status[0], status[1]])
+}
+
+/// Builds a CBOR map from its entries.
+pub fn map<K: Into<Value>, V: Into<Value>>(entries: Vec<(K, V)>) -> Value {
+    Value::Map(entries.into_iter().map(|(k, v)| (k.into(), v.into())).collect::<BTreeMap<_, _>>())
+}
+
+pub fn text(text: &str) -> Value {
+    Value::Text(text.to_string())
+}
+
+/// Looks up an integer key in a CBOR map.
+pub fn get(value: &Value, key: i128) -> &Value {
+    match value {
+        Value::Map(map) => map.get(&Value::Integer(key)).unwrap_or_else(|| panic!("missing key {}", key)),
+        _ => panic!("not a map: {:?}", value),
+    }
+}
+
+pub fn bytes(value: &Value) -> &[u8] {
+    match value {
+        Value::Bytes(bytes) => bytes,
+        _ => panic!("not a byte string: {:?}", value),
+    }
+}
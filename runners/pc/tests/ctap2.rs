@@ -0,0 +1,116 @@
+mod common;
+
+use common::{bytes, get, map, text, Device};
+use serde_cbor::Value;
+
+const MAKE_CREDENTIAL: u8 = 0x01;
+const GET_ASSERTION: u8 = 0x02;
+const GET_INFO: u8 = 0x04;
+const SELECTION: u8 = 0x0B;
+const LARGE_BLOBS: u8 = 0x0C;
+const CONFIG: u8 = 0x0D;
+const PREVIEW_BIO_ENROLLMENT: u8 = 0x40;
+
+// CTAP1_ERR_INVALID_COMMAND
+const INVALID_COMMAND: u8 = 0x01;
+// CTAP2_ERR_INVALID_CBOR
+const INVALID_CBOR: u8 = 0x12;
+
+const ES256: i64 = -7;
+
+const FLAG_UP: u8 = 0x01;
+const FLAG_AT: u8 = 0x40;
+
+const RP_ID: &str = "example.com";
+
+#[test]
+fn make_credential_then_get_assertion() {
+    let mut device = Device::new();
+
+    let (status, info) = device.ctap2(GET_INFO, None);
+    assert_eq!(status, 0);
+    let versions = get(info.as_ref().unwrap(), 1);
+    assert!(matches!(versions, Value::Array(versions) if versions.contains(&text("FIDO_2_0"))));
+
+    let make_credential = map(vec![
+        (1, Value::Bytes(vec![0x11; 32])),
+        (2, map(vec![(text("id"), text(RP_ID)), (text("name"), text("Example"))])),
+        (3, map(vec![(text("id"), Value::Bytes(b"alice".to_vec())), (text("name"), text("alice"))])),
+        (4, Value::Array(vec![map(vec![(text("alg"), Value::from(ES256)), (text("type"), text("public-key"))])])),
+    ]);
+    let (status, attestation) = device.ctap2(MAKE_CREDENTIAL, Some(make_credential));
+    assert_eq!(status, 0);
+    let attestation = attestation.unwrap();
+
+    // rpIdHash (32) | flags (1) | signCount (4) | aaguid (16) | credentialIdLength (2) | credentialId | ...
+    let auth_data = bytes(get(&attestation, 2));
+    let rp_id_hash = &auth_data[..32];
+    let flags = auth_data[32];
+    assert_eq!(flags & (FLAG_UP | FLAG_AT), FLAG_UP | FLAG_AT);
+    let credential_id_length = u16::from_be_bytes([auth_data[53], auth_data[54]]) as usize;
+    let credential_id = auth_data[55..][..credential_id_length].to_vec();
+    assert!(matches!(get(&attestation, 1), Value::Text(_)));
+
+    let get_assertion = map(vec![
+        (1, text(RP_ID)),
+        (2, Value::Bytes(vec![0x22; 32])),
+        (3, Value::Array(vec![map(vec![
+            (text("id"), Value::Bytes(credential_id.clone())),
+            (text("type"), text("public-key")),
+        ])])),
+    ]);
+    let (status, assertion) = device.ctap2(GET_ASSERTION, Some(get_assertion));
+    assert_eq!(status, 0);
+    let assertion = assertion.unwrap();
+
+    let credential = get(&assertion, 1);
+    match credential {
+        Value::Map(credential) => assert_eq!(
+            credential.get(&text("id")),
+            Some(&Value::Bytes(credential_id)),
+        ),
+        _ => panic!("credential is not a map"),
+    }
+    let auth_data = bytes(get(&assertion, 2));
+    assert_eq!(&auth_data[..32], rp_id_hash);
+    assert_eq!(auth_data[32] & FLAG_UP, FLAG_UP);
+    assert!(!bytes(get(&assertion, 3)).is_empty());
+
+    // unknown credentials are rejected
+    let get_assertion = map(vec![
+        (1, text(RP_ID)),
+        (2, Value::Bytes(vec![0x22; 32])),
+        (3, Value::Array(vec![map(vec![
+            (text("id"), Value::Bytes(vec![0x33; 64])),
+            (text("type"), text("public-key")),
+        ])])),
+    ]);
+    let (status, _) = device.ctap2(GET_ASSERTION, Some(get_assertion));
+    // CTAP2_ERR_NO_CREDENTIALS
+    assert_eq!(status, 0x2E);
+
+    ctap21_commands(&mut device);
+}
+
+/// The CTAP 2.1 commands a platform may probe: selection works, the commands for
+/// features the authenticator does not have are invalid.
+fn ctap21_commands(device: &mut Device) {
+    // the user presence check is approved
+    assert_eq!(device.ctap2(SELECTION, None), (0, None));
+    // and leaves no credential behind
+    let get_assertion = map(vec![(1, text(".dummy")), (2, Value::Bytes(vec![0; 32]))]);
+    // CTAP2_ERR_NO_CREDENTIALS
+    assert_eq!(device.ctap2(GET_ASSERTION, Some(get_assertion)).0, 0x2E);
+
+    // get, offset 0
+    let large_blobs = map(vec![(1, Value::from(1024)), (3, Value::from(0))]);
+    assert_eq!(device.ctap2(LARGE_BLOBS, Some(large_blobs)), (INVALID_COMMAND, None));
+    // enableEnterpriseAttestation
+    let config = map(vec![(1, Value::from(1))]);
+    assert_eq!(device.ctap2(CONFIG, Some(config)), (INVALID_COMMAND, None));
+    // getModality
+    let bio_enrollment = map(vec![(6, Value::Bool(true))]);
+    assert_eq!(device.ctap2(PREVIEW_BIO_ENROLLMENT, Some(bio_enrollment)), (INVALID_COMMAND, None));
+
+    assert_eq!(device.ctap2(CONFIG, Some(Value::from(1))), (INVALID_CBOR, None));
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::{select, split_status, Device};
+
+const NDEF_AID: [u8; 7] = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+
+fn select_file(id: [u8; 2]) -> Vec<u8> {
+    vec![0x00, 0xA4, 0x00, 0x0C, 0x02, id[0], id[1]]
+}
+
+fn read_binary(offset: u16, length: u8) -> Vec<u8> {
+    let offset = offset.to_be_bytes();
+    vec![0x00, 0xB0, offset[0], offset[1], length]
+}
+
+#[test]
+fn read_capability_container_and_ndef() {
+    let mut device = Device::new();
+
+    assert_eq!(split_status(&device.apdu_contactless(&select(&NDEF_AID))).1, [0x90, 0x00]);
+
+    // capability container
+    assert_eq!(device.apdu_contactless(&select_file([0xE1, 0x03])), [0x90, 0x00]);
+    let response = device.apdu_contactless(&read_binary(0, 15));
+    let (cc, status) = split_status(&response);
+    assert_eq!(status, [0x90, 0x00]);
+    assert_eq!(cc, ndef_app::App::CAPABILITY_CONTAINER);
+
+    // NDEF file: length prefix, then a URI record
+    assert_eq!(device.apdu_contactless(&select_file([0xE1, 0x04])), [0x90, 0x00]);
+    let response = device.apdu_contactless(&read_binary(0, 2));
+    let (length, status) = split_status(&response);
+    assert_eq!(status, [0x90, 0x00]);
+    let length = u16::from_be_bytes([length[0], length[1]]);
+
+    let response = device.apdu_contactless(&read_binary(2, length as u8));
+    let (record, status) = split_status(&response);
+    assert_eq!(status, [0x90, 0x00]);
+    assert_eq!(record, &ndef_app::App::NDEF[2..]);
+    // well-known type "U", prefix "https://"
+    assert_eq!(&record[3..5], &[0x55, 0x04]);
+    assert_eq!(&record[5..], b"solokeys.com/");
+
+    // unknown files
+    assert_eq!(device.apdu_contactless(&select_file([0xE1, 0x05])), [0x6A, 0x82]);
+}
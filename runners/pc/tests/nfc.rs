@@ -0,0 +1,76 @@
+//! CTAP over NFC: the tap is the user's presence, and GetInfo lists NFC over both transports.
+
+mod common;
+
+use common::{get, map, select, split_status, text, Device};
+use serde_cbor::Value;
+use solo_pc::{presence::Presence, UserInterface};
+
+const FIDO_AID: [u8; 8] = [0xA0, 0x00, 0x00, 0x06, 0x47, 0x2F, 0x00, 0x01];
+
+const NFCCTAP_MSG: u8 = 0x10;
+const NFCCTAP_GETRESPONSE: u8 = 0x11;
+
+const MAKE_CREDENTIAL: u8 = 0x01;
+const GET_INFO: u8 = 0x04;
+
+/// Sends a CTAP2 command via NFCCTAP_MSG, returning the status byte and the response map (if any).
+fn ctap2_nfc(device: &mut Device, operation: u8, parameters: Option<Value>) -> (u8, Option<Value>) {
+    let mut request = vec![operation];
+    if let Some(parameters) = parameters {
+        request.extend_from_slice(&serde_cbor::to_vec(&parameters).unwrap());
+    }
+    let mut apdu = vec![0x80, NFCCTAP_MSG, 0x00, 0x00, 0x00];
+    apdu.extend_from_slice(&(request.len() as u16).to_be_bytes());
+    apdu.extend_from_slice(&request);
+    apdu.extend_from_slice(&[0x00, 0x00]);
+
+    let response = device.apdu_contactless(&apdu);
+    let (response, status) = split_status(&response);
+    assert_eq!(status, [0x90, 0x00]);
+    let value = match response.len() {
+        0 => panic!("empty CTAP2 response"),
+        1 => None,
+        _ => Some(serde_cbor::from_slice(&response[1..]).expect("invalid CBOR in response")),
+    };
+    (response[0], value)
+}
+
+fn transports(info: &Value) -> Vec<Value> {
+    match get(info, 9) {
+        Value::Array(transports) => transports.clone(),
+        other => panic!("transports are not an array: {:?}", other),
+    }
+}
+
+#[test]
+fn presence_and_info_per_transport() {
+    // nobody ever presses the button
+    let user_interface = UserInterface::new(Presence::Script("deny".parse().unwrap()));
+    let mut device = Device::with_user_interface(user_interface);
+
+    // the simulated device has NFC, whichever transport asks
+    let (status, info) = device.ctap2(GET_INFO, None);
+    assert_eq!(status, 0);
+    assert_eq!(transports(info.as_ref().unwrap()), [text("nfc"), text("usb")]);
+
+    assert_eq!(split_status(&device.apdu_contactless(&select(&FIDO_AID))).1, [0x90, 0x00]);
+    let (status, info) = ctap2_nfc(&mut device, GET_INFO, None);
+    assert_eq!(status, 0);
+    assert_eq!(transports(info.as_ref().unwrap()), [text("nfc"), text("usb")]);
+
+    // the tap counts as user presence
+    let make_credential = map(vec![
+        (1, Value::Bytes(vec![0x11; 32])),
+        (2, map(vec![(text("id"), text("example.com")), (text("name"), text("Example"))])),
+        (3, map(vec![(text("id"), Value::Bytes(b"alice".to_vec())), (text("name"), text("alice"))])),
+        (4, Value::Array(vec![map(vec![(text("alg"), Value::from(-7)), (text("type"), text("public-key"))])])),
+    ]);
+    let (status, attestation) = ctap2_nfc(&mut device, MAKE_CREDENTIAL, Some(make_credential));
+    assert_eq!(status, 0);
+    assert!(attestation.is_some());
+
+    // nothing is ever left pending
+    let response = device.apdu_contactless(&[0x80, NFCCTAP_GETRESPONSE, 0x00, 0x00, 0x00]);
+    assert_eq!(response, [0x69, 0x85]);
+}
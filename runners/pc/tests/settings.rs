@@ -0,0 +1,40 @@
+//! The apps the stored settings disable are left out at boot.
+
+mod common;
+
+use common::{map, select, split_status, text, Command, Device, VendorCommand};
+use serde_cbor::Value;
+
+const ADMIN_AID: [u8; 9] = [0xA0, 0x00, 0x00, 0x08, 0x47, 0x00, 0x00, 0x00, 0x01];
+const FIDO_AID: [u8; 8] = [0xA0, 0x00, 0x00, 0x06, 0x47, 0x2F, 0x00, 0x01];
+const NDEF_AID: [u8; 7] = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+
+const GET_INFO: u8 = 0x04;
+const CONFIG_GET: VendorCommand = VendorCommand::H67;
+
+fn disable_fido_and_ndef(store: solo_pc::Store) {
+    let mut config = admin_app::Config::default();
+    config.apps.fido = false;
+    config.apps.ndef = false;
+    config.save(store).unwrap();
+}
+
+#[test]
+fn disabled_apps() {
+    let mut device = Device::with_store(disable_fido_and_ndef);
+
+    assert_ne!(split_status(&device.apdu_contactless(&select(&NDEF_AID))).1, [0x90, 0x00]);
+    assert_ne!(split_status(&device.apdu(&select(&FIDO_AID))).1, [0x90, 0x00]);
+    assert_eq!(device.ctaphid(Command::Cbor, &[GET_INFO]), Err(common::HidError::InvalidCommand));
+
+    // the admin app can't be disabled, to enable them again
+    assert_eq!(split_status(&device.apdu(&select(&ADMIN_AID))).1, [0x90, 0x00]);
+    let apps = device.ctaphid(Command::Vendor(CONFIG_GET), b"apps").unwrap();
+    let apps: Value = serde_cbor::from_slice(&apps).unwrap();
+    assert_eq!(apps, map(vec![
+        (text("fido"), false),
+        (text("ndef"), false),
+        (text("oath"), true),
+        (text("piv"), true),
+    ]));
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use common::{select, split_status, Command, Device};
+
+use littlefs2::path::PathBuf;
+use trussed::key::{Flags, Key, Kind as KeyKind};
+
+const FIDO_AID: [u8; 8] = [0xA0, 0x00, 0x00, 0x06, 0x47, 0x2F, 0x00, 0x01];
+
+const REGISTER: u8 = 0x01;
+const AUTHENTICATE: u8 = 0x02;
+const VERSION: u8 = 0x03;
+
+const ENFORCE_USER_PRESENCE_AND_SIGN: u8 = 0x03;
+const CHECK_ONLY: u8 = 0x07;
+
+const CHALLENGE: [u8; 32] = [0x11; 32];
+const APPLICATION: [u8; 32] = [0x22; 32];
+
+/// Injects an attestation key and certificate, as the provisioner app would.
+fn provision_attestation(store: solo_pc::Store) {
+    let key = Key {
+        flags: Flags::LOCAL | Flags::SENSITIVE,
+        kind: KeyKind::P256,
+        material: heapless_bytes::Bytes::try_from_slice(&[0x42; 32]).unwrap(),
+    };
+    trussed::store::store(
+        store,
+        trussed::types::Location::Internal,
+        &PathBuf::from(&b"/attn/sec/01"[..]),
+        &key.serialize(),
+    ).unwrap();
+
+    // only the DER framing matters here: SEQUENCE of 124 bytes
+    let mut certificate = vec![0x30, 0x81, 0x7c];
+    certificate.resize(3 + 0x7c, 0);
+    trussed::store::store(
+        store,
+        trussed::types::Location::Internal,
+        &PathBuf::from(&b"/attn/x5c/01"[..]),
+        &certificate,
+    ).unwrap();
+}
+
+/// Extended length APDU, as sent over CTAPHID MSG.
+fn apdu(ins: u8, p1: u8, data: &[u8]) -> Vec<u8> {
+    let mut apdu = vec![0x00, ins, p1, 0x00, 0x00];
+    apdu.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    apdu.extend_from_slice(data);
+    apdu.extend_from_slice(&[0x00, 0x00]);
+    apdu
+}
+
+fn register_request() -> Vec<u8> {
+    [&CHALLENGE[..], &APPLICATION[..]].concat()
+}
+
+fn authenticate_request(application: &[u8], key_handle: &[u8]) -> Vec<u8> {
+    [&CHALLENGE[..], application, &[key_handle.len() as u8], key_handle].concat()
+}
+
+/// Checks a registration response and returns the key handle.
+fn key_handle(registration: &[u8]) -> Vec<u8> {
+    // reserved byte, then uncompressed P256 public key
+    assert_eq!(registration[0], 0x05);
+    assert_eq!(registration[1], 0x04);
+    let length = registration[66] as usize;
+    registration[67..][..length].to_vec()
+}
+
+#[test]
+fn register_then_authenticate() {
+    let mut device = Device::with_store(provision_attestation);
+
+    // over CTAPHID MSG
+    let response = device.ctaphid(Command::Msg, &apdu(VERSION, 0, &[])).unwrap();
+    assert_eq!(response, b"U2F_V2\x90\x00");
+
+    // instructions U2F does not know get a status word, not a CTAP2 status code
+    let response = device.ctaphid(Command::Msg, &apdu(0x04, 0, &[])).unwrap();
+    let (data, status) = split_status(&response);
+    assert_eq!(data, b"");
+    assert_ne!(status, [0x90, 0x00]);
+
+    let response = device.ctaphid(Command::Msg, &apdu(REGISTER, 0, &register_request())).unwrap();
+    let (registration, status) = split_status(&response);
+    assert_eq!(status, [0x90, 0x00]);
+    let key_handle = key_handle(registration);
+
+    let request = authenticate_request(&APPLICATION, &key_handle);
+    let response = device.ctaphid(Command::Msg, &apdu(AUTHENTICATE, CHECK_ONLY, &request)).unwrap();
+    assert_eq!(split_status(&response).1, [0x69, 0x85]);
+
+    let response = device.ctaphid(Command::Msg, &apdu(AUTHENTICATE, ENFORCE_USER_PRESENCE_AND_SIGN, &request)).unwrap();
+    let (authentication, status) = split_status(&response);
+    assert_eq!(status, [0x90, 0x00]);
+    // user presence, counter, DER signature
+    assert_eq!(authentication[0] & 0x01, 0x01);
+    assert_eq!(authentication[5], 0x30);
+
+    // key handles are bound to their application
+    let request = authenticate_request(&[0x33; 32], &key_handle);
+    let response = device.ctaphid(Command::Msg, &apdu(AUTHENTICATE, ENFORCE_USER_PRESENCE_AND_SIGN, &request)).unwrap();
+    assert_eq!(split_status(&response).1, [0x6A, 0x80]);
+
+    // over NFC
+    let response = device.apdu_contactless(&select(&FIDO_AID));
+    assert_eq!(response, b"U2F_V2\x90\x00");
+
+    let response = device.apdu_contactless(&apdu(VERSION, 0, &[]));
+    assert_eq!(response, b"U2F_V2\x90\x00");
+
+    let response = device.apdu_contactless(&apdu(REGISTER, 0, &register_request()));
+    let (registration, status) = split_status(&response);
+    assert_eq!(status, [0x90, 0x00]);
+    let key_handle = key_handle(registration);
+
+    let request = authenticate_request(&APPLICATION, &key_handle);
+    let response = device.apdu_contactless(&apdu(AUTHENTICATE, ENFORCE_USER_PRESENCE_AND_SIGN, &request));
+    assert_eq!(split_status(&response).1, [0x90, 0x00]);
+}